@@ -0,0 +1,286 @@
+// The `wrangler` command, for running wrangler over a tree of shaders without writing Rust:
+//
+//     wrangler build --src shaders --out compiled --kinds vert,frag
+//
+// `build` exits with 1 if any shader fails to compile, after printing every failure.  `watch`
+// takes the same options and builds again whenever a shader changes, until it's interrupted.
+// `compare` shows what changed between the manifests of two builds.  On Unix, `daemon` does
+// what `watch` does while taking commands over a socket, which `send` passes along, as in
+// `wrangler send --socket build.sock pause`.
+
+use clap::{Parser, Subcommand};
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use wrangler::{
+    CompilationReport, Instructions, ManifestDiff, ShaderKind, SpirvVersion, TargetEnv,
+};
+
+const KINDS: &[(&str, ShaderKind)] = &[
+    ("vert", ShaderKind::Vertex),
+    ("frag", ShaderKind::Fragment),
+    ("comp", ShaderKind::Compute),
+    ("geom", ShaderKind::Geometry),
+    ("tesc", ShaderKind::TessControl),
+    ("tese", ShaderKind::TessEvaluation),
+    ("rgen", ShaderKind::RayGeneration),
+    ("rmiss", ShaderKind::Miss),
+    ("rchit", ShaderKind::ClosestHit),
+    ("rahit", ShaderKind::AnyHit),
+    ("rint", ShaderKind::Intersection),
+    ("rcall", ShaderKind::Callable),
+    ("task", ShaderKind::Task),
+    ("mesh", ShaderKind::Mesh),
+];
+
+fn parse_kind(ext: &str) -> Result<ShaderKind, String> {
+    KINDS
+        .iter()
+        .find(|(name, _)| *name == ext)
+        .map(|(_, kind)| *kind)
+        .ok_or_else(|| format!("`{}` isn't a shader stage extension, like `vert`", ext))
+}
+
+fn parse_target_env(name: &str) -> Result<TargetEnv, String> {
+    match name {
+        "vulkan1.0" => Ok(TargetEnv::Vulkan1_0),
+        "vulkan1.1" => Ok(TargetEnv::Vulkan1_1),
+        "vulkan1.2" => Ok(TargetEnv::Vulkan1_2),
+        "vulkan1.3" => Ok(TargetEnv::Vulkan1_3),
+        "opengl4.5" => Ok(TargetEnv::OpenGl4_5),
+        _ => Err("expected vulkan1.0 to vulkan1.3, or opengl4.5".to_owned()),
+    }
+}
+
+fn parse_spirv_version(version: &str) -> Result<SpirvVersion, String> {
+    match version {
+        "1.0" => Ok(SpirvVersion::V1_0),
+        "1.1" => Ok(SpirvVersion::V1_1),
+        "1.2" => Ok(SpirvVersion::V1_2),
+        "1.3" => Ok(SpirvVersion::V1_3),
+        "1.4" => Ok(SpirvVersion::V1_4),
+        "1.5" => Ok(SpirvVersion::V1_5),
+        "1.6" => Ok(SpirvVersion::V1_6),
+        _ => Err("expected 1.0 to 1.6".to_owned()),
+    }
+}
+
+/// `NAME` or `NAME=VALUE`, as for `-D`.
+fn parse_define(define: &str) -> Result<(String, Option<String>), String> {
+    match define.split_once('=') {
+        Some((name, value)) => Ok((name.to_owned(), Some(value.to_owned()))),
+        None => Ok((define.to_owned(), None)),
+    }
+}
+
+/// `NAME=DIR`, as for `--include-package`.
+fn parse_package(package: &str) -> Result<(String, PathBuf), String> {
+    match package.split_once('=') {
+        Some((name, dir)) if !name.is_empty() => Ok((name.to_owned(), PathBuf::from(dir))),
+        _ => Err("expected NAME=DIR".to_owned()),
+    }
+}
+
+#[derive(Parser)]
+#[command(
+    name = "wrangler",
+    version,
+    about = "Compiles a tree of shaders to SPIR-V"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compile every shader under --src that changed since the last build.
+    Build(Build),
+    /// Build, then build again whenever a shader under --src or an include directory changes.
+    Watch(Build),
+    /// Show what changed between the manifests of two builds.
+    Compare {
+        old: PathBuf,
+        new: PathBuf,
+        /// Print the differences as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Watch, while taking commands from other tools over a socket.
+    #[cfg(unix)]
+    Daemon {
+        /// Where to listen.
+        #[arg(long)]
+        socket: PathBuf,
+        #[command(flatten)]
+        build: Build,
+    },
+    /// Send a command to a daemon and print its reply: status, build, pause, resume,
+    /// invalidate [SHADER...], or stop.
+    #[cfg(unix)]
+    Send {
+        /// Where the daemon is listening.
+        #[arg(long)]
+        socket: PathBuf,
+        #[arg(required = true)]
+        command: Vec<String>,
+    },
+}
+
+#[derive(clap::Args)]
+struct Build {
+    /// Where to search for shaders.
+    #[arg(long)]
+    src: PathBuf,
+    /// Where to write compiled modules, laid out like --src.
+    #[arg(long)]
+    out: PathBuf,
+    /// Stages to compile, by extension.  All of them if not given.
+    #[arg(long, value_delimiter = ',', value_parser = parse_kind)]
+    kinds: Vec<ShaderKind>,
+    /// Where to keep the record of what has been compiled.  Defaults to `.wrangler_record` in
+    /// --out.
+    #[arg(long)]
+    record: Option<PathBuf>,
+    /// Defines a macro for every compile, as `NAME` or `NAME=VALUE`.
+    #[arg(short = 'D', long = "define", value_parser = parse_define)]
+    defines: Vec<(String, Option<String>)>,
+    /// A directory to search for includes.  May be given more than once.
+    #[arg(short = 'I', long = "include-dir")]
+    include_dirs: Vec<PathBuf>,
+    /// A shader library for `#include <NAME:path>`, as `NAME=DIR`.  May be given more than once.
+    #[arg(long = "include-package", value_parser = parse_package)]
+    include_packages: Vec<(String, PathBuf)>,
+    /// What to compile for: vulkan1.0 to vulkan1.3, or opengl4.5.
+    #[arg(long, value_parser = parse_target_env)]
+    target_env: Option<TargetEnv>,
+    /// The SPIR-V version to emit, 1.0 to 1.6.
+    #[arg(long, value_parser = parse_spirv_version)]
+    spirv_version: Option<SpirvVersion>,
+    /// The function each shader starts at.
+    #[arg(long)]
+    entry_point: Option<String>,
+    /// Include debug info in modules.
+    #[arg(short = 'g', long)]
+    debug_info: bool,
+    /// Compile `.hlsl` files as well.
+    #[arg(long)]
+    hlsl: bool,
+    /// Fail shaders the compiler warns about.
+    #[arg(long)]
+    warnings_as_errors: bool,
+    /// A device profile, in JSON, to check every artifact's limits against.  May be given more
+    /// than once.
+    #[arg(long = "device-profile")]
+    device_profiles: Vec<PathBuf>,
+    /// Decide what changed by contents rather than modification times.
+    #[arg(long)]
+    hash_contents: bool,
+}
+
+impl Build {
+    fn instructions(self) -> Instructions {
+        let record_path = match self.record {
+            Some(record) => record,
+            None => self.out.join(".wrangler_record"),
+        };
+        let to_compile = if self.kinds.is_empty() {
+            KINDS.iter().map(|(_, kind)| *kind).collect()
+        } else {
+            self.kinds
+        };
+        Instructions {
+            to_compile,
+            search_root: self.src,
+            output_root: self.out,
+            record_path,
+            compilation_error_terminates: true,
+            defines: self.defines,
+            include_dirs: self.include_dirs,
+            include_packages: self.include_packages.into_iter().collect(),
+            target_env: self.target_env.unwrap_or_default(),
+            spirv_version: self.spirv_version,
+            entry_point: self.entry_point,
+            generate_debug_info: self.debug_info,
+            hlsl: self.hlsl,
+            warnings_as_errors: self.warnings_as_errors,
+            hash_contents: self.hash_contents,
+            device_profiles: self.device_profiles,
+            ..Default::default()
+        }
+    }
+}
+
+/// Prints what a run in `watch` did.
+fn print_run(report: &CompilationReport) -> ControlFlow<()> {
+    for e in report.errors.iter() {
+        eprintln!("error: {}", e);
+    }
+    let compiled = report.compiled().count();
+    let failed = report.failed().count();
+    if compiled + failed > 0 {
+        println!("compiled {}, failed {}", compiled, failed);
+    }
+    ControlFlow::Continue(())
+}
+
+/// Prints the differences `compare` found, a shader to a line.
+fn print_diff(diff: &ManifestDiff) {
+    for shader in diff.added.iter() {
+        println!(
+            "added    {}  {} bytes",
+            shader.source.display(),
+            shader.size
+        );
+    }
+    for shader in diff.removed.iter() {
+        println!(
+            "removed  {}  {} bytes",
+            shader.source.display(),
+            shader.size
+        );
+    }
+    for shader in diff.changed.iter() {
+        println!(
+            "changed  {}  {:+} bytes  {} -> {}",
+            shader.source.display(),
+            shader.size_delta(),
+            &shader.old_hash[..shader.old_hash.len().min(12)],
+            &shader.new_hash[..shader.new_hash.len().min(12)]
+        );
+    }
+    println!(
+        "{} added, {} removed, {} changed, {} unchanged, {:+} bytes",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.changed.len(),
+        diff.unchanged,
+        diff.size_delta()
+    );
+}
+
+fn main() -> ExitCode {
+    let result = match Cli::parse().command {
+        Command::Build(build) => wrangler::run(build.instructions()),
+        Command::Watch(build) => wrangler::watch(build.instructions(), print_run),
+        Command::Compare { old, new, json } => {
+            wrangler::compare(&old, &new).map(|diff| match json {
+                true => println!("{}", serde_json::to_string_pretty(&diff).unwrap()),
+                false => print_diff(&diff),
+            })
+        }
+        #[cfg(unix)]
+        Command::Daemon { socket, build } => wrangler::daemon::serve(build.instructions(), &socket),
+        #[cfg(unix)]
+        Command::Send { socket, command } => {
+            wrangler::daemon::send(&socket, &command.join(" ")).map(|reply| println!("{}", reply))
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
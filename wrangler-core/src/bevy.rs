@@ -0,0 +1,261 @@
+// Compiles shaders one asset at a time, for asset pipelines like Bevy's that hand a processor
+// each asset as it changes and take the processed bytes back, instead of having wrangler walk
+// `search_root` itself.  Each asset still goes through the record: one that's up to date with
+// its artifact in `output_root` is read back rather than compiled, and one that isn't is
+// compiled, written, and logged the same way `run` would.
+//
+// `ShaderLoader` plugs this into Bevy as an asset loader making `CompiledShader`s.  Outside
+// Bevy, use a `ShaderProcessor` directly.  `Instructions` aren't `Send`, so a processor can't be
+// shared between Bevy's worker threads, and the loader builds one per asset instead.
+
+use crate::record::{self, Record};
+use crate::{
+    after_write, compile_candidate, extend_obfuscation_map, is_spvasm, kind_of, reason,
+    remove_stale, resources, write_output, Backend, CompilationCandidate, Error, Instructions,
+    Result,
+};
+use bevy_asset::io::Reader;
+use bevy_asset::{Asset, AssetLoader, LoadContext};
+use bevy_reflect::TypePath;
+use std::path::Path;
+
+/// What an asset is processed into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum ShaderFormat {
+    #[default]
+    Spirv,
+    /// SPIR-V, translated to WGSL with naga, which is what Bevy's renderer reads most readily.
+    Wgsl,
+}
+
+/// One asset, processed by `ShaderProcessor::process`.
+#[derive(Clone, Debug)]
+pub struct ProcessedShader {
+    /// The module, in the machine's byte order.
+    pub spirv: Vec<u32>,
+    /// The module as WGSL, if that's what was asked for.
+    pub wgsl: Option<String>,
+    /// Whether the record had it up to date, so it wasn't compiled again.
+    pub cached: bool,
+}
+
+/// Compiles shader assets one at a time through the record, reusing one compiler throughout.
+pub struct ShaderProcessor {
+    instructions: Instructions,
+    backend: Option<Backend>,
+}
+
+impl ShaderProcessor {
+    /// `instructions.search_root` should be where the asset pipeline keeps its sources, like
+    /// Bevy's `assets` directory, and `output_root` somewhere the artifacts can be cached.
+    pub fn new(instructions: Instructions) -> ShaderProcessor {
+        ShaderProcessor {
+            instructions,
+            backend: None,
+        }
+    }
+
+    pub fn instructions(&self) -> &Instructions {
+        &self.instructions
+    }
+
+    /// Processes the shader at `path`, relative to `search_root`, into `format`.  The stage is
+    /// taken from its extension, as in `run`.
+    pub fn process(&mut self, path: &Path, format: ShaderFormat) -> Result<ProcessedShader> {
+        let instructions = &self.instructions;
+        let location = instructions.search_root.join(path);
+        let candidate = CompilationCandidate {
+            shader_kind: kind_of(instructions, &location)?,
+            location,
+        };
+        let _lock = record::lock(instructions)?;
+        let mut record = Record::try_load(instructions)?;
+        let artifact = record.outputs(&candidate.location).into_iter().next();
+        let cached = match artifact {
+            Some(artifact) if !is_spvasm(&artifact) => {
+                match reason(instructions, &candidate, &record)? {
+                    None => resources::read_words(&artifact),
+                    Some(_) => None,
+                }
+            }
+            _ => None,
+        };
+        let (spirv, cached) = match cached {
+            Some(spirv) => (spirv, true),
+            None => {
+                let mut backend = match self.backend.take() {
+                    Some(backend) => backend,
+                    None => Backend::new(instructions)?,
+                };
+                let output = compile_candidate(&mut backend, instructions, &candidate);
+                self.backend = Some(backend);
+                let output = output?;
+                let mut written = write_output(instructions, &output)?;
+                let artifact = written[0].clone();
+                written.extend(after_write(instructions, &output, &artifact)?);
+                let previous = record.outputs(&output.location);
+                record.log(&output.location, &output.includes, &written)?;
+                if instructions.hashed_output_names {
+                    remove_stale(&previous, &written)?;
+                }
+                record.write()?;
+                if let Some(path) = &instructions.obfuscation_map {
+                    extend_obfuscation_map(path, output.renamed)?;
+                }
+                (output.spirv, false)
+            }
+        };
+        let wgsl =
+            match format {
+                ShaderFormat::Spirv => None,
+                ShaderFormat::Wgsl => Some(crate::front::to_wgsl(&spirv).map_err(|message| {
+                    Error::CrossCompilation {
+                        path: candidate.location.clone(),
+                        target: crate::CrossTarget::Wgsl,
+                        message,
+                    }
+                })?),
+            };
+        Ok(ProcessedShader {
+            spirv,
+            wgsl,
+            cached,
+        })
+    }
+}
+
+/// A shader asset, as `ShaderLoader` loads it.
+#[derive(Asset, TypePath, Clone, Debug)]
+pub struct CompiledShader {
+    /// The module, in the machine's byte order.
+    pub spirv: Vec<u32>,
+    /// The module as WGSL, if the asset's settings asked for it.
+    pub wgsl: Option<String>,
+}
+
+/// The settings of a shader asset, as its `.meta` file or `load_with_settings` gives them.
+#[derive(Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct ShaderLoaderSettings {
+    pub format: ShaderFormat,
+}
+
+/// Loads shader assets for Bevy's `AssetServer`, compiling each through a `ShaderProcessor`.
+/// Register it with `app.register_asset_loader(ShaderLoader::new(...))` and
+/// `app.init_asset::<CompiledShader>()`.  It claims the GLSL stage extensions, `.hlsl`,
+/// `.spvasm`, and `.wgsl`, some of which Bevy's own shader loader claims as well; asking for a
+/// `Handle<CompiledShader>` picks this one.
+#[derive(TypePath)]
+pub struct ShaderLoader {
+    instructions: Box<dyn Fn() -> Instructions + Send + Sync>,
+}
+
+impl ShaderLoader {
+    /// `instructions` is called for every asset loaded.  Its `search_root` should be the
+    /// directory Bevy's default asset source reads, as `ShaderProcessor::new` says.
+    pub fn new(instructions: impl Fn() -> Instructions + Send + Sync + 'static) -> ShaderLoader {
+        ShaderLoader {
+            instructions: Box::new(instructions),
+        }
+    }
+}
+
+impl AssetLoader for ShaderLoader {
+    type Asset = CompiledShader;
+    type Settings = ShaderLoaderSettings;
+    type Error = Error;
+
+    // The record decides whether to read the artifact or the source, so what Bevy read is left
+    // alone.
+    async fn load(
+        &self,
+        _reader: &mut dyn Reader,
+        settings: &ShaderLoaderSettings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<CompiledShader> {
+        let mut processor = ShaderProcessor::new((self.instructions)());
+        let processed = processor.process(load_context.path().path(), settings.format)?;
+        Ok(CompiledShader {
+            spirv: processed.spirv,
+            wgsl: processed.wgsl,
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[
+            "vert", "frag", "comp", "geom", "tesc", "tese", "rgen", "rmiss", "rchit", "rahit",
+            "rint", "rcall", "task", "mesh", "hlsl", "spvasm", "wgsl",
+        ]
+    }
+}
+
+// The only frontend that works without shaderc is naga's WGSL one.
+#[cfg(all(test, feature = "wgsl"))]
+mod tests {
+    use super::*;
+    use bevy_app::{App, TaskPoolPlugin};
+    use bevy_asset::{AssetApp, AssetPlugin, AssetServer, Assets, Handle, LoadState};
+    use std::fs;
+
+    #[test]
+    fn bevy_loads_shaders_through_the_record() {
+        let dir = std::env::temp_dir().join(format!("wrangler-bevy-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("sky.comp.wgsl"),
+            "@compute @workgroup_size(1)\nfn main() {}\n",
+        )
+        .unwrap();
+        let root = dir.clone();
+        let mut app = App::new();
+        app.add_plugins((
+            TaskPoolPlugin::default(),
+            AssetPlugin {
+                file_path: dir.to_str().unwrap().to_owned(),
+                watch_for_changes_override: Some(false),
+                use_asset_processor_override: Some(false),
+                ..Default::default()
+            },
+        ))
+        .init_asset::<CompiledShader>()
+        .register_asset_loader(ShaderLoader::new(move || Instructions {
+            search_root: root.clone(),
+            output_root: root.join("out"),
+            record_path: root.join("record"),
+            ..Default::default()
+        }));
+        let server = app.world().resource::<AssetServer>().clone();
+        let handle: Handle<CompiledShader> = server.load("sky.comp.wgsl");
+        let mut loaded = None;
+        for _ in 0..10_000 {
+            app.update();
+            if let LoadState::Failed(e) = server.load_state(&handle) {
+                panic!("{}", e);
+            }
+            if let Some(shader) = app
+                .world()
+                .resource::<Assets<CompiledShader>>()
+                .get(&handle)
+            {
+                loaded = Some(shader.clone());
+                break;
+            }
+        }
+        let shader = loaded.expect("the shader never loaded");
+        assert_eq!(shader.spirv[0], crate::spirv::MAGIC);
+        assert_eq!(shader.wgsl, None);
+        // It went through the record, so the next load of it is a cache hit.
+        let mut processor = ShaderProcessor::new(Instructions {
+            search_root: dir.clone(),
+            output_root: dir.join("out"),
+            record_path: dir.join("record"),
+            ..Default::default()
+        });
+        let again = processor
+            .process(Path::new("sky.comp.wgsl"), ShaderFormat::Spirv)
+            .unwrap();
+        assert!(again.cached);
+        assert_eq!(again.spirv, shader.spirv);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
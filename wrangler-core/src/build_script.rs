@@ -0,0 +1,92 @@
+// Running wrangler from a `build.rs` the way cargo expects build scripts to behave: artifacts
+// in `OUT_DIR`, reruns only when a shader changes, and problems shown in cargo's own output.
+// `feature_defines` ties shader defines to the crate's own features.
+
+use crate::{run_report, Error, Instructions, Result};
+use std::env;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+/// Prints `message` so cargo shows it as a warning, one line of the message at a time, since
+/// cargo only takes the first line of each.
+fn cargo_warning(message: impl Display) {
+    for line in message.to_string().lines() {
+        println!("cargo:warning={}", line);
+    }
+}
+
+fn rerun_if_changed(path: &Path) {
+    println!("cargo:rerun-if-changed={}", path.display());
+}
+
+/// The defines of the features in `table` cargo turned on for the crate being built, for
+/// `Instructions::defines`.  `table` pairs a feature, as `Cargo.toml` names it, with the define
+/// it turns on, as `NAME` or `NAME=VALUE`.  Keeping the table in one place means a shader can't
+/// be built with a feature the Rust code was built without, or the other way around.
+///
+/// ```ignore
+/// let mut instructions = wrangler::Instructions { /* ... */ ..Default::default() };
+/// instructions.defines.extend(wrangler::build_script::feature_defines(&[
+///     ("raytracing", "USE_RAYTRACING"),
+///     ("shadow-quality-high", "SHADOW_SAMPLES=16"),
+/// ]));
+/// ```
+pub fn feature_defines(table: &[(&str, &str)]) -> Vec<(String, Option<String>)> {
+    table
+        .iter()
+        .filter(|(feature, _)| {
+            let var = format!("CARGO_FEATURE_{}", feature.to_uppercase().replace('-', "_"));
+            env::var_os(var).is_some()
+        })
+        .map(|(_, define)| match define.split_once('=') {
+            Some((name, value)) => (name.to_owned(), Some(value.to_owned())),
+            None => ((*define).to_owned(), None),
+        })
+        .collect()
+}
+
+/// Runs `instructions` from a build script.  An empty `output_root` or `record_path` defaults
+/// to `shaders` or `shader_record.dat` in `OUT_DIR`.  Cargo is told to run the build script
+/// again when anything under `search_root`, `include_dirs`, or `include_packages` changes, and
+/// every warning and failure is printed as a `cargo:warning`.  Failures fail the build only if
+/// `compilation_error_terminates` says so, in a `BatchError` like `run`'s.
+///
+/// ```ignore
+/// // build.rs
+/// fn main() {
+///     wrangler::build_script::run(wrangler::Instructions {
+///         search_root: "shaders".into(),
+///         to_compile: vec![wrangler::ShaderKind::Vertex, wrangler::ShaderKind::Fragment],
+///         ..Default::default()
+///     })
+///     .unwrap();
+/// }
+/// ```
+pub fn run(mut instructions: Instructions) -> Result<()> {
+    let out_dir = env::var_os("OUT_DIR").map(PathBuf::from);
+    if instructions.output_root.as_os_str().is_empty() {
+        instructions.output_root = out_dir.clone().ok_or(Error::NoOutDir)?.join("shaders");
+    }
+    if instructions.record_path.as_os_str().is_empty() {
+        instructions.record_path = out_dir.ok_or(Error::NoOutDir)?.join("shader_record.dat");
+    }
+    // Directories count as changed when anything in them does, which covers new shaders.
+    rerun_if_changed(&instructions.search_root);
+    instructions
+        .include_dirs
+        .iter()
+        .chain(instructions.include_packages.values())
+        .for_each(|dir| rerun_if_changed(dir));
+
+    let terminates = instructions.compilation_error_terminates;
+    let report = run_report(instructions)?;
+    for file in report.files.iter() {
+        rerun_if_changed(&file.source);
+        file.warnings.iter().for_each(cargo_warning);
+    }
+    report.errors.iter().for_each(cargo_warning);
+    if terminates && !report.errors.is_empty() {
+        return Err(Error::BatchError(report.errors));
+    }
+    Ok(())
+}
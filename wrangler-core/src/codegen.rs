@@ -0,0 +1,241 @@
+// Writes a Rust source file embedding every artifact, for `Instructions::rust_module`, so a
+// game can carry its shaders in the binary instead of reading them at runtime.  Each artifact
+// gets a `pub const` byte slice, named after its source like `FX_SKY_FRAG` for `fx/sky.frag`,
+// which points at the artifact through `include_bytes!` and is aligned to four bytes so it can
+// be cast to `&[u32]`.  A `shader` function finds them by the source's path relative to
+// `search_root`, with forward slashes, the names packs use.
+//
+// With `platforms`, each platform's run writes a module of its own, and the one at `rust_module`
+// includes each inside a `pub mod` named after the platform, under the `cfg` that selects it.
+// Whichever platform is selected has its constants and `shader` re-exported at the top, so code
+// using the module doesn't change, and the other platforms' artifacts aren't embedded at all.
+
+use crate::platform::{self, Platform};
+use crate::record::Record;
+use crate::{hashed, is_spvasm, CompilationCandidate, Instructions, IoContext, Result};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// Turns a shader's name into a constant's: `fx/sky.frag` becomes `FX_SKY_FRAG`.
+fn const_name(name: &str) -> String {
+    let mut ident: String = name
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect();
+    if !ident.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        ident.insert(0, 'S');
+    }
+    ident
+}
+
+/// Writes the module to `path`, unless it already says the same thing.
+pub(crate) fn write(
+    instructions: &Instructions,
+    candidates: &[CompilationCandidate],
+    record: &Record,
+    path: &Path,
+) -> Result<()> {
+    let mut shaders = Vec::new();
+    for candidate in candidates.iter() {
+        let artifact = match record.outputs(&candidate.location).into_iter().next() {
+            Some(artifact) if !is_spvasm(&artifact) => artifact,
+            _ => continue,
+        };
+        let size = match fs::metadata(&artifact) {
+            Ok(metadata) => metadata.len(),
+            Err(_) => continue,
+        };
+        // `include_bytes!` takes paths relative to the file it's in, which is wherever the
+        // module gets included from.
+        let artifact = fs::canonicalize(&artifact).context("find", &artifact)?;
+        let tail = candidate
+            .location
+            .strip_prefix(&instructions.search_root)
+            .unwrap();
+        shaders.push((hashed::key(tail), artifact, size));
+    }
+    shaders.sort();
+
+    let mut taken = BTreeSet::new();
+    let mut text = String::from(
+        "// Generated by wrangler from the shaders it compiled.  Changes are overwritten.\n\n\
+         #[repr(C, align(4))]\n\
+         struct Aligned<T: ?Sized>(T);\n",
+    );
+    let mut arms = String::new();
+    for (name, artifact, size) in shaders.iter() {
+        let mut ident = const_name(name);
+        // `a-b.frag` and `a_b.frag` would otherwise both be `A_B_FRAG`.
+        while !taken.insert(ident.clone()) {
+            ident.push('_');
+        }
+        write!(
+            text,
+            "\n/// `{}`\npub const {}: &[u8] = {{\n    \
+             const ALIGNED: &Aligned<[u8; {}]> = &Aligned(*include_bytes!({:?}));\n    \
+             &ALIGNED.0\n}};\n",
+            name, ident, size, artifact
+        )
+        .unwrap();
+        writeln!(arms, "        {:?} => Some({}),", name, ident).unwrap();
+    }
+    write!(
+        text,
+        "\n/// The module compiled from `name`, a path relative to the search root such as\n\
+         /// `fx/sky.frag`.\n\
+         pub fn shader(name: &str) -> Option<&'static [u8]> {{\n    \
+         match name {{\n{}        _ => None,\n    }}\n}}\n",
+        arms
+    )
+    .unwrap();
+    write_if_changed(path, &text)
+}
+
+/// The `cfg` predicate that selects `platform`.
+fn platform_cfg(platform: &Platform) -> String {
+    match &platform.rust_cfg {
+        Some(cfg) => cfg.clone(),
+        None => format!("feature = {:?}", platform.name),
+    }
+}
+
+/// The module a platform's constants go in: `steam-deck` goes in `steam_deck`.
+fn module_name(platform: &str) -> String {
+    let name = platform.replace('-', "_");
+    match name.starts_with(|c: char| c.is_ascii_digit()) {
+        true => format!("_{}", name),
+        false => name,
+    }
+}
+
+/// Writes the module at `path` that picks among the modules each of `platforms` was given.
+pub(crate) fn write_platforms(platforms: &[Platform], path: &Path) -> Result<()> {
+    let mut text = String::from(
+        "// Generated by wrangler from the shaders it compiled.  Changes are overwritten.\n",
+    );
+    for platform in platforms.iter() {
+        let module = platform::tagged(path, &platform.name);
+        let module = fs::canonicalize(&module).context("find", &module)?;
+        let cfg = platform_cfg(platform);
+        write!(
+            text,
+            "\n#[cfg({cfg})]\npub mod {name} {{\n    include!({module:?});\n}}\n\
+             #[cfg({cfg})]\npub use {name}::*;\n",
+            cfg = cfg,
+            name = module_name(&platform.name),
+            module = module,
+        )
+        .unwrap();
+    }
+    write_if_changed(path, &text)
+}
+
+/// Writes `text` to `path`, unless it's there already, so that cargo doesn't rebuild whatever
+/// includes it after every run.
+fn write_if_changed(path: &Path, text: &str) -> Result<()> {
+    if fs::read_to_string(path).ok().as_deref() == Some(text) {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("create directory", parent)?;
+    }
+    fs::write(path, text).context("write", path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ShaderKind;
+
+    #[test]
+    fn every_artifact_gets_an_aligned_constant_and_a_match_arm() {
+        let dir = std::env::temp_dir().join(format!("wrangler-rust-module-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src/fx")).unwrap();
+        fs::create_dir_all(dir.join("out")).unwrap();
+        let instructions = Instructions {
+            search_root: dir.join("src"),
+            output_root: dir.join("out"),
+            record_path: dir.join("record"),
+            ..Default::default()
+        };
+        let mut record = Record::try_load(&instructions).unwrap();
+        let mut candidates = Vec::new();
+        for name in ["fx/a-b.frag", "fx/a_b.frag", "9.frag", "unbuilt.frag"] {
+            let source = dir.join("src").join(name);
+            fs::write(&source, "").unwrap();
+            if name != "unbuilt.frag" {
+                let artifact = dir.join("out").join(name.replace('/', "_"));
+                fs::write(&artifact, [0u8; 8]).unwrap();
+                record.log(&source, &[], &[artifact]).unwrap();
+            }
+            candidates.push(CompilationCandidate {
+                location: source,
+                shader_kind: ShaderKind::Fragment,
+            });
+        }
+        let path = dir.join("shaders.rs");
+        write(&instructions, &candidates, &record, &path).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let artifact = fs::canonicalize(dir.join("out/fx_a-b.frag")).unwrap();
+        assert!(text.contains(&format!(
+            "/// `fx/a-b.frag`\npub const FX_A_B_FRAG: &[u8] = {{\n    \
+             const ALIGNED: &Aligned<[u8; 8]> = &Aligned(*include_bytes!({:?}));\n",
+            artifact
+        )));
+        // Names that come out the same are told apart, and ones that would start with a digit
+        // get a letter first.
+        assert!(text.contains("pub const FX_A_B_FRAG_: &[u8]"));
+        assert!(text.contains("        \"fx/a_b.frag\" => Some(FX_A_B_FRAG_),\n"));
+        assert!(text.contains("        \"9.frag\" => Some(S9_FRAG),\n"));
+        assert!(!text.contains("unbuilt"));
+
+        // An unchanged module isn't written again, so cargo has nothing to rebuild.
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        write(&instructions, &candidates, &record, &path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), modified);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn each_platform_is_embedded_under_its_cfg() {
+        let dir = std::env::temp_dir().join(format!("wrangler-codegen-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("shaders.desktop.rs"), "").unwrap();
+        fs::write(dir.join("shaders.steam-deck.rs"), "").unwrap();
+        let platforms = [
+            Platform {
+                name: "desktop".to_owned(),
+                ..Default::default()
+            },
+            Platform {
+                name: "steam-deck".to_owned(),
+                rust_cfg: Some("target_os = \"linux\"".to_owned()),
+                ..Default::default()
+            },
+        ];
+        let path = dir.join("shaders.rs");
+        write_platforms(&platforms, &path).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let deck = fs::canonicalize(dir.join("shaders.steam-deck.rs")).unwrap();
+        assert!(text.contains("#[cfg(feature = \"desktop\")]\npub mod desktop {\n"));
+        assert!(text.contains(&format!(
+            "#[cfg(target_os = \"linux\")]\npub mod steam_deck {{\n    include!({:?});\n}}\n\
+             #[cfg(target_os = \"linux\")]\npub use steam_deck::*;\n",
+            deck
+        )));
+
+        // A platform whose run wrote no module can't be embedded.
+        fs::remove_file(dir.join("shaders.desktop.rs")).unwrap();
+        assert!(write_platforms(&platforms, &path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
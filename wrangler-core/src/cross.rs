@@ -0,0 +1,126 @@
+// Translates finished modules into other shading languages, for platforms that don't take
+// SPIR-V, and writes each translation into a tree of its own that mirrors `output_root`.  SPIRV-Cross
+// does the translating, except to WGSL, which naga does.  Translation goes off the final module,
+// after specialization and obfuscation.
+
+use crate::{
+    kind_ext, place, CompileOutput, CrossTarget, Error, Instructions, IoContext, Result, ShaderKind,
+};
+#[cfg(feature = "cross")]
+use spirv_cross::spirv::{Ast, Compile, Module, Parse, Target};
+#[cfg(feature = "cross")]
+use spirv_cross::{glsl, hlsl, msl, ErrorCode};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn translate(target: CrossTarget, spirv: &[u32]) -> std::result::Result<String, String> {
+    match target {
+        #[cfg(feature = "cross")]
+        CrossTarget::Msl { ios } => {
+            let mut options = msl::CompilerOptions::default();
+            options.version = msl::Version::V2_0;
+            if ios {
+                options.platform = msl::Platform::iOS;
+            }
+            spirv_cross::<msl::Target>(spirv, &options)
+        }
+        #[cfg(feature = "cross")]
+        CrossTarget::Hlsl => {
+            let mut options = hlsl::CompilerOptions::default();
+            options.shader_model = hlsl::ShaderModel::V5_1;
+            spirv_cross::<hlsl::Target>(spirv, &options)
+        }
+        #[cfg(feature = "cross")]
+        CrossTarget::GlslEs => {
+            let mut options = glsl::CompilerOptions::default();
+            options.version = glsl::Version::V3_10Es;
+            spirv_cross::<glsl::Target>(spirv, &options)
+        }
+        #[cfg(feature = "wgsl-out")]
+        CrossTarget::Wgsl => crate::front::to_wgsl(spirv),
+    }
+}
+
+#[cfg(feature = "cross")]
+fn spirv_cross<T>(
+    spirv: &[u32],
+    options: &<Ast<T> as Compile<T>>::CompilerOptions,
+) -> std::result::Result<String, String>
+where
+    T: Target,
+    Ast<T>: Parse<T> + Compile<T>,
+{
+    let module = Module::from_words(spirv);
+    let mut ast = Ast::<T>::parse(&module).map_err(message)?;
+    ast.set_compiler_options(options).map_err(message)?;
+    ast.compile().map_err(message)
+}
+
+#[cfg(feature = "cross")]
+fn message(e: ErrorCode) -> String {
+    match e {
+        ErrorCode::CompilationError(message) => message,
+        ErrorCode::Unhandled => "unhandled error".to_owned(),
+    }
+}
+
+fn extension(target: CrossTarget) -> &'static str {
+    match target {
+        #[cfg(feature = "cross")]
+        CrossTarget::Msl { .. } => "metal",
+        #[cfg(feature = "cross")]
+        CrossTarget::Hlsl => "hlsl",
+        #[cfg(feature = "cross")]
+        CrossTarget::GlslEs => "glsl",
+        #[cfg(feature = "wgsl-out")]
+        CrossTarget::Wgsl => "wgsl",
+    }
+}
+
+/// Where the translation of `location` for `target` goes under `root`, e.g. `sky.frag.metal`.
+/// It goes where the rename policy puts the artifact under `output_root`, but keeps this naming
+/// whatever `output_name` says.
+fn cross_path(
+    instructions: &Instructions,
+    location: &Path,
+    kind: &ShaderKind,
+    target: CrossTarget,
+    root: &Path,
+) -> Result<PathBuf> {
+    let tail = place(instructions, location, kind, "{stem}.{kind}")?;
+    let mut dest = root.join(tail);
+    dest.set_extension(format!("{}.{}", kind_ext(kind)?, extension(target)));
+    Ok(dest)
+}
+
+/// Where every translation `cross_compile` asks for of `location` goes.
+pub(crate) fn paths(
+    instructions: &Instructions,
+    location: &Path,
+    kind: &ShaderKind,
+) -> Result<Vec<PathBuf>> {
+    instructions
+        .cross_compile
+        .iter()
+        .map(|(target, root)| cross_path(instructions, location, kind, *target, root))
+        .collect()
+}
+
+/// Writes every translation `cross_compile` asks for and returns their paths.
+pub(crate) fn write(instructions: &Instructions, out: &CompileOutput) -> Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+    for (target, root) in instructions.cross_compile.iter() {
+        let source = translate(*target, &out.spirv).map_err(|e| Error::CrossCompilation {
+            path: out.location.clone(),
+            target: *target,
+            message: e,
+        })?;
+        let dest = cross_path(instructions, &out.location, &out.shader_kind, *target, root)?;
+        if let Some(dir) = dest.parent() {
+            fs::create_dir_all(dir).context("create directory", dir)?;
+        }
+        fs::write(&dest, source).context("write", &dest)?;
+        written.push(dest);
+    }
+    Ok(written)
+}
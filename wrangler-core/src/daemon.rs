@@ -0,0 +1,253 @@
+// A long-running `Watcher` that other tools on the machine drive over a Unix socket, so that
+// they share one warm compiler and one record rather than each running builds of their own.
+// A connection sends one command per line and gets back a line of JSON for each:
+//
+//     status                  whether building is paused, and what the last build did
+//     build                   build now, even while paused
+//     pause                   stop building as shaders change; the changes pile up meanwhile
+//     resume                  build whatever changed while paused, and keep building as before
+//     invalidate [shader...]  compile these shaders, or all of them, at the next build
+//     stop                    shut the daemon down
+//
+// Every reply has `ok`, the daemon's state, and `error` if the command failed.  Only the user
+// running the daemon can connect to its socket.
+
+use crate::{invalidate, CompilationReport, Error, Instructions, IoContext, Result, Watcher};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// How often the daemon looks for changes to build when no commands are coming in.
+const POLL: Duration = Duration::from_millis(50);
+
+/// What the last build did, for `status`.
+#[derive(Clone, Serialize, Deserialize)]
+struct Summary {
+    compiled: usize,
+    up_to_date: usize,
+    failed: usize,
+    errors: Vec<String>,
+}
+
+impl Summary {
+    fn new(report: &CompilationReport) -> Summary {
+        Summary {
+            compiled: report.compiled().count(),
+            up_to_date: report.up_to_date().count(),
+            failed: report.failed().count(),
+            errors: report.errors.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Reply {
+    ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    paused: bool,
+    builds: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last: Option<Summary>,
+}
+
+struct Daemon {
+    watcher: Watcher,
+    paused: bool,
+    builds: usize,
+    last: Option<Summary>,
+    stopping: bool,
+}
+
+impl Daemon {
+    fn finish(&mut self, report: Result<CompilationReport>) -> Result<()> {
+        self.builds += 1;
+        match report {
+            Ok(report) => {
+                self.last = Some(Summary::new(&report));
+                Ok(())
+            }
+            // Unlike `watch`, a failed build doesn't end anything; it's only reported.
+            Err(e) => {
+                self.last = Some(Summary {
+                    compiled: 0,
+                    up_to_date: 0,
+                    failed: 0,
+                    errors: vec![e.to_string()],
+                });
+                Err(e)
+            }
+        }
+    }
+
+    fn handle(&mut self, line: &str) -> Reply {
+        let mut words = line.split_whitespace();
+        let result = match words.next() {
+            Some("status") => Ok(()),
+            Some("build") => {
+                let report = self.watcher.build();
+                self.finish(report)
+            }
+            Some("pause") => {
+                self.paused = true;
+                Ok(())
+            }
+            Some("resume") => {
+                self.paused = false;
+                Ok(())
+            }
+            Some("invalidate") => {
+                let shaders: Vec<PathBuf> = words.map(PathBuf::from).collect();
+                invalidate(self.watcher.instructions(), &shaders)
+            }
+            Some("stop") => {
+                self.stopping = true;
+                Ok(())
+            }
+            _ => Err(Error::Daemon(format!("unknown command `{}`", line.trim()))),
+        };
+        Reply {
+            ok: result.is_ok(),
+            error: result.err().map(|e| e.to_string()),
+            paused: self.paused,
+            builds: self.builds,
+            last: self.last.clone(),
+        }
+    }
+}
+
+/// Reads commands off `stream` and hands each to the daemon, writing back its reply.
+fn converse(stream: UnixStream, requests: Sender<(String, Sender<Reply>)>) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        let (reply, replies) = mpsc::channel();
+        if requests.send((line, reply)).is_err() {
+            return;
+        }
+        let reply = match replies.recv() {
+            Ok(reply) => reply,
+            Err(_) => return,
+        };
+        let mut text = serde_json::to_string(&reply).unwrap();
+        text.push('\n');
+        if writer.write_all(text.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+/// Builds `instructions`, then keeps building them as shaders change, like `watch`, while
+/// taking commands from any tool that connects to `socket`, until one sends `stop`.  Errors
+/// building, other than shaders failing to compile, don't end the daemon but show up in `status`.
+pub fn serve(instructions: Instructions, socket: &Path) -> Result<()> {
+    if socket.exists() {
+        if UnixStream::connect(socket).is_ok() {
+            return Err(Error::Daemon(format!(
+                "a daemon is already listening at `{}`",
+                socket.display()
+            )));
+        }
+        // Left behind by a daemon that didn't get to clean up.
+        fs::remove_file(socket).context("remove", socket)?;
+    }
+    let listener = bind_private(socket)?;
+    let (requests, incoming) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let requests = requests.clone();
+            thread::spawn(move || converse(stream, requests));
+        }
+    });
+
+    let mut daemon = Daemon {
+        watcher: Watcher::new(instructions)?,
+        paused: false,
+        builds: 0,
+        last: None,
+        stopping: false,
+    };
+    while !daemon.stopping {
+        match incoming.recv_timeout(POLL) {
+            Ok((line, reply)) => {
+                let _ = reply.send(daemon.handle(&line));
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        if !daemon.paused {
+            if let Some(report) = daemon.watcher.try_recv() {
+                let _ = daemon.finish(report);
+            }
+        }
+    }
+    fs::remove_file(socket).context("remove", socket)
+}
+
+/// Listens at `socket` with only this user able to connect.  It's bound in a directory no one
+/// else can enter and made 0600 before it's moved into place, so no one gets in between.
+fn bind_private(socket: &Path) -> Result<UnixListener> {
+    let mut dir = socket.to_owned().into_os_string();
+    dir.push(".bind");
+    let dir = PathBuf::from(dir);
+    if dir.exists() {
+        fs::remove_dir_all(&dir).context("remove", &dir)?;
+    }
+    fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&dir)
+        .context("create directory", &dir)?;
+    let bound = dir.join("socket");
+    let listener = UnixListener::bind(&bound).context("listen on", &bound)?;
+    fs::set_permissions(&bound, fs::Permissions::from_mode(0o600))
+        .context("set permissions of", &bound)?;
+    fs::rename(&bound, socket).context("move", &bound)?;
+    fs::remove_dir(&dir).context("remove", &dir)?;
+    Ok(listener)
+}
+
+/// Sends `command` to the daemon listening at `socket` and returns its reply, a line of JSON.
+/// A command the daemon couldn't carry out comes back as an `Error::Daemon`.
+pub fn send(socket: &Path, command: &str) -> Result<String> {
+    let mut stream = UnixStream::connect(socket).context("connect to", socket)?;
+    stream
+        .write_all(format!("{}\n", command.trim()).as_bytes())
+        .context("write to", socket)?;
+    let mut text = String::new();
+    BufReader::new(stream)
+        .read_line(&mut text)
+        .context("read from", socket)?;
+    let reply: Reply = serde_json::from_str(&text)
+        .map_err(|e| Error::Daemon(format!("bad reply `{}`: {}", text.trim(), e)))?;
+    match reply.error {
+        Some(error) if !reply.ok => Err(Error::Daemon(error)),
+        _ => Ok(text.trim_end().to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_its_user_can_use_the_socket() {
+        let socket = std::env::temp_dir().join(format!("wrangler-daemon-{}", std::process::id()));
+        let _listener = bind_private(&socket).unwrap();
+        let mode = fs::metadata(&socket).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(UnixStream::connect(&socket).is_ok());
+        fs::remove_file(&socket).unwrap();
+    }
+}
@@ -0,0 +1,391 @@
+// Picks apart the messages shaderc hands back, which look like
+// `path/to/sky.frag:12: error: 'x' : undeclared identifier`, into values with the location and
+// severity split out.  Lines that don't look like a message are taken to continue the one before.
+// With the `pretty` feature, diagnostics can also be printed with the lines they point at.
+//
+// Diagnostics from GLSL get hints for the mistakes that come up most, like a missing `#version`,
+// an extension that isn't enabled, a built-in from another stage, or `std140` layout rules,
+// going by the message and the source.
+
+use crate::Severity;
+use regex::Regex;
+use serde::Serialize;
+use std::fmt;
+use std::path::PathBuf;
+#[cfg(feature = "pretty")]
+use {
+    codespan_reporting::diagnostic::{Diagnostic as Report, Label, Severity as Level},
+    codespan_reporting::files::SimpleFiles,
+    codespan_reporting::term::{self, termcolor},
+    std::collections::HashMap,
+    std::ops::Range,
+    std::path::Path,
+};
+
+/// One message from the compiler.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    /// The file the message is about, which may be an include rather than the shader itself.
+    pub file: Option<PathBuf>,
+    pub line: Option<u32>,
+    /// glslang rarely reports columns, so this is usually `None`.
+    pub column: Option<u32>,
+    pub severity: Severity,
+    pub message: String,
+    /// Suggestions for fixing what the message is about.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hints: Vec<String>,
+}
+
+impl Diagnostic {
+    /// A message with no location, for failures that don't come with one.
+    pub(crate) fn error(file: Option<PathBuf>, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            file,
+            line: None,
+            column: None,
+            severity: Severity::Error,
+            message: message.into(),
+            hints: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file.display())?;
+        }
+        if let Some(line) = self.line {
+            write!(f, "{}:", line)?;
+        }
+        if let Some(column) = self.column {
+            write!(f, "{}:", column)?;
+        }
+        if self.file.is_some() || self.line.is_some() {
+            f.write_str(" ")?;
+        }
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}: {}", severity, self.message)?;
+        for hint in self.hints.iter() {
+            write!(f, "\n  hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the text of shaderc's error or warning messages.
+#[cfg_attr(not(feature = "shaderc"), allow(dead_code))]
+pub(crate) fn parse(text: &str) -> Vec<Diagnostic> {
+    // The file name is matched lazily so that a drive letter, as in `C:\sky.frag:3:`, stays part
+    // of it.
+    let re = Regex::new(
+        r"^(?:(?P<file>.*?):(?:(?P<line>\d+):)?(?:(?P<column>\d+):)? )?(?P<severity>warning|error|fatal error|internal error): (?P<message>.*)$",
+    )
+    .unwrap();
+    let summary =
+        Regex::new(r"^\d+ (errors?|warnings?)( and \d+ (errors?|warnings?))? generated\.$")
+            .unwrap();
+    let mut diagnostics = Vec::<Diagnostic>::new();
+    for line in text.lines().map(str::trim_end) {
+        if line.trim().is_empty() || summary.is_match(line) {
+            continue;
+        }
+        let captures = match re.captures(line) {
+            Some(captures) => captures,
+            None => {
+                match diagnostics.last_mut() {
+                    Some(last) => {
+                        last.message.push('\n');
+                        last.message.push_str(line);
+                    }
+                    None => diagnostics.push(Diagnostic::error(None, line)),
+                }
+                continue;
+            }
+        };
+        let number = |name| captures.name(name).and_then(|m| m.as_str().parse().ok());
+        diagnostics.push(Diagnostic {
+            file: captures
+                .name("file")
+                .filter(|m| !m.as_str().is_empty())
+                .map(|m| PathBuf::from(m.as_str())),
+            line: number("line"),
+            column: number("column"),
+            severity: match &captures["severity"] {
+                "warning" => Severity::Warning,
+                _ => Severity::Error,
+            },
+            message: captures["message"].trim().to_owned(),
+            hints: Vec::new(),
+        });
+    }
+    diagnostics
+}
+
+/// Turns any failure from shaderc into diagnostics.  Only compilation errors carry messages in
+/// the usual format; the rest become a single error with no location.
+#[cfg(feature = "shaderc")]
+pub(crate) fn from_shaderc(error: shaderc::Error) -> Vec<Diagnostic> {
+    let text = match error {
+        shaderc::Error::CompilationError(_, text) => {
+            let diagnostics = parse(&text);
+            if !diagnostics.is_empty() {
+                return diagnostics;
+            }
+            text
+        }
+        shaderc::Error::InternalError(text) => text,
+        shaderc::Error::InvalidStage(text) => format!("invalid stage: {}", text),
+        shaderc::Error::InvalidAssembly(text) => format!("invalid assembly: {}", text),
+        shaderc::Error::NullResultObject(text) => text,
+    };
+    vec![Diagnostic::error(None, text)]
+}
+
+/// Built-ins and functions only some stages have, with the extension of the stage they're for.
+const STAGE_ONLY: &[(&str, &str)] = &[
+    ("gl_FragCoord", "frag"),
+    ("gl_FragDepth", "frag"),
+    ("gl_FrontFacing", "frag"),
+    ("gl_PointCoord", "frag"),
+    ("gl_HelperInvocation", "frag"),
+    ("discard", "frag"),
+    ("dFdx", "frag"),
+    ("dFdy", "frag"),
+    ("fwidth", "frag"),
+    ("gl_VertexIndex", "vert"),
+    ("gl_InstanceIndex", "vert"),
+    ("gl_GlobalInvocationID", "comp"),
+    ("gl_LocalInvocationID", "comp"),
+    ("gl_LocalInvocationIndex", "comp"),
+    ("gl_WorkGroupID", "comp"),
+    ("gl_NumWorkGroups", "comp"),
+    ("EmitVertex", "geom"),
+    ("EndPrimitive", "geom"),
+    ("gl_TessLevelOuter", "tesc"),
+    ("gl_TessLevelInner", "tesc"),
+    ("gl_TessCoord", "tese"),
+];
+
+fn has_version(source: &str) -> bool {
+    source
+        .lines()
+        .any(|line| line.trim_start().starts_with("#version"))
+}
+
+const NO_VERSION: &str = "there is no `#version` line, so an old version of GLSL is assumed; \
+                          start the file with one, like `#version 450`";
+
+/// A suggestion for `diagnostic`, other than `NO_VERSION`, from GLSL `source` compiled as a
+/// shader with the stage extension `ext`.
+fn hint(diagnostic: &Diagnostic, source: &str, ext: &str) -> Option<String> {
+    let message = diagnostic.message.as_str();
+    if message.contains("extension") {
+        let needed: Vec<&str> = Regex::new(r"GL_[A-Za-z0-9_]+")
+            .unwrap()
+            .find_iter(message)
+            .map(|m| m.as_str())
+            .filter(|extension| !source.contains(&format!("#extension {}", extension)))
+            .collect();
+        match needed.as_slice() {
+            [] => {}
+            [extension] => {
+                return Some(format!(
+                    "enable it below `#version` with `#extension {} : require`",
+                    extension
+                ))
+            }
+            extensions => {
+                return Some(format!(
+                    "enable one of {} below `#version`, like `#extension {} : require`",
+                    extensions.join(", "),
+                    extensions[0]
+                ))
+            }
+        }
+    }
+    if message.contains("std430") && message.contains("buffer") {
+        return Some(
+            "`std430` is only for `buffer` blocks; use `std140` for uniform blocks, or \
+             `scalar` with GL_EXT_scalar_block_layout"
+                .to_owned(),
+        );
+    }
+    if message.contains("offset") && (message.contains("align") || message.contains("multiple")) {
+        return Some(
+            "under `std140`, a `vec3` is aligned like a `vec4`, and array elements and structs \
+             are rounded up to 16 bytes; pad the block to match, or use `std430` in a storage \
+             buffer"
+                .to_owned(),
+        );
+    }
+    let stage_specific = message.contains("undeclared identifier")
+        || message.contains("not supported in this stage")
+        || message.contains("no matching overloaded function");
+    if stage_specific {
+        let quoted = Regex::new(r"'([^']+)'").unwrap();
+        let name = quoted.captures(message)?.get(1)?.as_str().trim();
+        let (_, stage) = STAGE_ONLY.iter().find(|(n, _)| *n == name)?;
+        if *stage != ext {
+            return Some(format!(
+                "`{}` is only for `.{}` shaders, and this file is compiled as `.{}` because of \
+                 its extension; rename it if it's meant for the other stage",
+                name, stage, ext
+            ));
+        }
+    }
+    None
+}
+
+/// Attaches suggestions for common mistakes to `diagnostics`, from GLSL `source` compiled as a
+/// shader with the stage extension `ext`.  A missing `#version` is only pointed out once.
+pub(crate) fn add_hints(diagnostics: &mut [Diagnostic], source: &str, ext: &str) {
+    let mut versioned = has_version(source);
+    for diagnostic in diagnostics.iter_mut() {
+        let message = diagnostic.message.as_str();
+        let about_version =
+            message.contains("#version") || message.contains("not supported for this version");
+        if about_version && !versioned {
+            diagnostic.hints.push(NO_VERSION.to_owned());
+            versioned = true;
+        } else if let Some(hint) = hint(diagnostic, source, ext) {
+            diagnostic.hints.push(hint);
+        }
+    }
+}
+
+/// Where the text of line `line`, counting from 1, sits in `source`, leaving out its
+/// indentation so the underline starts under the code.
+#[cfg(feature = "pretty")]
+fn line_range(source: &str, line: u32) -> Option<Range<usize>> {
+    let mut start = 0;
+    for (number, text) in source.split('\n').enumerate() {
+        if number + 1 == line as usize {
+            let text = text.trim_end_matches('\r');
+            let indent = text.len() - text.trim_start().len();
+            return Some(start + indent..start + text.len());
+        }
+        start += text.len() + 1;
+    }
+    None
+}
+
+/// Prints `diagnostics` to stderr, each with the source line it points at underlined, in color
+/// if stderr is a terminal.  Diagnostics whose file can't be read or has no such line are
+/// printed without a snippet.
+#[cfg(feature = "pretty")]
+pub(crate) fn print_pretty(diagnostics: &[Diagnostic]) {
+    let mut files = SimpleFiles::new();
+    let mut ids = HashMap::<&Path, Option<usize>>::new();
+    let stderr = termcolor::StandardStream::stderr(termcolor::ColorChoice::Auto);
+    let config = term::Config::default();
+    for diagnostic in diagnostics.iter() {
+        let level = match diagnostic.severity {
+            Severity::Warning => Level::Warning,
+            Severity::Error => Level::Error,
+        };
+        let mut report = Report::new(level).with_message(&diagnostic.message);
+        let file = diagnostic.file.as_deref();
+        let span = file.zip(diagnostic.line).and_then(|(file, line)| {
+            let id = *ids.entry(file).or_insert_with(|| {
+                let source = std::fs::read_to_string(file).ok()?;
+                Some(files.add(file.display().to_string(), source))
+            });
+            let id = id?;
+            let range = line_range(files.get(id).ok()?.source(), line)?;
+            Some((id, range))
+        });
+        report = match (span, file) {
+            (Some((id, range)), _) => report.with_labels(vec![Label::primary(id, range)]),
+            (None, Some(file)) => report.with_notes(vec![format!("in `{}`", file.display())]),
+            (None, None) => report,
+        };
+        let hints = diagnostic
+            .hints
+            .iter()
+            .map(|hint| format!("hint: {}", hint));
+        report = report.with_notes(hints.collect());
+        // Nothing more useful to do if stderr is gone.
+        let _ = term::emit_to_write_style(&mut stderr.lock(), &config, &files, &report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_locations_and_severities() {
+        let text = "shaders/sky.frag:12: error: 'x' : undeclared identifier\n\
+                    shaders/sky.frag:3:7: warning: unused variable\n\
+                    2 errors generated.\n";
+        let diagnostics = parse(text);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].file, Some(PathBuf::from("shaders/sky.frag")));
+        assert_eq!(diagnostics[0].line, Some(12));
+        assert_eq!(diagnostics[0].column, None);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].message, "'x' : undeclared identifier");
+        assert_eq!(diagnostics[1].line, Some(3));
+        assert_eq!(diagnostics[1].column, Some(7));
+        assert_eq!(diagnostics[1].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn keeps_drive_letters_in_file_names() {
+        let diagnostics = parse(r"C:\shaders\sky.frag:3: fatal error: can't open include");
+        assert_eq!(
+            diagnostics[0].file,
+            Some(PathBuf::from(r"C:\shaders\sky.frag"))
+        );
+        assert_eq!(diagnostics[0].line, Some(3));
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn continues_messages_over_lines() {
+        let diagnostics = parse("sky.frag:1: error: first\n  more about it\nerror: no location");
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].message, "first\n  more about it");
+        assert_eq!(diagnostics[1].file, None);
+        assert_eq!(diagnostics[1].line, None);
+        // Text with nothing before it to continue is an error of its own.
+        assert_eq!(parse("something odd")[0].message, "something odd");
+    }
+
+    #[test]
+    fn displays_like_the_compiler() {
+        let mut diagnostic = parse("sky.frag:12: error: bad").remove(0);
+        assert_eq!(diagnostic.to_string(), "sky.frag:12: error: bad");
+        diagnostic.hints.push("fix it".to_owned());
+        assert_eq!(
+            diagnostic.to_string(),
+            "sky.frag:12: error: bad\n  hint: fix it"
+        );
+        assert_eq!(Diagnostic::error(None, "bad").to_string(), "error: bad");
+    }
+
+    #[test]
+    fn hints_at_common_mistakes() {
+        let mut diagnostics = parse(
+            "sky.vert:4: error: 'gl_FragCoord' : undeclared identifier\n\
+             sky.vert:5: error: '#extension' : GL_EXT_nonuniform_qualifier required\n\
+             sky.vert:6: error: '' : not supported for this version\n\
+             sky.vert:7: error: '#version' : bad profile",
+        );
+        add_hints(&mut diagnostics, "void main() {}", "vert");
+        assert!(diagnostics[0].hints[0].contains("only for `.frag` shaders"));
+        assert!(diagnostics[1].hints[0].contains("#extension GL_EXT_nonuniform_qualifier"));
+        assert_eq!(diagnostics[2].hints, [NO_VERSION]);
+        // Only pointed out once.
+        assert!(diagnostics[3].hints.is_empty());
+
+        let mut diagnostics = parse("sky.frag:4: error: 'gl_FragCoord' : undeclared identifier");
+        add_hints(&mut diagnostics, "#version 450", "frag");
+        assert!(diagnostics[0].hints.is_empty());
+    }
+}
@@ -0,0 +1,148 @@
+// Pulls `///` doc comments out of shader sources so engine tooling can show them in an editor.
+//
+// A run of `///` lines documents the first non-blank line after it, e.g. a uniform block, an
+// output, or a function.  Lines starting with `@param name` describe one of its parameters.
+
+use crate::{kind_ext, CompilationCandidate, IoContext, Result};
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize)]
+struct Param {
+    name: String,
+    description: String,
+}
+
+#[derive(Serialize)]
+struct Item {
+    declaration: String,
+    description: String,
+    params: Vec<Param>,
+}
+
+#[derive(Serialize)]
+struct ShaderDocs {
+    path: PathBuf,
+    kind: &'static str,
+    items: Vec<Item>,
+}
+
+fn extract(source: &str) -> Vec<Item> {
+    let mut items = Vec::new();
+    let mut description = Vec::<&str>::new();
+    let mut params = Vec::<Param>::new();
+    for line in source.lines().map(str::trim) {
+        if let Some(doc) = line.strip_prefix("///") {
+            let doc = doc.trim();
+            match doc.strip_prefix("@param") {
+                Some(param) => {
+                    let mut parts = param.trim().splitn(2, char::is_whitespace);
+                    params.push(Param {
+                        name: parts.next().unwrap_or_default().to_owned(),
+                        description: parts.next().unwrap_or_default().trim().to_owned(),
+                    });
+                }
+                None => description.push(doc),
+            }
+        } else if !line.is_empty() && (!description.is_empty() || !params.is_empty()) {
+            items.push(Item {
+                declaration: line.trim_end_matches('{').trim().to_owned(),
+                description: description.join("\n").trim().to_owned(),
+                params: std::mem::take(&mut params),
+            });
+            description.clear();
+        }
+    }
+    items
+}
+
+fn markdown(docs: &[ShaderDocs]) -> String {
+    let mut out = String::from("# Shader documentation\n");
+    for shader in docs.iter() {
+        write!(out, "\n## `{}` ({})\n", shader.path.display(), shader.kind).unwrap();
+        for item in shader.items.iter() {
+            write!(out, "\n### `{}`\n", item.declaration).unwrap();
+            if !item.description.is_empty() {
+                write!(out, "\n{}\n", item.description).unwrap();
+            }
+            if !item.params.is_empty() {
+                out.push('\n');
+            }
+            for param in item.params.iter() {
+                writeln!(out, "- `{}`: {}", param.name, param.description).unwrap();
+            }
+        }
+    }
+    out
+}
+
+/// Writes the documentation for every documented shader in `candidates` to `path`, as JSON if
+/// the path ends in `.json` and as markdown otherwise.
+pub(crate) fn write(path: &Path, candidates: &[CompilationCandidate]) -> Result<()> {
+    let mut docs = Vec::new();
+    for candidate in candidates.iter() {
+        let items =
+            extract(&fs::read_to_string(&candidate.location).context("read", &candidate.location)?);
+        if !items.is_empty() {
+            docs.push(ShaderDocs {
+                path: candidate.location.clone(),
+                kind: kind_ext(&candidate.shader_kind)?,
+                items,
+            });
+        }
+    }
+    let text = if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::to_string_pretty(&docs).context("encode", path)?
+    } else {
+        markdown(&docs)
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("create directory", parent)?;
+    }
+    fs::write(path, text).context("write", path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_the_next_declaration() {
+        let items = extract(
+            "#version 450\n\
+             /// The camera.\n\
+             ///   Updated once a frame.\n\
+             layout(set = 0, binding = 0) uniform Camera {\n\
+             \x20   mat4 view;\n\
+             };\n\
+             \n\
+             /// Shades a point.\n\
+             /// @param normal  The surface normal.\n\
+             /// @param light\n\
+             vec3 shade(vec3 normal, vec3 light) {\n\
+             }\n\
+             void main() {}\n",
+        );
+        assert_eq!(items.len(), 2);
+        assert_eq!(
+            items[0].declaration,
+            "layout(set = 0, binding = 0) uniform Camera"
+        );
+        assert_eq!(items[0].description, "The camera.\nUpdated once a frame.");
+        assert!(items[0].params.is_empty());
+        assert_eq!(items[1].declaration, "vec3 shade(vec3 normal, vec3 light)");
+        assert_eq!(items[1].description, "Shades a point.");
+        assert_eq!(items[1].params[0].name, "normal");
+        assert_eq!(items[1].params[0].description, "The surface normal.");
+        assert_eq!(items[1].params[1].name, "light");
+        assert_eq!(items[1].params[1].description, "");
+    }
+
+    #[test]
+    fn skips_undocumented_code() {
+        assert!(extract("// Not a doc comment.\nvoid main() {}\n").is_empty());
+    }
+}
@@ -0,0 +1,124 @@
+// Compiles shaders with naga rather than shaderc: WGSL, which shaderc doesn't read, and GLSL when
+// `GlslCompiler::Naga` is chosen.  A WGSL module can hold entry points for several stages, so each
+// file is named for the stage it is compiled for, as in `sky.frag.wgsl`, and only that stage's
+// entry point ends up in the artifact.  naga also turns finished modules into WGSL for
+// `CrossTarget::Wgsl`.
+
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+#[cfg(feature = "wgsl")]
+use {crate::kind_from_ext, std::path::Path};
+#[cfg(any(feature = "wgsl", feature = "naga-glsl"))]
+use {
+    crate::{Error, Result, ShaderKind},
+    naga::back::spv,
+};
+
+#[cfg(feature = "wgsl")]
+pub(crate) fn is_wgsl(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "wgsl")
+}
+
+/// Takes the stage of a WGSL file from the extension before `.wgsl`, written like the GLSL ones.
+#[cfg(feature = "wgsl")]
+pub(crate) fn wgsl_kind(path: &Path) -> Option<ShaderKind> {
+    let stage = Path::new(path.file_stem()?).extension()?;
+    kind_from_ext(stage.to_str()?)
+}
+
+#[cfg(any(feature = "wgsl", feature = "naga-glsl"))]
+fn stage(kind: ShaderKind) -> Result<naga::ShaderStage> {
+    match kind {
+        ShaderKind::Vertex => Ok(naga::ShaderStage::Vertex),
+        ShaderKind::Fragment => Ok(naga::ShaderStage::Fragment),
+        ShaderKind::Compute => Ok(naga::ShaderStage::Compute),
+        ShaderKind::Task => Ok(naga::ShaderStage::Task),
+        ShaderKind::Mesh => Ok(naga::ShaderStage::Mesh),
+        x => Err(Error::UnsupportedKind(x)),
+    }
+}
+
+/// Validates `module` and writes out the entry point for `stage` named `entry_point`, or the
+/// only entry point for that stage if none has that name.
+#[cfg(any(feature = "wgsl", feature = "naga-glsl"))]
+fn write(
+    module: naga::Module,
+    source: &str,
+    name: &str,
+    stage: naga::ShaderStage,
+    entry_point: &str,
+    debug_info: bool,
+) -> Result<Vec<u32>> {
+    let info = Validator::new(ValidationFlags::all(), Capabilities::all())
+        .validate(&module)
+        .map_err(|e| Error::Naga(e.emit_to_string_with_path(source, name)))?;
+    let candidates: Vec<_> = module
+        .entry_points
+        .iter()
+        .filter(|e| e.stage == stage)
+        .collect();
+    let entry = match candidates.iter().find(|e| e.name == entry_point) {
+        Some(entry) => entry,
+        None if candidates.len() == 1 => candidates[0],
+        None => {
+            return Err(Error::Naga(format!(
+                "{}: no {:?} entry point named `{}`",
+                name, stage, entry_point
+            )))
+        }
+    };
+    let mut options = spv::Options::default();
+    options.flags.set(spv::WriterFlags::DEBUG, debug_info);
+    let pipeline = spv::PipelineOptions {
+        shader_stage: stage,
+        entry_point: entry.name.clone(),
+    };
+    spv::write_vec(&module, &info, &options, Some(&pipeline))
+        .map_err(|e| Error::Naga(format!("{}: {}", name, e)))
+}
+
+#[cfg(feature = "wgsl")]
+pub(crate) fn compile_wgsl(
+    path: &Path,
+    source: &str,
+    kind: ShaderKind,
+    entry_point: &str,
+    debug_info: bool,
+) -> Result<Vec<u32>> {
+    let name = path.to_string_lossy();
+    let module = naga::front::wgsl::parse_str(source)
+        .map_err(|e| Error::Naga(e.emit_to_string_with_path(source, &name)))?;
+    write(module, source, &name, stage(kind)?, entry_point, debug_info)
+}
+
+/// naga's GLSL frontend has no `#include`, and its entry point is always `main`.
+#[cfg(feature = "naga-glsl")]
+pub(crate) fn compile_glsl(
+    name: &str,
+    source: &str,
+    kind: ShaderKind,
+    defines: &[(String, Option<String>)],
+    debug_info: bool,
+) -> Result<Vec<u32>> {
+    let stage = stage(kind)?;
+    let mut options = naga::front::glsl::Options::from(stage);
+    for (define, value) in defines.iter() {
+        let value = value.clone().unwrap_or_default();
+        options.defines.insert(define.clone(), value);
+    }
+    let module = naga::front::glsl::Frontend::default()
+        .parse(&options, source)
+        .map_err(|e| Error::Naga(e.emit_to_string_with_path(source, name)))?;
+    write(module, source, name, stage, "main", debug_info)
+}
+
+#[cfg(feature = "wgsl-out")]
+pub(crate) fn to_wgsl(spirv: &[u32]) -> std::result::Result<String, String> {
+    let bytes: Vec<u8> = spirv.iter().flat_map(|word| word.to_le_bytes()).collect();
+    let options = naga::front::spv::Options::default();
+    let module = naga::front::spv::parse_u8_slice(&bytes, &options).map_err(|e| e.to_string())?;
+    let info = Validator::new(ValidationFlags::all(), Capabilities::all())
+        .validate(&module)
+        .map_err(|e| e.into_inner().to_string())?;
+    naga::back::wgsl::write_string(&module, &info, naga::back::wgsl::WriterFlags::empty())
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,51 @@
+// Names artifacts after their contents, for `hashed_output_names`, so an asset pipeline can
+// cache them forever: `sky.spv_frag` is written as `sky.3f2a9c1e.spv_frag`, and a new name comes
+// with every change.  A manifest in `output_root` maps each artifact's plain name, the one it
+// would have without the hash, to the name it was written under.
+//
+// Plain names are paths relative to `output_root` with forward slashes, as is what they map to.
+
+use crate::{IoContext, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The manifest's file name, in `output_root`.
+pub(crate) const MANIFEST: &str = "hashed_names.json";
+
+/// Puts the first eight hex digits of a hash of `contents` in front of the last extension of
+/// `tail`.
+pub(crate) fn insert_hash(tail: &Path, contents: &[u8]) -> PathBuf {
+    let hash = &blake3::hash(contents).to_hex()[..8];
+    let stem = tail.file_stem().unwrap().to_string_lossy();
+    let name = match tail.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, hash, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, hash),
+    };
+    tail.with_file_name(name)
+}
+
+pub(crate) fn key(tail: &Path) -> String {
+    tail.to_string_lossy().replace('\\', "/")
+}
+
+/// Reads the manifest under `output_root`, which is empty if there isn't one yet.
+pub(crate) fn load(output_root: &Path) -> BTreeMap<String, String> {
+    fs::read(output_root.join(MANIFEST))
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
+
+/// Replaces the manifest under `output_root` with `names`, by way of a temporary file so a
+/// reader never sees half of it.
+pub(crate) fn write(output_root: &Path, names: &BTreeMap<String, String>) -> Result<()> {
+    let path = output_root.join(MANIFEST);
+    let mut temp = path.clone().into_os_string();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    let text = serde_json::to_string_pretty(names).context("encode", &path)?;
+    fs::write(&temp, text).context("write", &temp)?;
+    fs::rename(&temp, &path).context("replace", &path)?;
+    Ok(())
+}
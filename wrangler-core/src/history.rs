@@ -0,0 +1,121 @@
+// An append-only log of past runs, kept next to the record so build times can be compared over
+// weeks of development.  Each run appends one MessagePack value.  Nothing is rewritten, except
+// that a torn tail left by a run that died mid-append is cut off by the next one.
+
+use crate::{Instructions, IoContext, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// What happened during one call to `run`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunSummary {
+    /// Counts up from zero over the life of the history file.
+    pub id: u64,
+    pub started: SystemTime,
+    /// Identifies the set of compile options in effect, so runs can be grouped by configuration.
+    pub options_hash: String,
+    /// How many shaders were found under `search_root`.
+    pub discovered: usize,
+    /// How many of those were out of date and compiled successfully.
+    pub compiled: usize,
+    pub failed: usize,
+    pub duration: Duration,
+}
+
+fn history_path(instructions: &Instructions) -> PathBuf {
+    instructions.record_path.with_extension("history")
+}
+
+/// Reads every run in the history, and how many bytes they take up; anything past that is a
+/// torn tail.
+fn load(instructions: &Instructions) -> Result<(Vec<RunSummary>, u64)> {
+    let path = history_path(instructions);
+    if !path.exists() {
+        return Ok((Vec::new(), 0));
+    }
+    let bytes = fs::read(&path).context("read", &path)?;
+    let mut rest = &bytes[..];
+    let mut runs = Vec::new();
+    let mut good = 0;
+    // Stop at the first entry that won't decode; a run that died mid-append leaves a torn tail.
+    while let Ok(run) = rmp_serde::from_read(&mut rest) {
+        runs.push(run);
+        good = bytes.len() - rest.len();
+    }
+    Ok((runs, good as u64))
+}
+
+pub(crate) fn append(instructions: &Instructions, mut run: RunSummary) -> Result<()> {
+    let (runs, good) = load(instructions)?;
+    run.id = runs.last().map_or(0, |last| last.id + 1);
+    let path = history_path(instructions);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("create directory", parent)?;
+    }
+    let mut bytes = Vec::new();
+    rmp_serde::encode::write(&mut bytes, &run)
+        .map_err(std::io::Error::other)
+        .context("encode", &path)?;
+    let mut f = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(&path)
+        .context("open", &path)?;
+    // Appending after a torn tail would leave this run, and every one after it, unreadable.
+    f.set_len(good).context("truncate", &path)?;
+    f.seek(SeekFrom::Start(good)).context("seek", &path)?;
+    f.write_all(&bytes).context("write", &path)?;
+    Ok(())
+}
+
+/// Returns up to `limit` of the most recent runs recorded for `instructions`, oldest first.
+/// Runs are only recorded when `keep_history` is set.
+pub fn recent_runs(instructions: &Instructions, limit: usize) -> Result<Vec<RunSummary>> {
+    let (mut runs, _) = load(instructions)?;
+    let skip = runs.len().saturating_sub(limit);
+    Ok(runs.split_off(skip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run() -> RunSummary {
+        RunSummary {
+            id: 0,
+            started: SystemTime::UNIX_EPOCH,
+            options_hash: String::new(),
+            discovered: 2,
+            compiled: 1,
+            failed: 0,
+            duration: Duration::from_millis(5),
+        }
+    }
+
+    #[test]
+    fn runs_after_a_torn_tail_can_be_read() {
+        let dir = std::env::temp_dir().join(format!("wrangler-history-{}", std::process::id()));
+        let instructions = Instructions {
+            record_path: dir.join("record.dat"),
+            ..Default::default()
+        };
+        append(&instructions, run()).unwrap();
+        append(&instructions, run()).unwrap();
+        let path = history_path(&instructions);
+        let mut f = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        f.write_all(&[0x95, 0x01]).unwrap();
+        append(&instructions, run()).unwrap();
+        let ids: Vec<u64> = recent_runs(&instructions, 10)
+            .unwrap()
+            .iter()
+            .map(|run| run.id)
+            .collect();
+        assert_eq!(ids, [0, 1, 2]);
+        assert_eq!(recent_runs(&instructions, 1).unwrap()[0].id, 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,116 @@
+// Writes a run as a JUnit XML report, one test case per shader, for CI dashboards that chart
+// test results.  A shader passes if it compiled or was already up to date.
+
+use crate::{FileReport, FileStatus, IoContext, Result};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // XML 1.0 can't hold most control characters, even escaped.
+            c if c.is_control() && !matches!(c, '\n' | '\r' | '\t') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Writes a test case for every shader in `files` to `path`.
+pub(crate) fn write(path: &Path, files: &[FileReport]) -> Result<()> {
+    let failures = files
+        .iter()
+        .filter(|f| f.status == FileStatus::Failed)
+        .count();
+    let time: f64 = files.iter().map(|f| f.duration.as_secs_f64()).sum();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n");
+    writeln!(
+        xml,
+        "  <testsuite name=\"wrangler\" tests=\"{}\" failures=\"{}\" errors=\"0\" time=\"{:.3}\">",
+        files.len(),
+        failures,
+        time
+    )
+    .unwrap();
+    for file in files.iter() {
+        write!(
+            xml,
+            "    <testcase classname=\"{:?}\" name=\"{}\" time=\"{:.3}\"",
+            file.kind,
+            escape(&file.source.to_string_lossy()),
+            file.duration.as_secs_f64()
+        )
+        .unwrap();
+        if file.status != FileStatus::Failed {
+            xml.push_str("/>\n");
+            continue;
+        }
+        let message = file.diagnostics.first().map_or("", |d| d.message.as_str());
+        let text: Vec<_> = file.diagnostics.iter().map(|d| d.to_string()).collect();
+        writeln!(
+            xml,
+            ">\n      <failure message=\"{}\">{}</failure>\n    </testcase>",
+            escape(message),
+            escape(&text.join("\n"))
+        )
+        .unwrap();
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("create directory", parent)?;
+    }
+    fs::write(path, xml).context("write", path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Diagnostic, Severity, ShaderKind};
+    use std::time::Duration;
+
+    #[test]
+    fn each_shader_is_a_test_case_and_failures_carry_their_diagnostics() {
+        let file = |source: &str, status, diagnostics| FileReport {
+            source: source.into(),
+            kind: ShaderKind::Fragment,
+            outputs: Vec::new(),
+            status,
+            duration: Duration::from_millis(250),
+            warnings: Vec::new(),
+            diagnostics,
+        };
+        let error = Diagnostic {
+            file: Some("fx/a&b.frag".into()),
+            line: Some(3),
+            column: None,
+            severity: Severity::Error,
+            message: "'x' : undeclared identifier \u{1}".to_owned(),
+            hints: Vec::new(),
+        };
+        let files = [
+            file("sky.frag", FileStatus::Compiled, Vec::new()),
+            file("fx/a&b.frag", FileStatus::Failed, vec![error]),
+            file("old.frag", FileStatus::UpToDate, Vec::new()),
+        ];
+        let path = std::env::temp_dir().join(format!("wrangler-junit-{}.xml", std::process::id()));
+        write(&path, &files).unwrap();
+        let xml = fs::read_to_string(&path).unwrap();
+        assert!(xml.contains("tests=\"3\" failures=\"1\" errors=\"0\" time=\"0.750\""));
+        assert!(xml.contains("<testcase classname=\"Fragment\" name=\"sky.frag\" time=\"0.250\"/>"));
+        assert!(xml.contains("name=\"fx/a&amp;b.frag\""));
+        assert!(xml.contains(
+            "<failure message=\"&apos;x&apos; : undeclared identifier \">\
+             fx/a&amp;b.frag:3: error: &apos;x&apos; : undeclared identifier </failure>"
+        ));
+        assert_eq!(xml.matches("<testcase ").count(), 3);
+        fs::remove_file(&path).unwrap();
+    }
+}
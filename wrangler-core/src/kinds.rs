@@ -0,0 +1,52 @@
+// Stand-ins for the types wrangler takes from shaderc, for builds without the `shaderc` feature.
+// They have the same variants and fields as shaderc's, so code written against one compiles
+// against the other.
+
+/// The stage a shader is compiled for, as shaderc's `ShaderKind` has it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderKind {
+    Vertex,
+    Fragment,
+    Compute,
+    Geometry,
+    TessControl,
+    TessEvaluation,
+    InferFromSource,
+    DefaultVertex,
+    DefaultFragment,
+    DefaultCompute,
+    DefaultGeometry,
+    DefaultTessControl,
+    DefaultTessEvaluation,
+    SpirvAssembly,
+    RayGeneration,
+    AnyHit,
+    ClosestHit,
+    Miss,
+    Intersection,
+    Callable,
+    DefaultRayGeneration,
+    DefaultAnyHit,
+    DefaultClosestHit,
+    DefaultMiss,
+    DefaultIntersection,
+    DefaultCallable,
+    Task,
+    Mesh,
+    DefaultTask,
+    DefaultMesh,
+}
+
+/// Whether an `#include` was `"quoted"` or `<bracketed>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IncludeType {
+    Relative,
+    Standard,
+}
+
+/// A file found for an `#include`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResolvedInclude {
+    pub resolved_name: String,
+    pub content: String,
+}
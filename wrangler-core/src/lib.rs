@@ -0,0 +1,2707 @@
+// The shader wrangler receives a source dir, a target dir, a rename policy, and a list of kinds of
+// shaders to compile.  It compiles via shaderc and looks for files with a parallel directory walk.
+
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime};
+use thiserror::Error;
+
+use record::Record;
+use trace::{event, span};
+
+#[cfg(feature = "bevy")]
+pub mod bevy;
+pub mod build_script;
+mod codegen;
+#[cfg(any(feature = "cross", feature = "wgsl-out"))]
+mod cross;
+#[cfg(all(feature = "daemon", unix))]
+pub mod daemon;
+mod diagnostic;
+mod docs;
+#[cfg(any(feature = "wgsl", feature = "naga-glsl", feature = "wgsl-out"))]
+mod front;
+mod hashed;
+pub mod history;
+mod junit;
+#[cfg(not(feature = "shaderc"))]
+mod kinds;
+#[cfg(feature = "link")]
+mod link;
+mod manifest;
+mod naming;
+mod pack;
+mod platform;
+mod pragma;
+mod record;
+#[cfg(feature = "remote")]
+pub mod remote;
+mod report;
+pub mod reporter;
+mod resources;
+mod sarif;
+#[cfg(feature = "ship")]
+mod ship;
+mod spirv;
+mod targets;
+pub mod telemetry;
+mod trace;
+#[cfg(feature = "watch")]
+mod watch;
+
+pub use ::spirv::Capability;
+pub use diagnostic::Diagnostic;
+#[cfg(not(feature = "shaderc"))]
+pub use kinds::{IncludeType, ResolvedInclude, ShaderKind};
+#[cfg(feature = "link")]
+pub use link::LinkedProgram;
+pub use manifest::{
+    compare, verify_manifest, ChangedShader, Churn, ManifestDiff, ManifestShader, VerifiedManifest,
+};
+pub use naming::NamingRule;
+#[cfg(feature = "zstd")]
+pub use pack::decompress;
+pub use pack::{KeyProvider, PackEntry, ShaderPack};
+pub use platform::Platform;
+pub use record::VERSION as RECORD_VERSION;
+pub use report::{CompilationReport, FileReport, FileStatus};
+pub use reporter::Reporter;
+#[cfg(feature = "shaderc")]
+pub use shaderc::{IncludeType, ResolvedInclude, ShaderKind};
+pub use spirv::{DescriptorKind, SpecValue};
+pub use telemetry::Telemetry;
+#[cfg(feature = "watch")]
+pub use watch::{watch, Watcher};
+
+/// Errors that `wrangler` might encounter during compilation.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Kind {0:?} not supported by wrangler")]
+    UnsupportedKind(ShaderKind),
+    #[error("No shader kind uses the extension of `{0}`")]
+    UnrecognizedExtension(PathBuf),
+    #[error("Bad exclude pattern: `{0}`")]
+    BadExcludePattern(String),
+    #[error("Bad target rule pattern: `{0}`")]
+    BadTargetPattern(String),
+    #[error("Bad naming rule pattern: `{0}`")]
+    BadNamingPattern(String),
+    #[error("`{path}` breaks the naming rule `{pattern}`")]
+    NamingViolation { path: PathBuf, pattern: String },
+    #[error("Error while searching for shaders: {0:?}")]
+    Walk(#[from] ignore::Error),
+    #[error("Couldn't {operation} `{}`: {source}", path.display())]
+    Io {
+        operation: &'static str,
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Error initializing the shaderc compiler")]
+    CompilerInit,
+    #[cfg(not(feature = "shaderc"))]
+    #[error("`{0}` needs shaderc, which wrangler was built without")]
+    NoShaderc(String),
+    #[error("Error compiling file to SPIR-V:{}", list(.0))]
+    Compilation(Vec<Diagnostic>),
+    #[cfg(feature = "link")]
+    #[error("Couldn't link `{}`: {1}", .0.display())]
+    Link(PathBuf, String),
+    #[error("Post-processing `{path}` exited with {status:?}: {output}")]
+    PostProcess {
+        path: PathBuf,
+        status: Option<i32>,
+        output: String,
+    },
+    #[cfg(feature = "remote")]
+    #[error("Remote compilation failed: {0}")]
+    Remote(String),
+    #[error("Module compiled from HLSL wasn't legalized; it still copies resources into {0}")]
+    NotLegalized(String),
+    #[error("Module uses {0}, which is not allowed")]
+    NotAllowed(String),
+    #[error("Module uses {0}, which {1:?} doesn't support")]
+    Unsupported(String, TargetEnv),
+    #[error("`{path}` has a bad wrangler option: `{option}`")]
+    BadPragma { path: PathBuf, option: String },
+    #[cfg(any(feature = "wgsl", feature = "naga-glsl"))]
+    #[error("naga couldn't compile shader: {0}")]
+    Naga(String),
+    #[cfg(any(feature = "cross", feature = "wgsl-out"))]
+    #[error("Couldn't translate `{path}` to {target:?}: {message}")]
+    CrossCompilation {
+        path: PathBuf,
+        target: CrossTarget,
+        message: String,
+    },
+    #[error("SPIR-V assembly can only be read or written by a local shaderc")]
+    AssemblyUnavailable,
+    #[error("Another run is using the record; its lock `{0}` is held")]
+    Locked(PathBuf),
+    #[error("`{}` and `{}` would both be written to `{}`", first.display(), second.display(), output.display())]
+    OutputCollision {
+        first: PathBuf,
+        second: PathBuf,
+        output: PathBuf,
+    },
+    #[cfg(feature = "watch")]
+    #[error("Couldn't watch for changes: {0}")]
+    Watch(#[from] notify::Error),
+    #[cfg(all(feature = "daemon", unix))]
+    #[error("Daemon error: {0}")]
+    Daemon(String),
+    #[error("OUT_DIR isn't set, as it is for build scripts")]
+    NoOutDir,
+    #[error("Bad manifest `{}`: {1}", .0.display())]
+    BadManifest(PathBuf, String),
+    #[error("Bad manifest key: {0}")]
+    BadManifestKey(String),
+    #[cfg(feature = "ship")]
+    #[error("`{}` changed since its artifact in `ship_from` was built", .0.display())]
+    SourceChanged(PathBuf),
+    #[cfg(feature = "ship")]
+    #[error("`{}` has no artifact in `ship_from`", .0.display())]
+    NotShipped(PathBuf),
+    #[error("Bad platform name `{0}`; names must be unique, and letters, digits, `-` and `_`")]
+    BadPlatform(String),
+    #[error("Bad device profile `{}`: {1}", .0.display())]
+    BadProfile(PathBuf, String),
+    #[error("`{}` goes over the limits of {profile}: {over}", .path.display())]
+    OverLimits {
+        path: PathBuf,
+        profile: String,
+        over: String,
+    },
+    #[error("Bad shader pack: {0}")]
+    BadPack(String),
+    #[error("Bad SPIR-V module: {0}")]
+    BadModule(String),
+    #[error("obfuscation_map is set without an obfuscation_key")]
+    NoObfuscationKey,
+    #[cfg(feature = "zstd")]
+    #[error("Couldn't decompress module: {0}")]
+    Decompression(std::io::Error),
+    #[error("Couldn't specialize module: {0}")]
+    Specialization(String),
+    #[error("Encountered errors compiling some files:{}", list(.0))]
+    BatchError(Vec<Error>),
+    #[error(transparent)]
+    File(Box<FileError>),
+}
+
+/// The failure of a single shader, found in a `BatchError` as `Error::File`.
+#[derive(Error, Debug)]
+#[error("`{}` ({kind:?}): {source}", path.display())]
+pub struct FileError {
+    pub path: PathBuf,
+    pub kind: ShaderKind,
+    pub source: Error,
+}
+
+/// Turns IO errors into `Error::Io`, naming what was being done to which path.
+trait IoContext<T> {
+    fn context(self, operation: &'static str, path: &Path) -> Result<T>;
+}
+
+impl<T> IoContext<T> for std::io::Result<T> {
+    fn context(self, operation: &'static str, path: &Path) -> Result<T> {
+        self.map_err(|source| Error::Io {
+            operation,
+            path: path.to_owned(),
+            source,
+        })
+    }
+}
+
+/// Turns failures to serialize what's written to `path` into `Error::Io` as well.
+impl<T> IoContext<T> for serde_json::Result<T> {
+    fn context(self, operation: &'static str, path: &Path) -> Result<T> {
+        self.map_err(std::io::Error::from).context(operation, path)
+    }
+}
+
+#[cfg(feature = "shaderc")]
+impl From<shaderc::Error> for Error {
+    fn from(e: shaderc::Error) -> Error {
+        Error::Compilation(diagnostic::from_shaderc(e))
+    }
+}
+
+fn list(items: &[impl std::fmt::Display]) -> String {
+    items.iter().map(|item| format!("\n    {}", item)).collect()
+}
+
+/// How seriously to take a problem wrangler finds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// Print a warning and carry on.
+    Warning,
+    /// Count the problem as a failure, like a shader that doesn't compile.
+    Error,
+}
+
+/// The byte order SPIR-V words are written to disk in.  Consumers can tell which was used from
+/// the magic number in the first word, but many loaders only accept their own byte order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Endianness {
+    /// Whatever the machine running wrangler uses.
+    #[default]
+    Native,
+    Little,
+    Big,
+}
+
+/// How modules in the pack are compressed, for `Instructions::pack_compression`.
+#[cfg(feature = "zstd")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PackCompression {
+    /// Every module at this zstd level, 1 to 22.
+    Level(i32),
+    /// Each module at the lowest level that makes it `ratio` of its size or smaller, trying
+    /// higher levels in turn on every run.  A level that takes longer than `max_time` over one
+    /// module is the last one tried, and if no level reaches `ratio` the smallest result wins.
+    Auto {
+        ratio: f64,
+        max_time: std::time::Duration,
+    },
+}
+
+/// A key modules in the pack are encrypted with, for `Instructions::pack_key`.  `id` is written
+/// into the pack, so that a reader holding several keys can tell which one it needs.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PackKey {
+    pub id: u32,
+    pub key: [u8; 32],
+}
+
+// Keys have no business in logs.
+impl std::fmt::Debug for PackKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PackKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// When written artifacts are flushed all the way to disk with `fsync`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum SyncPolicy {
+    /// Leave it to the operating system.
+    #[default]
+    Never,
+    /// Sync each artifact as soon as it has been written.
+    EachFile,
+    /// Sync every artifact written during the run once they are all written.
+    EndOfRun,
+}
+
+/// How the record is stored, for `Instructions::record_format`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum RecordFormat {
+    #[default]
+    MessagePack,
+    /// One JSON value per line, for reading in a text editor.
+    Json,
+}
+
+/// What a run does when another one is using the same record, for `Instructions::lock`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum LockPolicy {
+    /// Wait for the other run to finish.
+    #[default]
+    Wait,
+    /// Give up with `Error::Locked`.
+    Fail,
+}
+
+/// Whether SPIR-V assembly is written for each shader, for `Instructions::spirv_assembly`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum SpirvAssembly {
+    #[default]
+    Never,
+    /// Beside the binary, with `.spvasm` appended to its name.
+    Alongside,
+    /// In place of the binary, under the name `Alongside` would give it.
+    Instead,
+}
+
+/// The API compiled modules are meant for.  This decides which SPIR-V versions and features the
+/// compiler may use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TargetEnv {
+    #[default]
+    Vulkan1_0,
+    Vulkan1_1,
+    Vulkan1_2,
+    Vulkan1_3,
+    OpenGl4_5,
+}
+
+/// A version of SPIR-V to emit, for `Instructions::spirv_version`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpirvVersion {
+    V1_0,
+    V1_1,
+    V1_2,
+    V1_3,
+    V1_4,
+    V1_5,
+    V1_6,
+}
+
+/// Which compiler turns GLSL into SPIR-V, for `Instructions::glsl_compiler`.
+#[cfg(feature = "naga-glsl")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum GlslCompiler {
+    #[default]
+    Shaderc,
+    /// naga's pure-Rust frontend.  It supports no `#include`, HLSL, or entry points named other
+    /// than `main`, and ignores the optimization and target options.
+    Naga,
+}
+
+/// A language `Instructions::cross_compile` translates modules into.
+#[cfg(any(feature = "cross", feature = "wgsl-out"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrossTarget {
+    /// Metal Shading Language 2.0, for iOS if `ios` is set and for macOS otherwise.
+    #[cfg(feature = "cross")]
+    Msl { ios: bool },
+    /// HLSL for shader model 5.1.
+    #[cfg(feature = "cross")]
+    Hlsl,
+    /// GLSL ES 3.10.
+    #[cfg(feature = "cross")]
+    GlslEs,
+    /// WGSL, for wgpu on the web.  Translated by naga, which reads less of SPIR-V than
+    /// SPIRV-Cross does; a module using something it doesn't know fails to translate.
+    #[cfg(feature = "wgsl-out")]
+    Wgsl,
+}
+
+/// Ranks a shader for `Instructions::priority`.
+pub type Priority = dyn Fn(&Path, ShaderKind) -> i32;
+
+/// Looks up the file named by an `#include`, for `Instructions::include_resolver`.  Receives the
+/// requested name, whether it was `"quoted"` or `<bracketed>`, and the name of the file doing
+/// the including.
+pub type IncludeResolver =
+    dyn Fn(&str, IncludeType, &str) -> std::result::Result<ResolvedInclude, String>;
+
+/// Rewrites a shader's source before it is compiled, for `Instructions::transform`.
+pub type Transform = dyn Fn(&Path, ShaderKind, &str) -> String;
+
+/// Places an artifact for `RenamePolicy::Custom`.  Receives the source's path relative to
+/// `search_root` and returns the artifact's path relative to `output_root`.
+pub type Rename = dyn Fn(&Path, ShaderKind) -> PathBuf;
+
+/// Where under `output_root` each shader's artifact goes.  Debug and assembly siblings, and
+/// translations under `cross_compile`, follow the artifact.
+#[derive(Default)]
+pub enum RenamePolicy {
+    /// Mirror the directory tree of `search_root`: `fx/sky.frag` goes to `fx/sky.spv_frag`.
+    #[default]
+    MirrorTree,
+    /// Put every artifact directly in `output_root`: `fx/sky.frag` goes to `sky.spv_frag`.  Two
+    /// shaders with the same name in different directories stop the run with
+    /// `Error::OutputCollision` rather than one overwriting the other.
+    Flatten,
+    /// Like `Flatten`, but name each artifact after a hash of its source's path, e.g.
+    /// `5f0b8e2c91d4a7e3.spv_frag`, so the layout of the sources doesn't show in what ships.
+    HashedNames,
+    Custom(Box<Rename>),
+}
+
+/// Specifies a couple behaviors of the `run` function.
+#[derive(Default)]
+pub struct Instructions {
+    /// The types of shaders we are to search for and compile.  Besides GLSL, files of SPIR-V
+    /// assembly named like `blur.comp.spvasm` are found and assembled, with a local shaderc.
+    pub to_compile: Vec<ShaderKind>,
+    /// Where shaders are searched for.  Paths may be relative to the working directory, which
+    /// for a build script is the crate root.
+    pub search_root: PathBuf,
+    pub output_root: PathBuf,
+    /// How artifact paths are derived from source paths.  Switching policies leaves the old
+    /// artifacts where they are; the next run writes every shader afresh in its new place.
+    pub rename_policy: RenamePolicy,
+    /// How artifacts are named, as a template in which `{stem}` is the source's file name
+    /// without its last extension, `{name}` is all of it, and `{kind}` is the stage's extension.
+    /// Defaults to `{stem}.spv_{kind}`, so `sky.frag` becomes `sky.spv_frag`; `{name}.spv` gives
+    /// `sky.frag.spv`.  Under `RenamePolicy::HashedNames` the stem and name are both the hash,
+    /// and a `Custom` policy names artifacts itself.
+    pub output_name: Option<String>,
+    /// If true, a hash of each artifact's contents goes in its name before the extension, as in
+    /// `sky.3f2a9c1e.spv_frag`, for pipelines that cache files forever by name.  Each run
+    /// replaces `hashed_names.json` in `output_root`, which maps every artifact's plain name to
+    /// its hashed one, and deletes a shader's previous artifacts once new ones are written.
+    pub hashed_output_names: bool,
+    /// If true, each run replaces `manifest.json` in `output_root`, which lists every shader
+    /// with an artifact: its source, kind, entry point, artifact path, size, and hash, and a
+    /// hash of its source and includes.
+    pub write_manifest: bool,
+    /// If set, `manifest.json` is signed with this key, so `verify_manifest` can tell at load
+    /// time whether it, or the artifacts and pack it lists, were changed after the build.  If
+    /// not, the key is taken from `WRANGLER_MANIFEST_KEY`, as 64 hex digits, if that's set.
+    pub manifest_key: Option<[u8; 32]>,
+    /// If set, nothing is compiled: this is the checked-in `output_root` of a build with
+    /// `write_manifest`, and each shader's artifact is checked against the manifest there and
+    /// copied into `output_root`.  A shader whose source or includes changed since, or that the
+    /// manifest doesn't list, fails.  With the `shaderc` feature off, this builds without shaderc.
+    #[cfg(feature = "ship")]
+    pub ship_from: Option<PathBuf>,
+    /// If set, each run packs the artifact of every compiled shader into this one `.spvpak`
+    /// file, replacing the last one: an index giving each module's name, kind, offset, length,
+    /// and hash, followed by the modules, each four-byte aligned.  The loose artifacts are still
+    /// written to `output_root`, which is what later runs build the pack from.
+    pub pack_output: Option<PathBuf>,
+    /// If set, modules in the pack are compressed with zstd, where that makes them smaller.
+    /// `ShaderPack` decompresses them as it reads the pack, and `manifest.json` gives the level
+    /// each module was packed at.
+    #[cfg(feature = "zstd")]
+    pub pack_compression: Option<PackCompression>,
+    /// If set, along with `pack_compression`, a zstd dictionary of at most this many bytes is
+    /// trained over every module and stored in the pack, and modules are compressed with it,
+    /// which does much better than compressing each on its own when there are many small ones.
+    /// `ShaderPack` reads it back by itself.  zstd suggests about 110 KiB.
+    #[cfg(feature = "zstd")]
+    pub pack_dictionary: Option<usize>,
+    /// If set, every module in the pack is encrypted with this key, after any compression, so
+    /// it can only be read with `ShaderPack::open_with_key`.  Names and kinds stay readable.
+    /// Encryption is deterministic, so packs stay reproducible, and only says when two modules
+    /// under the same name are the same.
+    pub pack_key: Option<PackKey>,
+    /// If set, each run writes a Rust source file here with a `pub const` byte slice for every
+    /// artifact, aligned for reading as `u32`s, and a `shader` function finding them by source
+    /// path, like `shader("fx/sky.frag")`.  From a build script, write it into `OUT_DIR` and
+    /// `include!(concat!(env!("OUT_DIR"), "/shaders.rs"))` to embed every shader in the binary.
+    pub rust_module: Option<PathBuf>,
+    /// Where the record of what has been compiled is kept.  Changing an option that affects the
+    /// compiled modules, like `defines` or `target_env`, makes the next run compile everything.
+    pub record_path: PathBuf,
+    /// If true, the record also keeps a hash of every shader and include, and a file whose
+    /// modification time has changed is only recompiled if its contents have too.  For git
+    /// checkouts, CI caches, and container layers, which don't preserve timestamps.
+    pub hash_contents: bool,
+    /// How the record is written.  Records are read in either format regardless, and one in the
+    /// other format is converted the next time it is saved.
+    pub record_format: RecordFormat,
+    /// If true, each run starts by deleting the artifacts of shaders whose sources have been
+    /// removed, like `clean()`.
+    pub prune_orphans: bool,
+    /// What to do when another run, e.g. a parallel build script in the same workspace, is
+    /// using `record_path`.  Runs lock a `.lock` file beside the record while they work.
+    pub lock: LockPolicy,
+    /// If true, `run()` will terminate with an `Err` value if one or more files
+    /// fails to compile.  Otherwise we print a warning describing which files
+    /// failed and how.
+    pub compilation_error_terminates: bool,
+    /// If true, a shader the compiler warns about fails to compile, with the warnings as its
+    /// errors.  Either way, each compiled shader's warnings are kept in its `CompileOutput` and in
+    /// the run's report.
+    pub warnings_as_errors: bool,
+    /// If true, print a warning for every function no entry point can reach and every input or
+    /// output variable the shader never touches.
+    pub report_dead_code: bool,
+    /// If true, each module gets a string carrying the wrangler version, a hash of the options
+    /// that produced it, and a hash of its source, so a frame capture can be traced back to the
+    /// build its shaders came from.
+    pub embed_build_metadata: bool,
+    /// A command run on every artifact after it is written, e.g. a vendor offline compiler.  It is
+    /// split on whitespace and each occurrence of `{spirv}` is replaced with the artifact's path.
+    /// A non-zero exit counts as a failure of that file, with the command's output attached.
+    pub post_process_command: Option<String>,
+    /// The address of a machine running `remote::serve`.  If set, sources are sent there to be
+    /// compiled instead of loading shaderc locally.
+    #[cfg(feature = "remote")]
+    pub remote_compiler: Option<String>,
+    /// The key shared with the `remote_compiler`, which it made `serve` with; it won't take
+    /// requests from a client that can't prove it has the key.
+    #[cfg(feature = "remote")]
+    pub remote_key: Option<[u8; 32]>,
+    /// What compiles GLSL on this machine.  Like the choice of `remote_compiler`, the compiler
+    /// picked for the first set of instructions in `run_all` is used for the rest.
+    #[cfg(feature = "naga-glsl")]
+    pub glsl_compiler: GlslCompiler,
+    /// Decides the order shaders are compiled and written in; higher comes first, and ties keep
+    /// discovery order.  Handy for getting the shaders needed at startup out before the rest.
+    pub priority: Option<Box<Priority>>,
+    /// If true, every run appends a summary of itself to a history file beside the record,
+    /// which `history::recent_runs` reads back.
+    pub keep_history: bool,
+    /// Where to send counters, timers, and gauges describing the run.
+    pub telemetry: Option<Box<dyn Telemetry>>,
+    /// What to tell about the run's progress as it goes.
+    pub reporter: Option<Box<dyn Reporter>>,
+    /// Conventions every discovered shader is checked against, on every run.
+    pub naming_rules: Vec<NamingRule>,
+    /// If true, warn about shaders that compile to identical modules after any run that compiled
+    /// something.  Every artifact in `output_root` gets read and hashed to find them.
+    pub report_duplicates: bool,
+    /// Gitignore-style globs, relative to `search_root`, for files and directories to leave out
+    /// of the search.  Excluded directories aren't descended into at all.
+    pub exclude: Vec<String>,
+    /// Additions to `exclude` that depend on the target being built for.
+    pub target_rules: Vec<TargetRule>,
+    /// Sources at least this many bytes long are memory-mapped and handed to the compiler
+    /// straight from the mapping rather than read into a `String` first, which keeps peak memory
+    /// down for huge generated shaders.
+    pub mmap_threshold: Option<u64>,
+    pub endianness: Endianness,
+    pub sync: SyncPolicy,
+    /// If set, every specialization constant is baked into a plain constant, taking the value
+    /// paired with its `SpecId` here or else its default.  For platforms where specializing at
+    /// pipeline creation is expensive.
+    pub specialization: Option<Vec<(u32, SpecValue)>>,
+    /// Specializes each shader again for every named set of values here, the way
+    /// `specialization` does, and writes each result beside the artifact with the name added to
+    /// its file name, as in `sky.frag.spv.low`.  The artifact itself is left to `specialization`.
+    /// Names end up in file names, so they are best kept to letters, digits, `-` and `_`, and
+    /// can't be `debug` or `spvasm`.
+    pub specialization_variants: Vec<(String, Vec<(u32, SpecValue)>)>,
+    /// Receives each shader's path, kind, and source and returns the text to compile in its
+    /// place, e.g. to inject instrumentation or expand project-specific syntax.  Whatever it
+    /// returns is what the embedded source hash describes.  wrangler can't see inside it, so a
+    /// change to what it does only rebuilds anything if `transform_id` changes with it.
+    pub transform: Option<Box<Transform>>,
+    /// Names the current behaviour of `transform`, like `instrument-v3`.  It is part of the
+    /// options the record was compiled with, so changing it compiles every shader again.
+    pub transform_id: Option<String>,
+    /// If set, every name in `OpName` and `OpMemberName` is swapped for an opaque one derived
+    /// from it and `obfuscation_key`, and the pairs are added to the mapping file at this path as
+    /// `obfuscated<TAB>original` lines once the run is done.  Entry point names are kept, and the
+    /// source text, file names, and line numbers debug info carries are dropped.  Meant for
+    /// shipping builds of studios that consider their shader interfaces sensitive; keep the
+    /// mapping file private.
+    pub obfuscation_map: Option<PathBuf>,
+    /// The secret the names `obfuscation_map` makes are derived with, which is needed whenever
+    /// it's set.  Without one, anyone could hash a list of likely names and match them up.  The
+    /// same key makes the same names, so keep it between runs to keep the mapping file short.
+    pub obfuscation_key: Option<[u8; 32]>,
+    /// If set, a module declaring any capability not listed here fails, so that e.g. a stray
+    /// `Int64` or `RayQueryKHR` is caught before it reaches a device that lacks it.  Whatever
+    /// the lists say, a module with a capability or extension `target_env` can't consume, like
+    /// `RayQueryKHR` for OpenGL or subgroup operations before SPIR-V 1.3, fails too, unless it's
+    /// listed here, which is taken to mean the target has it.
+    pub allowed_capabilities: Option<Vec<Capability>>,
+    /// Like `allowed_capabilities`, for `OpExtension` names such as `SPV_KHR_ray_query`.
+    pub allowed_extensions: Option<Vec<String>>,
+    /// If set, `///` doc comments are collected from every discovered shader and written here,
+    /// as JSON if the path ends in `.json` and as markdown otherwise.  A run of `///` lines
+    /// documents the next line of code; lines starting with `@param name` describe a parameter.
+    pub docs_output: Option<PathBuf>,
+    /// If set, the descriptor bindings of every artifact in `output_root` are written here as
+    /// JSON after each run, both per shader and grouped by set and binding.
+    pub resource_report: Option<PathBuf>,
+    /// If set, the errors and warnings of every shader compiled each run are written here as a
+    /// SARIF log, for CI systems that annotate changes with it.  Paths are as wrangler saw them,
+    /// relative to the working directory unless `search_root` is absolute.  Shaders that were
+    /// up to date aren't compiled, so their warnings only show up in the run that compiled them.
+    pub sarif_output: Option<PathBuf>,
+    /// If set, each run writes a JUnit XML report here with a test case for every discovered
+    /// shader.  Failures carry the compiler's messages; up-to-date shaders pass.
+    pub junit_output: Option<PathBuf>,
+    /// If set, each run writes everything it did here as JSON: every discovered shader with its
+    /// status, time taken, artifacts, and messages, along with totals and the `options_hash`
+    /// fingerprint the record keys on.
+    pub json_report: Option<PathBuf>,
+    /// The most descriptors of each kind a single shader may use.  A shader going over is
+    /// reported with a warning when `resource_report` is written.
+    pub descriptor_budget: Vec<(DescriptorKind, u32)>,
+    /// Device profiles every artifact is checked against after each run: JSON files like
+    /// `{"name": "mobile", "limits": {"maxPushConstantsSize": 128}}`, with any of the
+    /// `VkPhysicalDeviceLimits` on push constants, bound sets, per-stage descriptors, and compute
+    /// workgroups.  A shader going over a profile's limits is an error.
+    pub device_profiles: Vec<PathBuf>,
+    /// Platforms to build every shader for, like `desktop`, `mobile`, and `deck`, each with
+    /// defines, limits, and a target of its own.  If there are any, each run builds once per
+    /// platform, into a directory under `output_root` named after it, with its own manifest and
+    /// its own record, so a platform only recompiles what is stale for it.  The record, pack,
+    /// Rust module, and reports get the platform's name before their extension, as in
+    /// `shaders.mobile.spvpak`, and `ship_from` and `cross_compile` directories get one each.
+    /// `rust_module` itself then picks one platform's module by `cfg`, so that a binary only
+    /// embeds the artifacts of the platform it's built for.
+    /// Only the `run` functions and `Watcher` build per platform; `plan`, `clean`, and the rest
+    /// look at the shared settings.
+    pub platforms: Vec<Platform>,
+    /// If true, every artifact is optimized for performance and gets a sibling with `.debug`
+    /// appended to its name, compiled without optimization and with full debug info, for frame
+    /// capture tools.  Both are compiled from one preprocessing pass.
+    pub debug_artifacts: bool,
+    /// Directories searched, in order, for `#include <...>`, and for `#include "..."` that isn't
+    /// found beside the including file.  Includes aren't resolved by `remote_compiler`, which
+    /// has no access to these directories.
+    pub include_dirs: Vec<PathBuf>,
+    /// Shared shader libraries, by name, for `#include <name:path>`, which reads `path` from the
+    /// library's directory rather than searching `include_dirs`, like `<pbr:lighting.glsl>`
+    /// with `pbr` mapped to wherever that version of the library is checked out.  Like
+    /// `include_dirs`, they aren't consulted by `remote_compiler`.
+    pub include_packages: BTreeMap<String, PathBuf>,
+    /// Consulted for every `#include` before `include_dirs`, e.g. to serve headers generated in
+    /// memory; an `Err` falls back to the usual search.  Only includes whose resolved name is a
+    /// file on disk are tracked for rebuilds.
+    pub include_resolver: Option<Box<IncludeResolver>>,
+    /// Macros defined for every compile, as though by `#define NAME VALUE`, or just
+    /// `#define NAME` when the value is `None`.
+    pub defines: Vec<(String, Option<String>)>,
+    /// If true, modules carry debug info, so tools like RenderDoc and Nsight can show variable
+    /// names and source lines.  Not to be confused with `debug_artifacts`, which writes a debug
+    /// build alongside each optimized one.
+    pub generate_debug_info: bool,
+    /// What the modules are compiled for.  Ray tracing, task, and mesh shaders are compiled for
+    /// at least Vulkan 1.2 when a Vulkan target is chosen, since they need SPIR-V 1.4.
+    pub target_env: TargetEnv,
+    /// The SPIR-V version to emit, if not the newest one `target_env` allows.  Asking for one
+    /// too old for a shader's features makes that shader fail to compile.
+    pub spirv_version: Option<SpirvVersion>,
+    /// The name of the function each shader starts at, `main` if not set.
+    pub entry_point: Option<String>,
+    /// Overrides `entry_point` for particular kinds, e.g. `VSMain` for vertex shaders and
+    /// `PSMain` for fragment shaders.
+    pub entry_points: Vec<(ShaderKind, String)>,
+    /// If true, `.hlsl` files are searched for as well and compiled as HLSL.  The stage comes
+    /// from the extension before `.hlsl`: `vs`, `ps`, `cs`, `gs`, `hs`, or `ds`, as in
+    /// `lighting.ps.hlsl`.  Files without one are left alone.  As with glslc, the SPIR-V is
+    /// legalized for Vulkan, and a module that still copies resources around fails to compile.
+    pub hlsl: bool,
+    /// Whether to write each shader's SPIR-V assembly, which reads better in code review and
+    /// diffs than the binary.  shaderc compiles the source a second time to produce it, so it
+    /// doesn't show the changes wrangler makes to the binary afterwards: frozen specialization
+    /// constants, obfuscated names, and build metadata.  WGSL sources get no assembly and keep
+    /// their binary even under `SpirvAssembly::Instead`.
+    pub spirv_assembly: SpirvAssembly,
+    /// Languages to translate every module into as well, each paired with the directory its
+    /// files go in, laid out like `output_root`.  `sky.frag` becomes e.g. `sky.frag.metal`.  A
+    /// shader that can't be translated counts as failed, so it's tried again next run.
+    #[cfg(any(feature = "cross", feature = "wgsl-out"))]
+    pub cross_compile: Vec<(CrossTarget, PathBuf)>,
+    /// Modules linked, with `spirv-link`, from the artifacts of several shaders after each run,
+    /// for shaders split into separately compiled units that export and import functions and
+    /// variables.  A program is linked again whenever any of its units' artifacts change, and one
+    /// that fails to link counts as a failed file.
+    #[cfg(feature = "link")]
+    pub linked_programs: Vec<LinkedProgram>,
+    /// The `spirv-link` to run for `linked_programs`, if not the one on `PATH`.
+    #[cfg(feature = "link")]
+    pub spirv_link: Option<PathBuf>,
+}
+
+impl Instructions {
+    /// Hashes every option that can change what a compiled module looks like.
+    fn options_hash(&self) -> blake3::Hash {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+        hasher.update(&[self.embed_build_metadata as u8]);
+        hasher.update(format!("{:?}", self.specialization).as_bytes());
+        hasher.update(format!("{:?}", self.specialization_variants).as_bytes());
+        hasher.update(&[self.obfuscation_map.is_some() as u8]);
+        if let Some(key) = &self.obfuscation_key {
+            hasher.update(blake3::keyed_hash(key, b"obfuscation").as_bytes());
+        }
+        hasher.update(&[self.debug_artifacts as u8]);
+        hasher.update(format!("{:?}", self.defines).as_bytes());
+        hasher.update(&[self.generate_debug_info as u8]);
+        hasher.update(format!("{:?}", self.target_env).as_bytes());
+        hasher.update(format!("{:?}", self.spirv_version).as_bytes());
+        hasher.update(format!("{:?} {:?}", self.entry_point, self.entry_points).as_bytes());
+        hasher.update(format!("{:?}", self.include_dirs).as_bytes());
+        hasher.update(format!("{:?}", self.include_packages).as_bytes());
+        hasher.update(format!("{:?}", self.endianness).as_bytes());
+        hasher.update(&[self.transform.is_some() as u8]);
+        hasher.update(format!("{:?}", self.transform_id).as_bytes());
+        #[cfg(feature = "naga-glsl")]
+        hasher.update(format!("{:?}", self.glsl_compiler).as_bytes());
+        #[cfg(any(feature = "cross", feature = "wgsl-out"))]
+        hasher.update(format!("{:?}", self.cross_compile).as_bytes());
+        // Not about the module, but a shader that compiled with warnings has to be looked at
+        // again once they count as errors.
+        hasher.update(&[self.warnings_as_errors as u8]);
+        hasher.finalize()
+    }
+
+    /// The entry point shaders of `kind` are compiled with.
+    fn entry_point(&self, kind: ShaderKind) -> &str {
+        let overridden = self.entry_points.iter().find(|(k, _)| *k == kind);
+        match (overridden, &self.entry_point) {
+            (Some((_, name)), _) => name,
+            (None, Some(name)) => name,
+            (None, None) => "main",
+        }
+    }
+
+    fn timer(&self, name: &str, duration: std::time::Duration) {
+        if let Some(telemetry) = &self.telemetry {
+            telemetry.timer(name, duration);
+        }
+    }
+
+    fn counter(&self, name: &str, value: u64) {
+        if let Some(telemetry) = &self.telemetry {
+            telemetry.counter(name, value);
+        }
+    }
+
+    fn gauge(&self, name: &str, value: f64) {
+        if let Some(telemetry) = &self.telemetry {
+            telemetry.gauge(name, value);
+        }
+    }
+
+    fn report(&self, event: impl FnOnce(&dyn Reporter)) {
+        if let Some(reporter) = &self.reporter {
+            event(reporter.as_ref());
+        }
+    }
+}
+
+fn deduplicate_kinds(kinds: &[ShaderKind]) -> Vec<ShaderKind> {
+    let mut out = Vec::<ShaderKind>::new();
+    'over_kinds: for kind in kinds.iter() {
+        for added in out.iter() {
+            if *added == *kind {
+                continue 'over_kinds;
+            }
+        }
+        out.push(*kind)
+    }
+    out
+}
+
+#[derive(Clone)]
+struct CompilationCandidate {
+    location: PathBuf,
+    shader_kind: ShaderKind,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+fn kind_ext(kind: &ShaderKind) -> Result<&'static str> {
+    match kind {
+        ShaderKind::Vertex => Ok("vert"),
+        ShaderKind::Fragment => Ok("frag"),
+        ShaderKind::Compute => Ok("comp"),
+        ShaderKind::Geometry => Ok("geom"),
+        ShaderKind::TessControl => Ok("tesc"),
+        ShaderKind::TessEvaluation => Ok("tese"),
+        ShaderKind::RayGeneration => Ok("rgen"),
+        ShaderKind::Miss => Ok("rmiss"),
+        ShaderKind::ClosestHit => Ok("rchit"),
+        ShaderKind::AnyHit => Ok("rahit"),
+        ShaderKind::Intersection => Ok("rint"),
+        ShaderKind::Callable => Ok("rcall"),
+        ShaderKind::Task => Ok("task"),
+        ShaderKind::Mesh => Ok("mesh"),
+        x => Err(Error::UnsupportedKind(*x)),
+    }
+}
+
+fn kind_from_ext(ext: &str) -> Option<ShaderKind> {
+    match ext {
+        "vert" => Some(ShaderKind::Vertex),
+        "frag" => Some(ShaderKind::Fragment),
+        "comp" => Some(ShaderKind::Compute),
+        "geom" => Some(ShaderKind::Geometry),
+        "tesc" => Some(ShaderKind::TessControl),
+        "tese" => Some(ShaderKind::TessEvaluation),
+        "rgen" => Some(ShaderKind::RayGeneration),
+        "rmiss" => Some(ShaderKind::Miss),
+        "rchit" => Some(ShaderKind::ClosestHit),
+        "rahit" => Some(ShaderKind::AnyHit),
+        "rint" => Some(ShaderKind::Intersection),
+        "rcall" => Some(ShaderKind::Callable),
+        "task" => Some(ShaderKind::Task),
+        "mesh" => Some(ShaderKind::Mesh),
+        _ => None,
+    }
+}
+
+fn is_hlsl(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "hlsl")
+}
+
+/// Takes the stage of an HLSL file from the extension before `.hlsl`, as in `lighting.ps.hlsl`.
+fn hlsl_kind(path: &Path) -> Option<ShaderKind> {
+    let stage = Path::new(path.file_stem()?).extension()?;
+    match stage.to_str()? {
+        "vs" => Some(ShaderKind::Vertex),
+        "ps" => Some(ShaderKind::Fragment),
+        "cs" => Some(ShaderKind::Compute),
+        "gs" => Some(ShaderKind::Geometry),
+        "hs" => Some(ShaderKind::TessControl),
+        "ds" => Some(ShaderKind::TessEvaluation),
+        _ => None,
+    }
+}
+
+fn is_spvasm(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "spvasm")
+}
+
+/// The kind of a source whose stage is named in its file name, like HLSL, WGSL, and SPIR-V
+/// assembly ones are.  Assembly is named like GLSL, as in `blur.comp.spvasm`.
+fn staged_kind(instructions: &Instructions, path: &Path) -> Option<ShaderKind> {
+    if instructions.hlsl && is_hlsl(path) {
+        return hlsl_kind(path);
+    }
+    if is_spvasm(path) {
+        let stage = Path::new(path.file_stem()?).extension()?;
+        return kind_from_ext(stage.to_str()?);
+    }
+    #[cfg(feature = "wgsl")]
+    {
+        if front::is_wgsl(path) {
+            return front::wgsl_kind(path);
+        }
+    }
+    None
+}
+
+/// Leaves parts of `search_root` out depending on the target being built for, e.g. to skip ray
+/// tracing shaders on mobile.  Rules are checked against the `TARGET` variable cargo gives build
+/// scripts, and don't apply at all when it isn't set.
+pub struct TargetRule {
+    /// A regex matched against the target triple.
+    pub target: String,
+    /// Paths, written like `exclude`, that are only searched when building for a matching target.
+    pub include: Vec<String>,
+    /// Paths, written like `exclude`, that are left out when building for a matching target.
+    pub exclude: Vec<String>,
+}
+
+/// Collects `exclude` along with whatever the target rules leave out for the current target.
+fn effective_excludes(instructions: &Instructions) -> Result<Vec<String>> {
+    let mut excludes = instructions.exclude.clone();
+    let target = match std::env::var("TARGET") {
+        Ok(target) => target,
+        Err(_) => return Ok(excludes),
+    };
+    for rule in instructions.target_rules.iter() {
+        let re = regex::Regex::new(&rule.target)
+            .map_err(|_| Error::BadTargetPattern(rule.target.clone()))?;
+        if re.is_match(&target) {
+            excludes.extend(rule.exclude.iter().cloned());
+        } else {
+            excludes.extend(rule.include.iter().cloned());
+        }
+    }
+    Ok(excludes)
+}
+
+/// Lists every file under `search_root`, pruning excluded directories rather than descending
+/// into them.  The walk itself runs on several threads; the result is sorted.
+fn walk_search_root(instructions: &Instructions) -> Result<Vec<PathBuf>> {
+    let excludes = effective_excludes(instructions)?;
+    let mut overrides = ignore::overrides::OverrideBuilder::new(&instructions.search_root);
+    for pattern in excludes.iter() {
+        overrides
+            .add(&format!("!{}", pattern))
+            .map_err(|_| Error::BadExcludePattern(pattern.to_string()))?;
+    }
+    let overrides = overrides
+        .build()
+        .map_err(|_| Error::BadExcludePattern(excludes.join(", ")))?;
+    let found = std::sync::Mutex::new(Vec::<std::result::Result<PathBuf, ignore::Error>>::new());
+    ignore::WalkBuilder::new(&instructions.search_root)
+        .standard_filters(false)
+        .overrides(overrides)
+        .build_parallel()
+        .run(|| {
+            Box::new(|entry| {
+                let entry = entry.map(|e| (e.file_type().is_some_and(|t| t.is_file()), e));
+                match entry {
+                    Ok((true, e)) => found.lock().unwrap().push(Ok(e.into_path())),
+                    Ok((false, _)) => {}
+                    Err(e) => found.lock().unwrap().push(Err(e)),
+                }
+                ignore::WalkState::Continue
+            })
+        });
+    let mut paths = found
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    paths.sort();
+    Ok(paths)
+}
+
+fn find_shaders(instructions: &Instructions) -> Result<Vec<CompilationCandidate>> {
+    let kinds = deduplicate_kinds(&instructions.to_compile);
+    let exts = kinds.iter().map(kind_ext).collect::<Result<Vec<_>>>()?;
+    let mut shaders = Vec::<CompilationCandidate>::new();
+    for location in walk_search_root(instructions)? {
+        let ext = location.extension().and_then(|ext| ext.to_str());
+        if let Some(i) = exts.iter().position(|e| Some(*e) == ext) {
+            shaders.push(CompilationCandidate {
+                location,
+                shader_kind: kinds[i],
+            });
+        } else if let Some(kind) = staged_kind(instructions, &location) {
+            if kinds.contains(&kind) {
+                shaders.push(CompilationCandidate {
+                    location,
+                    shader_kind: kind,
+                });
+            }
+        }
+    }
+    // Keep shaders of the same kind together, in the order the kinds were asked for.
+    shaders.sort_by_key(|c| kinds.iter().position(|k| *k == c.shader_kind));
+    Ok(shaders)
+}
+
+fn check_against_record(
+    instructions: &Instructions,
+    candidates: &[CompilationCandidate],
+    record: &Record,
+) -> Result<Vec<CompilationCandidate>> {
+    let mut needs_compile = Vec::<CompilationCandidate>::new();
+    for candidate in candidates.iter() {
+        if reason(instructions, candidate, record)?.is_some() {
+            needs_compile.push(candidate.clone());
+        }
+    }
+    Ok(needs_compile)
+}
+
+/// Why `candidate` needs compiling, or `None` if it is up to date.
+fn reason(
+    instructions: &Instructions,
+    candidate: &CompilationCandidate,
+    record: &Record,
+) -> Result<Option<Reason>> {
+    if let Some(reason) = record.change(&candidate.location)? {
+        return Ok(Some(reason));
+    }
+    if outputs_missing(instructions, candidate, record)? {
+        return Ok(Some(Reason::MissingOutput));
+    }
+    Ok(None)
+}
+
+/// Whether any artifact a compile of `candidate` would write is gone, e.g. deleted by hand.
+fn outputs_missing(
+    instructions: &Instructions,
+    candidate: &CompilationCandidate,
+    record: &Record,
+) -> Result<bool> {
+    if instructions.hashed_output_names {
+        // The names depend on what was compiled, so only the record knows them.
+        let outputs = record.outputs(&candidate.location);
+        return Ok(outputs.is_empty() || outputs.iter().any(|o| !o.exists()));
+    }
+    let dest = output_path(instructions, &candidate.location, &candidate.shader_kind)?;
+    if instructions.debug_artifacts && !debug_path(&dest).exists() {
+        return Ok(true);
+    }
+    let variants = instructions.specialization_variants.iter();
+    if variants
+        .map(|(name, _)| variant_path(&dest, name))
+        .any(|path| !path.exists())
+    {
+        return Ok(true);
+    }
+    #[cfg(any(feature = "cross", feature = "wgsl-out"))]
+    {
+        let translations = cross::paths(instructions, &candidate.location, &candidate.shader_kind)?;
+        if translations.iter().any(|path| !path.exists()) {
+            return Ok(true);
+        }
+    }
+    #[cfg(feature = "wgsl")]
+    {
+        if front::is_wgsl(&candidate.location) {
+            return Ok(!dest.exists());
+        }
+    }
+    let binary = instructions.spirv_assembly != SpirvAssembly::Instead;
+    let assembly = instructions.spirv_assembly != SpirvAssembly::Never;
+    Ok((binary && !dest.exists()) || (assembly && !assembly_path(&dest).exists()))
+}
+
+/// A single compiled shader.
+pub struct CompileOutput {
+    /// The source file the module was compiled from.
+    pub location: PathBuf,
+    pub shader_kind: ShaderKind,
+    pub spirv: Vec<u32>,
+    /// The unoptimized module with debug info, if `debug_artifacts` is set.
+    pub debug_spirv: Option<Vec<u32>>,
+    /// Every file `#include`d while compiling, directly or not.
+    pub includes: Vec<PathBuf>,
+    /// The module as SPIR-V assembly, if `spirv_assembly` asks for it.
+    pub assembly: Option<String>,
+    /// What the compiler warned about.  Only shaderc reports warnings; naga's are errors or
+    /// nothing.
+    pub warnings: Vec<Diagnostic>,
+    /// What `report_dead_code` found, looked for before `obfuscation_map` renames anything.
+    dead_code: Option<spirv::DeadCode>,
+    /// The module specialized for each of `specialization_variants`, by name.
+    pub variants: Vec<(String, Vec<u32>)>,
+    /// The names `obfuscation_map` swapped, by what they were swapped for.
+    renamed: BTreeMap<String, String>,
+}
+
+/// What the note `build_metadata` makes starts with.
+const BUILD_METADATA_PREFIX: &str = "wrangler ";
+
+fn build_metadata(instructions: &Instructions, source: &str) -> String {
+    format!(
+        "{}{} options={} source={}",
+        BUILD_METADATA_PREFIX,
+        env!("CARGO_PKG_VERSION"),
+        &instructions.options_hash().to_hex()[..16],
+        &blake3::hash(source.as_bytes()).to_hex()[..16],
+    )
+}
+
+/// How hard shaderc tries to optimize a module.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+enum OptLevel {
+    #[default]
+    Zero,
+    Size,
+    Performance,
+}
+
+/// The compiler settings that vary between the modules wrangler builds, kept separate from
+/// `shaderc::CompileOptions` so they can be sent to a remote compiler.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct CompileSettings {
+    opt_level: OptLevel,
+    debug_info: bool,
+    target_env: TargetEnv,
+    spirv_version: Option<SpirvVersion>,
+    hlsl: bool,
+    defines: Vec<(String, Option<String>)>,
+    warnings_as_errors: bool,
+}
+
+/// What shaders of `kind` are compiled for.
+fn target_env(instructions: &Instructions, kind: ShaderKind) -> TargetEnv {
+    // GL_EXT_ray_tracing and GL_EXT_mesh_shader need SPIR-V 1.4, which Vulkan 1.0 doesn't
+    // allow.  The NV mesh shading extension is happy either way.
+    let needs_spirv_1_4 = matches!(
+        kind,
+        ShaderKind::RayGeneration
+            | ShaderKind::Miss
+            | ShaderKind::ClosestHit
+            | ShaderKind::AnyHit
+            | ShaderKind::Intersection
+            | ShaderKind::Callable
+            | ShaderKind::Task
+            | ShaderKind::Mesh
+    );
+    match instructions.target_env {
+        TargetEnv::Vulkan1_0 | TargetEnv::Vulkan1_1 if needs_spirv_1_4 => TargetEnv::Vulkan1_2,
+        target_env => target_env,
+    }
+}
+
+impl CompileSettings {
+    fn new(instructions: &Instructions, kind: ShaderKind) -> CompileSettings {
+        CompileSettings {
+            target_env: target_env(instructions, kind),
+            spirv_version: instructions.spirv_version,
+            debug_info: instructions.generate_debug_info,
+            defines: instructions.defines.clone(),
+            warnings_as_errors: instructions.warnings_as_errors,
+            ..Default::default()
+        }
+    }
+
+    /// Builds the options for one compile, resolving includes with `resolver` or else against
+    /// `include_dirs` and `packages`.  Every file on disk that gets included is added to
+    /// `resolved`.
+    #[cfg(feature = "shaderc")]
+    fn to_options<'a>(
+        &self,
+        include_dirs: &'a [PathBuf],
+        packages: &'a BTreeMap<String, PathBuf>,
+        resolver: Option<&'a IncludeResolver>,
+        resolved: &'a RefCell<Vec<PathBuf>>,
+    ) -> Result<shaderc::CompileOptions<'a>> {
+        let mut options = shaderc::CompileOptions::new().ok_or(Error::CompilerInit)?;
+        // For HLSL, shaderc runs spirv-opt's legalization passes on its own, whatever the
+        // optimization level, just as glslc does; `finish` checks that they did their job.
+        if self.hlsl {
+            options.set_source_language(shaderc::SourceLanguage::HLSL);
+        }
+        options.set_include_callback(move |requested, include_type, requesting, _depth| {
+            let include = match resolver.map(|r| r(requested, include_type, requesting)) {
+                Some(Ok(include)) => include,
+                _ => resolve_include(include_dirs, packages, requested, include_type, requesting)?,
+            };
+            let path = PathBuf::from(&include.resolved_name);
+            if path.is_file() {
+                resolved.borrow_mut().push(path);
+            }
+            Ok(include)
+        });
+        for (name, value) in self.defines.iter() {
+            options.add_macro_definition(name, value.as_deref());
+        }
+        options.set_optimization_level(match self.opt_level {
+            OptLevel::Zero => shaderc::OptimizationLevel::Zero,
+            OptLevel::Size => shaderc::OptimizationLevel::Size,
+            OptLevel::Performance => shaderc::OptimizationLevel::Performance,
+        });
+        if self.debug_info {
+            options.set_generate_debug_info();
+        }
+        if self.warnings_as_errors {
+            options.set_warnings_as_errors();
+        }
+        let (env, version) = match self.target_env {
+            TargetEnv::Vulkan1_0 => (shaderc::TargetEnv::Vulkan, shaderc::EnvVersion::Vulkan1_0),
+            TargetEnv::Vulkan1_1 => (shaderc::TargetEnv::Vulkan, shaderc::EnvVersion::Vulkan1_1),
+            TargetEnv::Vulkan1_2 => (shaderc::TargetEnv::Vulkan, shaderc::EnvVersion::Vulkan1_2),
+            TargetEnv::Vulkan1_3 => (shaderc::TargetEnv::Vulkan, shaderc::EnvVersion::Vulkan1_3),
+            TargetEnv::OpenGl4_5 => (shaderc::TargetEnv::OpenGL, shaderc::EnvVersion::OpenGL4_5),
+        };
+        options.set_target_env(env, version as u32);
+        if let Some(version) = self.spirv_version {
+            options.set_target_spirv(match version {
+                SpirvVersion::V1_0 => shaderc::SpirvVersion::V1_0,
+                SpirvVersion::V1_1 => shaderc::SpirvVersion::V1_1,
+                SpirvVersion::V1_2 => shaderc::SpirvVersion::V1_2,
+                SpirvVersion::V1_3 => shaderc::SpirvVersion::V1_3,
+                SpirvVersion::V1_4 => shaderc::SpirvVersion::V1_4,
+                SpirvVersion::V1_5 => shaderc::SpirvVersion::V1_5,
+                SpirvVersion::V1_6 => shaderc::SpirvVersion::V1_6,
+            });
+        }
+        Ok(options)
+    }
+}
+
+/// Finds the file an `#include` refers to.  `"quoted"` includes are looked for beside the file
+/// doing the including; if that fails, shaderc tries again as though it were `<bracketed>`, which
+/// reads `<name:path>` from the package `name` in `packages` and searches `include_dirs` in order
+/// for anything else.
+#[cfg(feature = "shaderc")]
+fn resolve_include(
+    include_dirs: &[PathBuf],
+    packages: &BTreeMap<String, PathBuf>,
+    requested: &str,
+    include_type: shaderc::IncludeType,
+    requesting: &str,
+) -> std::result::Result<shaderc::ResolvedInclude, String> {
+    let package = requested
+        .split_once(':')
+        .and_then(|(name, path)| Some((packages.get(name)?, path)));
+    let candidates: Vec<PathBuf> = match (include_type, package) {
+        (shaderc::IncludeType::Relative, _) => match Path::new(requesting).parent() {
+            Some(dir) => vec![dir.join(requested)],
+            None => vec![PathBuf::from(requested)],
+        },
+        (shaderc::IncludeType::Standard, Some((dir, path))) => vec![dir.join(path)],
+        (shaderc::IncludeType::Standard, None) => {
+            include_dirs.iter().map(|d| d.join(requested)).collect()
+        }
+    };
+    for path in candidates {
+        if let Ok(content) = fs::read_to_string(&path) {
+            return Ok(shaderc::ResolvedInclude {
+                resolved_name: path.to_string_lossy().into_owned(),
+                content,
+            });
+        }
+    }
+    Err(format!("couldn't find `{}`", requested))
+}
+
+/// Whatever turns source into SPIR-V for this run.
+enum Backend {
+    #[cfg(feature = "shaderc")]
+    Local(shaderc::Compiler),
+    #[cfg(feature = "remote")]
+    Remote(remote::Client),
+    /// naga's GLSL frontend, which leaves shaderc unloaded.
+    #[cfg(feature = "naga-glsl")]
+    Naga,
+    /// No compiler at all, without shaderc.  Only naga's WGSL frontend is left, which doesn't
+    /// go through a backend.
+    #[cfg(not(feature = "shaderc"))]
+    Unavailable,
+}
+
+impl Backend {
+    #[cfg_attr(
+        not(any(feature = "remote", feature = "naga-glsl")),
+        allow(unused_variables)
+    )]
+    fn new(instructions: &Instructions) -> Result<Backend> {
+        #[cfg(feature = "remote")]
+        {
+            if let Some(address) = &instructions.remote_compiler {
+                let key = instructions.remote_key.ok_or_else(|| {
+                    Error::Remote("remote_compiler is set without a remote_key".into())
+                })?;
+                return Ok(Backend::Remote(remote::Client::connect(address, &key)?));
+            }
+        }
+        #[cfg(feature = "naga-glsl")]
+        {
+            if instructions.glsl_compiler == GlslCompiler::Naga {
+                return Ok(Backend::Naga);
+            }
+        }
+        // If shaderc can't run on this machine, there's not much we can do here.
+        #[cfg(feature = "shaderc")]
+        return Ok(Backend::Local(shaderc::Compiler::new().unwrap()));
+        #[cfg(not(feature = "shaderc"))]
+        Ok(Backend::Unavailable)
+    }
+
+    #[cfg_attr(not(feature = "shaderc"), allow(unused_variables))]
+    fn compile(
+        &mut self,
+        source: &str,
+        kind: ShaderKind,
+        name: &str,
+        entry_point: &str,
+        settings: &CompileSettings,
+        instructions: &Instructions,
+    ) -> Result<(Vec<u32>, Vec<PathBuf>, Vec<Diagnostic>)> {
+        match self {
+            #[cfg(feature = "shaderc")]
+            Backend::Local(compiler) => {
+                let resolved = RefCell::default();
+                let options = settings.to_options(
+                    &instructions.include_dirs,
+                    &instructions.include_packages,
+                    instructions.include_resolver.as_deref(),
+                    &resolved,
+                )?;
+                let artifact =
+                    compiler.compile_into_spirv(source, kind, name, entry_point, Some(&options))?;
+                drop(options);
+                Ok((
+                    artifact.as_binary().to_vec(),
+                    resolved.into_inner(),
+                    diagnostic::parse(&artifact.get_warning_messages()),
+                ))
+            }
+            #[cfg(feature = "remote")]
+            Backend::Remote(client) => {
+                let (spirv, warnings) =
+                    client.compile(source, kind, name, entry_point, settings)?;
+                Ok((spirv, Vec::new(), diagnostic::parse(&warnings)))
+            }
+            #[cfg(feature = "naga-glsl")]
+            Backend::Naga if settings.hlsl => Err(Error::Naga(format!(
+                "{}: HLSL can only be compiled with shaderc",
+                name
+            ))),
+            #[cfg(feature = "naga-glsl")]
+            Backend::Naga => {
+                let spirv = front::compile_glsl(
+                    name,
+                    source,
+                    kind,
+                    &settings.defines,
+                    settings.debug_info,
+                )?;
+                Ok((spirv, Vec::new(), Vec::new()))
+            }
+            #[cfg(not(feature = "shaderc"))]
+            Backend::Unavailable => Err(Error::NoShaderc(name.to_owned())),
+        }
+    }
+
+    /// Compiles `source` into SPIR-V assembly.
+    #[cfg_attr(not(feature = "shaderc"), allow(unused_variables))]
+    fn compile_assembly(
+        &mut self,
+        source: &str,
+        kind: ShaderKind,
+        name: &str,
+        entry_point: &str,
+        settings: &CompileSettings,
+        instructions: &Instructions,
+    ) -> Result<String> {
+        match self {
+            #[cfg(feature = "shaderc")]
+            Backend::Local(compiler) => {
+                let resolved = RefCell::default();
+                let options = settings.to_options(
+                    &instructions.include_dirs,
+                    &instructions.include_packages,
+                    instructions.include_resolver.as_deref(),
+                    &resolved,
+                )?;
+                let artifact = compiler.compile_into_spirv_assembly(
+                    source,
+                    kind,
+                    name,
+                    entry_point,
+                    Some(&options),
+                )?;
+                Ok(artifact.as_text())
+            }
+            #[cfg(any(feature = "remote", feature = "naga-glsl", not(feature = "shaderc")))]
+            _ => Err(Error::AssemblyUnavailable),
+        }
+    }
+
+    /// Assembles the SPIR-V assembly in `source`.
+    #[cfg_attr(not(feature = "shaderc"), allow(unused_variables))]
+    fn assemble(
+        &mut self,
+        source: &str,
+        settings: &CompileSettings,
+        instructions: &Instructions,
+    ) -> Result<Vec<u32>> {
+        match self {
+            #[cfg(feature = "shaderc")]
+            Backend::Local(compiler) => {
+                let resolved = RefCell::default();
+                let options = settings.to_options(
+                    &instructions.include_dirs,
+                    &instructions.include_packages,
+                    instructions.include_resolver.as_deref(),
+                    &resolved,
+                )?;
+                let artifact = compiler.assemble(source, Some(&options))?;
+                Ok(artifact.as_binary().to_vec())
+            }
+            #[cfg(any(feature = "remote", feature = "naga-glsl", not(feature = "shaderc")))]
+            _ => Err(Error::AssemblyUnavailable),
+        }
+    }
+
+    /// Runs the preprocessor over `source` so it can be compiled more than once without
+    /// repeating that work, along with the files it included.  Remote and naga backends leave it
+    /// to the compile.
+    #[cfg_attr(not(feature = "shaderc"), allow(unused_variables))]
+    fn preprocess<'s>(
+        &mut self,
+        source: &'s str,
+        name: &str,
+        entry_point: &str,
+        settings: &CompileSettings,
+        instructions: &Instructions,
+    ) -> Result<(std::borrow::Cow<'s, str>, Vec<PathBuf>)> {
+        match self {
+            #[cfg(feature = "shaderc")]
+            Backend::Local(compiler) => {
+                let resolved = RefCell::default();
+                let options = settings.to_options(
+                    &instructions.include_dirs,
+                    &instructions.include_packages,
+                    instructions.include_resolver.as_deref(),
+                    &resolved,
+                )?;
+                let artifact = compiler.preprocess(source, name, entry_point, Some(&options))?;
+                drop(options);
+                Ok((artifact.as_text().into(), resolved.into_inner()))
+            }
+            #[cfg(feature = "remote")]
+            Backend::Remote(_) => Ok((source.into(), Vec::new())),
+            #[cfg(feature = "naga-glsl")]
+            Backend::Naga => Ok((source.into(), Vec::new())),
+            #[cfg(not(feature = "shaderc"))]
+            Backend::Unavailable => Ok((source.into(), Vec::new())),
+        }
+    }
+}
+
+/// Merges `renamed` into the mapping file at `path`.  Obfuscated names only depend on the
+/// original and the key, so entries from earlier runs stay valid while the key stays the same.
+fn extend_obfuscation_map(path: &Path, mut renamed: BTreeMap<String, String>) -> Result<()> {
+    if let Ok(existing) = fs::read_to_string(path) {
+        for line in existing.lines() {
+            if let Some((obfuscated, original)) = line.split_once('\t') {
+                renamed
+                    .entry(obfuscated.to_owned())
+                    .or_insert_with(|| original.to_owned());
+            }
+        }
+    }
+    let mut f = std::io::BufWriter::new(fs::File::create(path).context("create", path)?);
+    for (obfuscated, original) in renamed.iter() {
+        writeln!(f, "{}\t{}", obfuscated, original).context("write", path)?;
+    }
+    f.flush().context("write", path)?;
+    Ok(())
+}
+
+/// Checks the capabilities and extensions a module declares against what `target` supports,
+/// and then against the allowed lists.
+fn preflight(instructions: &Instructions, target: TargetEnv, spirv: &[u32]) -> Result<()> {
+    let unsupported = targets::unsupported(
+        target,
+        instructions.spirv_version,
+        spirv,
+        instructions.allowed_capabilities.as_deref().unwrap_or(&[]),
+        instructions.allowed_extensions.as_deref().unwrap_or(&[]),
+    );
+    if !unsupported.is_empty() {
+        return Err(Error::Unsupported(unsupported.join(", "), target));
+    }
+    let mut refused = Vec::<String>::new();
+    if let Some(allowed) = &instructions.allowed_capabilities {
+        for number in spirv::capabilities(spirv) {
+            match Capability::from_u32(number) {
+                Some(capability) if allowed.contains(&capability) => {}
+                Some(capability) => refused.push(format!("capability {:?}", capability)),
+                None => refused.push(format!("unknown capability {}", number)),
+            }
+        }
+    }
+    if let Some(allowed) = &instructions.allowed_extensions {
+        for extension in spirv::extensions(spirv) {
+            if !allowed.contains(&extension) {
+                refused.push(format!("extension {}", extension));
+            }
+        }
+    }
+    if refused.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::NotAllowed(refused.join(", ")))
+    }
+}
+
+/// Compiles `contents` as the shader at `location`, with hints attached to what the compiler
+/// has to say about GLSL.
+fn compile_contents(
+    backend: &mut Backend,
+    instructions: &Instructions,
+    location: &Path,
+    contents: &str,
+    shader_kind: ShaderKind,
+) -> Result<CompileOutput> {
+    let mut result = compile_unhinted(backend, instructions, location, contents, shader_kind);
+    let glsl = !is_hlsl(location)
+        && !is_spvasm(location)
+        && location.extension().and_then(|ext| ext.to_str()) != Some("wgsl");
+    if let (true, Ok(ext)) = (glsl, kind_ext(&shader_kind)) {
+        match &mut result {
+            Ok(output) => diagnostic::add_hints(&mut output.warnings, contents, ext),
+            Err(Error::Compilation(diagnostics)) => {
+                diagnostic::add_hints(diagnostics, contents, ext)
+            }
+            Err(_) => {}
+        }
+    }
+    result
+}
+
+fn compile_unhinted(
+    backend: &mut Backend,
+    instructions: &Instructions,
+    location: &Path,
+    contents: &str,
+    shader_kind: ShaderKind,
+) -> Result<CompileOutput> {
+    let transformed;
+    let contents = match &instructions.transform {
+        Some(transform) => {
+            transformed = transform(location, shader_kind, contents);
+            transformed.as_str()
+        }
+        None => contents,
+    };
+    let name = location.to_str().unwrap();
+    let dead_code = RefCell::new(None);
+    let variants = RefCell::new(Vec::new());
+    let renamed = RefCell::new(BTreeMap::new());
+    // What's done to every module once it's specialized.
+    let polish = |mut spirv: Vec<u32>| -> Result<Vec<u32>> {
+        if instructions.obfuscation_map.is_some() {
+            let key = instructions
+                .obfuscation_key
+                .ok_or(Error::NoObfuscationKey)?;
+            let mut renamed = renamed.borrow_mut();
+            spirv = spirv::rename_identifiers(&spirv, |name| {
+                let hash = blake3::keyed_hash(&key, name.as_bytes());
+                let obfuscated = format!("_{}", &hash.to_hex()[..12]);
+                renamed.insert(obfuscated.clone(), name.to_owned());
+                obfuscated
+            })
+            .map_err(Error::BadModule)?;
+        }
+        if instructions.embed_build_metadata {
+            spirv::insert_note(&mut spirv, &build_metadata(instructions, contents));
+        }
+        Ok(spirv)
+    };
+    let target = target_env(instructions, shader_kind);
+    // `main` is false for the debug sibling, which would only report the same things again and
+    // has no variants.
+    let finish = |mut spirv: Vec<u32>, main: bool| -> Result<Vec<u32>> {
+        if is_hlsl(location) {
+            let locals = spirv::opaque_locals(&spirv);
+            if !locals.is_empty() {
+                return Err(Error::NotLegalized(locals.join(", ")));
+            }
+        }
+        preflight(instructions, target, &spirv)?;
+        if main {
+            for (name, values) in instructions.specialization_variants.iter() {
+                let variant = spirv::freeze_spec_constants(&spirv, values)
+                    .map_err(|e| Error::Specialization(format!("variant `{}`: {}", name, e)))?;
+                variants.borrow_mut().push((name.clone(), polish(variant)?));
+            }
+        }
+        if let Some(values) = &instructions.specialization {
+            spirv = spirv::freeze_spec_constants(&spirv, values).map_err(Error::Specialization)?;
+        }
+        if main && instructions.report_dead_code {
+            *dead_code.borrow_mut() = Some(spirv::find_dead_code(&spirv));
+        }
+        polish(spirv)
+    };
+    let entry_point = instructions.entry_point(shader_kind);
+    let mut settings = CompileSettings::new(instructions, shader_kind);
+    settings.hlsl = is_hlsl(location);
+    pragma::apply(location, contents, &mut settings)?;
+    if is_spvasm(location) {
+        let spirv = backend.assemble(contents, &settings, instructions)?;
+        let debug_spirv = if instructions.debug_artifacts {
+            Some(finish(spirv.clone(), false)?)
+        } else {
+            None
+        };
+        return Ok(CompileOutput {
+            location: location.to_owned(),
+            shader_kind,
+            spirv: finish(spirv, true)?,
+            debug_spirv,
+            includes: Vec::new(),
+            assembly: (instructions.spirv_assembly != SpirvAssembly::Never)
+                .then(|| contents.to_owned()),
+            warnings: Vec::new(),
+            dead_code: dead_code.take(),
+            variants: variants.take(),
+            renamed: renamed.take(),
+        });
+    }
+    #[cfg(feature = "wgsl")]
+    {
+        if front::is_wgsl(location) {
+            let compile = |debug_info| {
+                front::compile_wgsl(location, contents, shader_kind, entry_point, debug_info)
+            };
+            let debug_spirv = if instructions.debug_artifacts {
+                Some(finish(compile(true)?, false)?)
+            } else {
+                None
+            };
+            return Ok(CompileOutput {
+                location: location.to_owned(),
+                shader_kind,
+                spirv: finish(compile(settings.debug_info)?, true)?,
+                debug_spirv,
+                includes: Vec::new(),
+                assembly: None,
+                warnings: Vec::new(),
+                dead_code: dead_code.take(),
+                variants: variants.take(),
+                renamed: renamed.take(),
+            });
+        }
+    }
+    let assembly = if instructions.spirv_assembly == SpirvAssembly::Never {
+        None
+    } else {
+        let mut settings = settings.clone();
+        if instructions.debug_artifacts {
+            settings.opt_level = OptLevel::Performance;
+        }
+        Some(backend.compile_assembly(
+            contents,
+            shader_kind,
+            name,
+            entry_point,
+            &settings,
+            instructions,
+        )?)
+    };
+    if !instructions.debug_artifacts {
+        let (spirv, includes, warnings) = backend.compile(
+            contents,
+            shader_kind,
+            name,
+            entry_point,
+            &settings,
+            instructions,
+        )?;
+        return Ok(CompileOutput {
+            location: location.to_owned(),
+            shader_kind,
+            spirv: finish(spirv, true)?,
+            debug_spirv: None,
+            includes: dedup_includes(includes),
+            assembly,
+            warnings,
+            dead_code: dead_code.take(),
+            variants: variants.take(),
+            renamed: renamed.take(),
+        });
+    }
+    let (expanded, includes) =
+        backend.preprocess(contents, name, entry_point, &settings, instructions)?;
+    let optimized = CompileSettings {
+        opt_level: OptLevel::Performance,
+        ..settings.clone()
+    };
+    let debug = CompileSettings {
+        opt_level: OptLevel::Zero,
+        debug_info: true,
+        ..settings
+    };
+    // The debug build sees the same source, so its warnings would only repeat these.
+    let (spirv, _, warnings) = backend.compile(
+        &expanded,
+        shader_kind,
+        name,
+        entry_point,
+        &optimized,
+        instructions,
+    )?;
+    let (debug_spirv, _, _) = backend.compile(
+        &expanded,
+        shader_kind,
+        name,
+        entry_point,
+        &debug,
+        instructions,
+    )?;
+    Ok(CompileOutput {
+        location: location.to_owned(),
+        shader_kind,
+        spirv: finish(spirv, true)?,
+        debug_spirv: Some(finish(debug_spirv, false)?),
+        includes: dedup_includes(includes),
+        assembly,
+        warnings,
+        dead_code: dead_code.take(),
+        variants: variants.take(),
+        renamed: renamed.take(),
+    })
+}
+
+fn dedup_includes(mut includes: Vec<PathBuf>) -> Vec<PathBuf> {
+    includes.sort();
+    includes.dedup();
+    includes
+}
+
+fn compile_candidate(
+    backend: &mut Backend,
+    instructions: &Instructions,
+    candidate: &CompilationCandidate,
+) -> Result<CompileOutput> {
+    let CompilationCandidate {
+        location,
+        shader_kind,
+    } = candidate;
+    let mut f = fs::File::open(location).context("open", location)?;
+    if let Some(threshold) = instructions.mmap_threshold {
+        if f.metadata().context("read metadata of", location)?.len() >= threshold {
+            // Safety: the mapping only lives for this compile.  A source truncated while it is
+            // mapped can take the process down with SIGBUS, which is why mapping is opt-in.
+            let map = unsafe { memmap2::Mmap::map(&f).context("map", location)? };
+            let contents = std::str::from_utf8(&map)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+                .context("read", location)?;
+            return compile_contents(backend, instructions, location, contents, *shader_kind);
+        }
+    }
+    let mut contents = String::new();
+    f.read_to_string(&mut contents).context("read", location)?;
+    compile_contents(backend, instructions, location, &contents, *shader_kind)
+}
+
+/// Compiles shaders one at a time as it is advanced, so a consumer that stops early doesn't pay
+/// for the rest.  Returned by `compile_iter`.
+pub struct Compilation<'a> {
+    instructions: &'a Instructions,
+    backend: Option<Backend>,
+    pending: std::vec::IntoIter<CompilationCandidate>,
+}
+
+impl<'a> Compilation<'a> {
+    /// Reuses `backend` if there is one, so several runs can share a compiler.
+    fn new(
+        instructions: &'a Instructions,
+        mut to_compile: Vec<CompilationCandidate>,
+        backend: Option<Backend>,
+    ) -> Result<Compilation<'a>> {
+        if let Some(priority) = &instructions.priority {
+            to_compile.sort_by_key(|c| std::cmp::Reverse(priority(&c.location, c.shader_kind)));
+        }
+        // Leave shaderc unloaded if we have no use for it
+        let backend = match backend {
+            None if !to_compile.is_empty() => Some(Backend::new(instructions)?),
+            backend => backend,
+        };
+        Ok(Compilation {
+            instructions,
+            backend,
+            pending: to_compile.into_iter(),
+        })
+    }
+}
+
+impl Compilation<'_> {
+    /// Compiles the next shader, handing it back along with the result.
+    fn next_compile(&mut self) -> Option<(CompilationCandidate, Result<CompileOutput>)> {
+        let candidate = self.pending.next()?;
+        let backend = self.backend.as_mut()?;
+        self.instructions
+            .report(|r| r.on_compile_start(&candidate.location));
+        let timer = Instant::now();
+        let result = {
+            let _span = span!("compile", path = candidate.location);
+            compile_candidate(backend, self.instructions, &candidate)
+        };
+        self.instructions.timer("wrangler.compile", timer.elapsed());
+        Some((candidate, result))
+    }
+}
+
+impl Iterator for Compilation<'_> {
+    type Item = Result<CompileOutput>;
+
+    fn next(&mut self) -> Option<Result<CompileOutput>> {
+        let (candidate, result) = self.next_compile()?;
+        let status = match result {
+            Ok(_) => FileStatus::Compiled,
+            Err(_) => FileStatus::Failed,
+        };
+        self.instructions
+            .report(|r| r.on_compile_finish(&candidate.location, status));
+        Some(result)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.pending.size_hint()
+    }
+}
+
+/// Finds every shader that is new, has changed since it was last recorded, or is missing an
+/// artifact, and returns an iterator that compiles them lazily.  Nothing is written and the
+/// record is left untouched.
+pub fn compile_iter(instructions: &Instructions) -> Result<Compilation<'_>> {
+    let compile_candidates = find_shaders(instructions)?;
+    let record = Record::try_load(instructions)?;
+    let to_compile = check_against_record(instructions, &compile_candidates, &record)?;
+    Compilation::new(instructions, to_compile, None)
+}
+
+/// Why `plan` expects a shader to be compiled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reason {
+    /// It has never been compiled, or not with the current options.
+    New,
+    /// It has changed since it was last compiled.
+    Modified,
+    /// A file it includes has changed since it was last compiled.
+    IncludeModified,
+    /// One of its artifacts is gone.
+    MissingOutput,
+}
+
+/// A shader the next run would compile, as listed by `plan`.
+#[derive(Clone, Debug)]
+pub struct Pending {
+    pub location: PathBuf,
+    pub shader_kind: ShaderKind,
+    pub reason: Reason,
+}
+
+/// Lists the shaders the next run would compile and why, in the order it would compile them,
+/// without compiling or writing anything.
+pub fn plan(instructions: &Instructions) -> Result<Vec<Pending>> {
+    let record = Record::try_load(instructions)?;
+    let mut pending = Vec::new();
+    let candidates = find_shaders(instructions)?;
+    check_collisions(instructions, &candidates)?;
+    for candidate in candidates {
+        if let Some(reason) = reason(instructions, &candidate, &record)? {
+            pending.push(Pending {
+                location: candidate.location,
+                shader_kind: candidate.shader_kind,
+                reason,
+            });
+        }
+    }
+    if let Some(priority) = &instructions.priority {
+        pending.sort_by_key(|p| std::cmp::Reverse(priority(&p.location, p.shader_kind)));
+    }
+    Ok(pending)
+}
+
+/// The kind of the shader at `location`, going by its extension.
+fn kind_of(instructions: &Instructions, location: &Path) -> Result<ShaderKind> {
+    let shader_kind = location
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(kind_from_ext)
+        .or_else(|| staged_kind(instructions, location));
+    shader_kind.ok_or_else(|| Error::UnrecognizedExtension(location.to_owned()))
+}
+
+/// Compiles one file and hands back the module, without searching `search_root`, consulting the
+/// record, or writing anything.  The kind is taken from the file's extension.  This is meant for
+/// editor integrations that want feedback on a single file as quickly as possible.
+pub fn compile_file(path: impl AsRef<Path>, instructions: &Instructions) -> Result<CompileOutput> {
+    let location = path.as_ref();
+    let candidate = CompilationCandidate {
+        location: location.to_owned(),
+        shader_kind: kind_of(instructions, location)?,
+    };
+    compile_candidate(&mut Backend::new(instructions)?, instructions, &candidate)
+}
+
+/// Compiles `source` as though it had been read from a file called `name`, e.g. an editor buffer
+/// that hasn't been saved yet.  Compilation goes through exactly the same steps as in `run`, and
+/// a failure comes back as `Error::Compilation` carrying the compiler's messages.
+pub fn compile_source(
+    name: &str,
+    source: &str,
+    shader_kind: ShaderKind,
+    instructions: &Instructions,
+) -> Result<CompileOutput> {
+    let mut backend = Backend::new(instructions)?;
+    compile_contents(
+        &mut backend,
+        instructions,
+        Path::new(name),
+        source,
+        shader_kind,
+    )
+}
+
+fn setup_files(instructions: &Instructions) -> Result<()> {
+    if !instructions.output_root.exists() {
+        fs::create_dir_all(&instructions.output_root)
+            .context("create directory", &instructions.output_root)?;
+    }
+    if !instructions.search_root.exists() {
+        fs::create_dir_all(&instructions.search_root)
+            .context("create directory", &instructions.search_root)?;
+    }
+    Ok(())
+}
+
+/// What artifacts are named unless `output_name` says otherwise.
+const DEFAULT_OUTPUT_NAME: &str = "{stem}.spv_{kind}";
+
+/// Where a file derived from the shader at `location` goes, relative to the root it is written
+/// under: in the directory the rename policy picks, named by filling in `template`.
+fn place(
+    instructions: &Instructions,
+    location: &Path,
+    kind: &ShaderKind,
+    template: &str,
+) -> Result<PathBuf> {
+    let tail = location.strip_prefix(&instructions.search_root).unwrap();
+    let stem = tail.file_stem().unwrap().to_string_lossy();
+    let name = tail.file_name().unwrap().to_string_lossy();
+    let (dir, stem, name) = match &instructions.rename_policy {
+        RenamePolicy::MirrorTree => (tail.parent().unwrap_or(Path::new("")), stem, name),
+        RenamePolicy::Flatten => (Path::new(""), stem, name),
+        RenamePolicy::HashedNames => {
+            // Hashed with forward slashes, so names are the same whichever OS builds them.
+            let key = tail.to_string_lossy().replace('\\', "/");
+            let hash = blake3::hash(key.as_bytes()).to_hex()[..16].to_owned();
+            (Path::new(""), hash.clone().into(), hash.into())
+        }
+        RenamePolicy::Custom(rename) => return Ok(rename(tail, *kind)),
+    };
+    let file = template
+        .replace("{stem}", &stem)
+        .replace("{name}", &name)
+        .replace("{kind}", kind_ext(kind)?);
+    Ok(dir.join(file))
+}
+
+/// Where the artifact of the shader at `location` goes, relative to `output_root`.
+fn artifact_tail(
+    instructions: &Instructions,
+    location: &Path,
+    kind: &ShaderKind,
+) -> Result<PathBuf> {
+    let template = instructions
+        .output_name
+        .as_deref()
+        .unwrap_or(DEFAULT_OUTPUT_NAME);
+    place(instructions, location, kind, template)
+}
+
+/// Makes sure no two shaders would be written to the same artifact, which flat layouts, naming
+/// templates without `{kind}`, and custom policies can all bring about.
+fn check_collisions(
+    instructions: &Instructions,
+    candidates: &[CompilationCandidate],
+) -> Result<()> {
+    let mut claimed = HashMap::<PathBuf, &Path>::new();
+    for candidate in candidates.iter() {
+        let output = output_path(instructions, &candidate.location, &candidate.shader_kind)?;
+        if let Some(first) = claimed.insert(output.clone(), &candidate.location) {
+            return Err(Error::OutputCollision {
+                first: first.to_owned(),
+                second: candidate.location.clone(),
+                output,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn output_path(instructions: &Instructions, location: &Path, kind: &ShaderKind) -> Result<PathBuf> {
+    Ok(instructions
+        .output_root
+        .join(artifact_tail(instructions, location, kind)?))
+}
+
+/// Where the debug sibling of the artifact at `artifact` goes.
+fn debug_path(artifact: &Path) -> PathBuf {
+    let mut path = artifact.to_owned().into_os_string();
+    path.push(".debug");
+    path.into()
+}
+
+/// Where the variant of the artifact at `artifact` named `name` goes.
+fn variant_path(artifact: &Path, name: &str) -> PathBuf {
+    let mut path = artifact.to_owned().into_os_string();
+    path.push(".");
+    path.push(name);
+    path.into()
+}
+
+/// Where the assembly of the artifact at `artifact` goes.
+fn assembly_path(artifact: &Path) -> PathBuf {
+    let mut path = artifact.to_owned().into_os_string();
+    path.push(".spvasm");
+    path.into()
+}
+
+/// Writes `out`, its assembly, its debug sibling, and its variants, whichever it has, and returns
+/// the paths written with the main artifact first.
+fn write_output(instructions: &Instructions, out: &CompileOutput) -> Result<Vec<PathBuf>> {
+    let binary = instructions.spirv_assembly != SpirvAssembly::Instead || out.assembly.is_none();
+    let module = module_bytes(instructions, &out.spirv);
+    let dest = if instructions.hashed_output_names {
+        let contents = match &out.assembly {
+            Some(assembly) if !binary => assembly.as_bytes(),
+            _ => &module[..],
+        };
+        let tail = artifact_tail(instructions, &out.location, &out.shader_kind)?;
+        instructions
+            .output_root
+            .join(hashed::insert_hash(&tail, contents))
+    } else {
+        output_path(instructions, &out.location, &out.shader_kind)?
+    };
+    if let Some(dir) = dest.parent() {
+        std::fs::create_dir_all(dir).context("create directory", dir)?;
+    }
+    let mut written = Vec::new();
+    if let Some(assembly) = &out.assembly {
+        let path = assembly_path(&dest);
+        fs::write(&path, assembly).context("write", &path)?;
+        written.push(path);
+    }
+    if binary {
+        write_module(instructions, &dest, &module)?;
+        written.insert(0, dest.clone());
+    }
+    if let Some(spirv) = &out.debug_spirv {
+        let debug = debug_path(&dest);
+        write_module(instructions, &debug, &module_bytes(instructions, spirv))?;
+        written.push(debug);
+    }
+    for (name, spirv) in out.variants.iter() {
+        let variant = variant_path(&dest, name);
+        write_module(instructions, &variant, &module_bytes(instructions, spirv))?;
+        written.push(variant);
+    }
+    Ok(written)
+}
+
+/// `spirv` as it is written to disk, in the byte order `endianness` asks for.
+fn module_bytes(instructions: &Instructions, spirv: &[u32]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(spirv.len() * 4);
+    for word in spirv.iter() {
+        bytes.extend_from_slice(&match instructions.endianness {
+            Endianness::Native => word.to_ne_bytes(),
+            Endianness::Little => word.to_le_bytes(),
+            Endianness::Big => word.to_be_bytes(),
+        });
+    }
+    bytes
+}
+
+fn write_module(instructions: &Instructions, dest: &Path, bytes: &[u8]) -> Result<()> {
+    let mut f = fs::File::create(dest).context("create", dest)?;
+    f.write_all(bytes).context("write", dest)?;
+    if instructions.sync == SyncPolicy::EachFile {
+        f.sync_all().context("sync", dest)?;
+    }
+    Ok(())
+}
+
+fn sync_outputs(written: &[PathBuf]) -> Result<()> {
+    for path in written.iter() {
+        fs::File::open(path)
+            .and_then(|f| f.sync_all())
+            .context("sync", path)?;
+    }
+    Ok(())
+}
+
+fn post_process(command: &str, artifact: &Path) -> Result<()> {
+    let path = artifact.to_str().unwrap();
+    let mut args = command
+        .split_whitespace()
+        .map(|arg| arg.replace("{spirv}", path));
+    let program = match args.next() {
+        Some(program) => program,
+        None => return Ok(()),
+    };
+    let output = std::process::Command::new(&program)
+        .args(args)
+        .output()
+        .context("run", Path::new(&program))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    Err(Error::PostProcess {
+        path: artifact.to_owned(),
+        status: output.status.code(),
+        output: text,
+    })
+}
+
+fn warn(message: impl std::fmt::Display) {
+    eprintln!("warning: {}", message);
+}
+
+/// Prints an error that isn't stopping the run.  With the `pretty` feature, compile errors come
+/// with the source lines they point at.
+fn print_failure(e: &Error) {
+    #[cfg(feature = "pretty")]
+    {
+        if let Error::File(failure) = e {
+            if let Error::Compilation(diagnostics) = &failure.source {
+                warn(format_args!(
+                    "`{}` failed to compile",
+                    failure.path.display()
+                ));
+                diagnostic::print_pretty(diagnostics);
+                return;
+            }
+        }
+    }
+    warn(e);
+}
+
+fn report_dead_code(out: &CompileOutput) {
+    let dead = match &out.dead_code {
+        Some(dead) => dead,
+        None => return,
+    };
+    let location = out.location.display();
+    for function in dead.functions.iter() {
+        warn(format_args!(
+            "{}: function `{}` is never called",
+            location, function
+        ));
+    }
+    for input in dead.inputs.iter() {
+        warn(format_args!(
+            "{}: input `{}` is never read",
+            location, input
+        ));
+    }
+    for output in dead.outputs.iter() {
+        warn(format_args!(
+            "{}: output `{}` is never written",
+            location, output
+        ));
+    }
+}
+
+/// Does everything `run` does for one set of instructions, collecting compile errors into
+/// `errors` rather than returning them.  Hands back the backend so the next set can reuse it.
+fn run_one(
+    instructions: &Instructions,
+    backend: Option<Backend>,
+    report: &mut CompilationReport,
+) -> Result<Option<Backend>> {
+    let started = SystemTime::now();
+    let timer = Instant::now();
+    let failed_before = report.errors.len();
+    let files_before = report.files.len();
+    let churn_before = report.churn.len();
+    let mut summary = history::RunSummary {
+        id: 0,
+        started,
+        options_hash: instructions.options_hash().to_hex().to_string(),
+        discovered: 0,
+        compiled: 0,
+        failed: 0,
+        duration: Default::default(),
+    };
+    let _lock = record::lock(instructions)?;
+    let backend = build(instructions, backend, report, &mut summary)?;
+    instructions.report(|r| r.on_run_finish());
+    if let Some(path) = &instructions.resource_report {
+        resources::write(instructions, path)?;
+    }
+    if !instructions.device_profiles.is_empty() {
+        resources::check_profiles(instructions, &mut report.errors)?;
+    }
+    if let Some(path) = &instructions.sarif_output {
+        sarif::write(path, &report.files[files_before..])?;
+    }
+    if let Some(path) = &instructions.junit_output {
+        junit::write(path, &report.files[files_before..])?;
+    }
+    summary.failed = report.errors.len() - failed_before;
+    summary.duration = timer.elapsed();
+    if let Some(path) = &instructions.json_report {
+        report::write_json(
+            path,
+            &report.files[files_before..],
+            &report.churn[churn_before..],
+            &summary.options_hash,
+            summary.duration,
+        )?;
+    }
+    instructions.counter("wrangler.compiled", summary.compiled as u64);
+    instructions.counter("wrangler.failed", summary.failed as u64);
+    instructions.timer("wrangler.run", summary.duration);
+    if instructions.keep_history {
+        history::append(instructions, summary)?;
+    }
+    Ok(backend)
+}
+
+/// Does what comes after writing a shader's artifacts: translating it and running the
+/// post-process command.  Returns the paths of any more files it wrote.
+#[cfg_attr(
+    not(any(feature = "cross", feature = "wgsl-out")),
+    allow(unused_variables, unused_mut)
+)]
+fn after_write(
+    instructions: &Instructions,
+    output: &CompileOutput,
+    artifact: &Path,
+) -> Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+    #[cfg(any(feature = "cross", feature = "wgsl-out"))]
+    written.extend(cross::write(instructions, output)?);
+    if let Some(command) = &instructions.post_process_command {
+        post_process(command, artifact)?;
+    }
+    Ok(written)
+}
+
+fn build(
+    instructions: &Instructions,
+    backend: Option<Backend>,
+    report: &mut CompilationReport,
+    summary: &mut history::RunSummary,
+) -> Result<Option<Backend>> {
+    setup_files(instructions)?;
+    let timer = Instant::now();
+    let compile_candidates = {
+        let _span = span!("discovery", root = instructions.search_root);
+        find_shaders(instructions)?
+    };
+    instructions.timer("wrangler.discovery", timer.elapsed());
+    summary.discovered = compile_candidates.len();
+    instructions.report(|r| r.on_discovered(summary.discovered));
+    naming::check(instructions, &compile_candidates, &mut report.errors)?;
+    check_collisions(instructions, &compile_candidates)?;
+    if let Some(path) = &instructions.docs_output {
+        docs::write(path, &compile_candidates)?;
+    }
+    let mut record = {
+        let _span = span!("record_load", path = instructions.record_path);
+        Record::try_load(instructions)?
+    };
+    if instructions.prune_orphans {
+        prune(&mut record)?;
+    }
+    #[cfg(feature = "ship")]
+    {
+        if let Some(dir) = &instructions.ship_from {
+            ship::ship(instructions, dir, &compile_candidates, &mut record, report)?;
+            record.write()?;
+            #[cfg(feature = "link")]
+            link::link_all(
+                instructions,
+                &compile_candidates,
+                &record,
+                &mut report.errors,
+            )?;
+            report.churn.extend(write_aggregates(
+                instructions,
+                &compile_candidates,
+                &record,
+            )?);
+            return Ok(backend);
+        }
+    }
+    let to_compile = check_against_record(instructions, &compile_candidates, &record)?;
+    let pending: HashSet<_> = to_compile.iter().map(|c| &c.location).collect();
+    for candidate in compile_candidates.iter() {
+        if !pending.contains(&candidate.location) {
+            report.files.push(FileReport {
+                source: candidate.location.clone(),
+                kind: candidate.shader_kind,
+                outputs: record.outputs(&candidate.location),
+                status: FileStatus::UpToDate,
+                duration: Default::default(),
+                warnings: Vec::new(),
+                diagnostics: Vec::new(),
+            });
+        }
+    }
+    instructions.gauge("wrangler.discovered", summary.discovered as f64);
+    instructions.gauge("wrangler.pending", to_compile.len() as f64);
+    instructions.report(|r| r.on_pending(to_compile.len()));
+    // GTFO now so we don't waste time loading shaderc if we have no use for it
+    if to_compile.is_empty() {
+        #[cfg(feature = "link")]
+        link::link_all(
+            instructions,
+            &compile_candidates,
+            &record,
+            &mut report.errors,
+        )?;
+        report.churn.extend(write_aggregates(
+            instructions,
+            &compile_candidates,
+            &record,
+        )?);
+        return Ok(backend);
+    }
+    let mut compilation = Compilation::new(instructions, to_compile, backend)?;
+    let mut written = Vec::<PathBuf>::new();
+    let mut renamed = BTreeMap::new();
+    loop {
+        let timer = Instant::now();
+        let (candidate, result) = match compilation.next_compile() {
+            Some(next) => next,
+            None => break,
+        };
+        let start = written.len();
+        let mut warnings = Vec::new();
+        let result = match result {
+            Ok(output) => {
+                warnings = output.warnings.clone();
+                let paths = write_output(instructions, &output)?;
+                let dest = paths[0].clone();
+                written.extend(paths);
+                after_write(instructions, &output, &dest).map(|paths| {
+                    written.extend(paths);
+                    output
+                })
+            }
+            Err(e) => Err(e),
+        };
+        let mut file = FileReport {
+            source: candidate.location,
+            kind: candidate.shader_kind,
+            outputs: written[start..].to_vec(),
+            status: FileStatus::Failed,
+            duration: Default::default(),
+            warnings,
+            diagnostics: Vec::new(),
+        };
+        match result {
+            Ok(output) => {
+                let previous = record.outputs(&output.location);
+                record.log(&output.location, &output.includes, &file.outputs)?;
+                if instructions.hashed_output_names {
+                    remove_stale(&previous, &file.outputs)?;
+                }
+                summary.compiled += 1;
+                if instructions.report_dead_code {
+                    report_dead_code(&output);
+                }
+                renamed.extend(output.renamed);
+                file.status = FileStatus::Compiled;
+            }
+            Err(e) => {
+                event!(warn, "`{}` failed: {}", file.source.display(), e);
+                file.diagnostics = match &e {
+                    Error::Compilation(diagnostics) => diagnostics.clone(),
+                    e => vec![Diagnostic::error(Some(file.source.clone()), e.to_string())],
+                };
+                report.errors.push(Error::File(Box::new(FileError {
+                    path: file.source.clone(),
+                    kind: file.kind,
+                    source: e,
+                })));
+            }
+        }
+        file.duration = timer.elapsed();
+        instructions.report(|r| r.on_compile_finish(&file.source, file.status));
+        report.files.push(file);
+    }
+    if instructions.sync == SyncPolicy::EndOfRun {
+        sync_outputs(&written)?;
+    }
+    if let Some(path) = &instructions.obfuscation_map {
+        extend_obfuscation_map(path, renamed)?;
+    }
+    {
+        let _span = span!("record_write", path = instructions.record_path);
+        record.write()?;
+    }
+    #[cfg(feature = "link")]
+    link::link_all(
+        instructions,
+        &compile_candidates,
+        &record,
+        &mut report.errors,
+    )?;
+    report.churn.extend(write_aggregates(
+        instructions,
+        &compile_candidates,
+        &record,
+    )?);
+    if instructions.report_duplicates {
+        report_duplicates(instructions)?;
+    }
+    Ok(compilation.backend)
+}
+
+/// Deletes the artifacts in `previous` that a new compile didn't write again.
+fn remove_stale(previous: &[PathBuf], written: &[PathBuf]) -> Result<()> {
+    for path in previous.iter().filter(|p| !written.contains(p)) {
+        match fs::remove_file(path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).context("remove", path),
+        }
+    }
+    Ok(())
+}
+
+/// Rewrites the manifest of hashed names from the record, for every shader that has been
+/// compiled at some point.
+fn write_hashed_names(
+    instructions: &Instructions,
+    candidates: &[CompilationCandidate],
+    record: &Record,
+) -> Result<()> {
+    let mut names = BTreeMap::new();
+    for candidate in candidates.iter() {
+        let plain = artifact_tail(instructions, &candidate.location, &candidate.shader_kind)?;
+        if let Some(artifact) = record.outputs(&candidate.location).first() {
+            let hashed = artifact.strip_prefix(&instructions.output_root).unwrap();
+            names.insert(hashed::key(&plain), hashed::key(hashed));
+        }
+    }
+    hashed::write(&instructions.output_root, &names)
+}
+
+/// Writes whichever of the files covering every artifact at once, the manifests, the pack, and
+/// the Rust module, are asked for.  Returns how the manifest changed, if one was written.
+fn write_aggregates(
+    instructions: &Instructions,
+    candidates: &[CompilationCandidate],
+    record: &Record,
+) -> Result<Option<Churn>> {
+    if instructions.hashed_output_names {
+        write_hashed_names(instructions, candidates, record)?;
+    }
+    // The pack goes first so the manifest can say how each module in it was compressed.
+    let codecs = match &instructions.pack_output {
+        Some(path) => pack::write(instructions, candidates, record, path)?,
+        None => BTreeMap::new(),
+    };
+    let churn = match instructions.write_manifest {
+        true => Some(manifest::write(instructions, candidates, record, &codecs)?),
+        false => None,
+    };
+    if let Some(path) = &instructions.rust_module {
+        codegen::write(instructions, candidates, record, path)?;
+    }
+    Ok(churn)
+}
+
+/// Where the artifact of `candidate` is now, which with `hashed_output_names` is wherever the
+/// manifest in `names` says.
+fn current_artifact(
+    instructions: &Instructions,
+    names: &BTreeMap<String, String>,
+    candidate: &CompilationCandidate,
+) -> Result<PathBuf> {
+    let tail = artifact_tail(instructions, &candidate.location, &candidate.shader_kind)?;
+    match names.get(&hashed::key(&tail)) {
+        Some(hashed) if instructions.hashed_output_names => {
+            Ok(instructions.output_root.join(hashed))
+        }
+        _ => Ok(instructions.output_root.join(tail)),
+    }
+}
+
+/// Deletes the artifacts of every recorded shader whose source is gone and drops it from the
+/// record, returning the paths deleted.
+fn prune(record: &mut Record) -> Result<Vec<PathBuf>> {
+    let mut deleted = Vec::new();
+    let orphans = record.orphans();
+    for shader in orphans.iter() {
+        for output in record.forget(shader) {
+            match fs::remove_file(&output) {
+                Ok(()) => deleted.push(output),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e).context("remove", &output),
+            }
+        }
+    }
+    if !orphans.is_empty() {
+        record.write()?;
+    }
+    Ok(deleted)
+}
+
+/// Deletes the artifacts left behind by shaders whose sources have been removed since they were
+/// compiled, as `prune_orphans` does at the start of every run, and returns the paths deleted.
+/// Only artifacts the record knows about are touched.
+pub fn clean(instructions: &Instructions) -> Result<Vec<PathBuf>> {
+    let _lock = record::lock(instructions)?;
+    let mut record = Record::try_load(instructions)?;
+    prune(&mut record)
+}
+
+/// Makes the record forget `shaders`, or every shader if there are none, so that the next run
+/// compiles them whether or not they changed.  Their artifacts are left as they are until then.
+pub fn invalidate(instructions: &Instructions, shaders: &[PathBuf]) -> Result<()> {
+    let _lock = record::lock(instructions)?;
+    let mut record = Record::try_load(instructions)?;
+    let shaders = match shaders.is_empty() {
+        true => record.shaders(),
+        false => shaders.to_vec(),
+    };
+    for shader in shaders.iter() {
+        record.forget(shader);
+    }
+    record.write()
+}
+
+/// The format version of the record at `instructions.record_path`, or `None` if there isn't
+/// one yet.  Records this version of wrangler writes are `RECORD_VERSION`; older ones are still
+/// read, and newer ones are set aside so that everything is compiled.
+pub fn record_version(instructions: &Instructions) -> Result<Option<u32>> {
+    record::version(&instructions.record_path)
+}
+
+/// Groups shaders whose compiled modules are identical, judging by the artifacts currently in
+/// `output_root` without the build metadata `embed_build_metadata` stamps into each, which names
+/// its source.  Each group lists two or more source files; shaders that haven't been compiled
+/// yet are left out.
+pub fn find_duplicates(instructions: &Instructions) -> Result<Vec<Vec<PathBuf>>> {
+    let mut by_hash = HashMap::<blake3::Hash, Vec<PathBuf>>::new();
+    let names = hashed::load(&instructions.output_root);
+    for candidate in find_shaders(instructions)? {
+        let artifact = current_artifact(instructions, &names, &candidate)?;
+        if let Some(words) = resources::read_words(&artifact) {
+            let words = spirv::remove_notes(&words, BUILD_METADATA_PREFIX);
+            let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+            by_hash
+                .entry(blake3::hash(&bytes))
+                .or_default()
+                .push(candidate.location);
+        }
+    }
+    let mut duplicates: Vec<_> = by_hash.into_values().filter(|g| g.len() > 1).collect();
+    duplicates.sort();
+    Ok(duplicates)
+}
+
+fn report_duplicates(instructions: &Instructions) -> Result<()> {
+    for group in find_duplicates(instructions)? {
+        let names: Vec<_> = group.iter().map(|p| format!("`{}`", p.display())).collect();
+        warn(format_args!(
+            "{} compile to identical SPIR-V",
+            names.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+pub fn run(instructions: Instructions) -> Result<()> {
+    run_all(vec![instructions])
+}
+
+/// Runs several sets of instructions, e.g. one per shader domain of an engine, loading the
+/// compiler only once.  The backend chosen for the first set that has anything to compile is
+/// used for the rest as well.  Compile errors from every set whose
+/// `compilation_error_terminates` is true are returned together in a single `BatchError`.
+pub fn run_all(all_instructions: impl IntoIterator<Item = Instructions>) -> Result<()> {
+    let mut backend = None;
+    let mut batch_errors = Vec::<Error>::new();
+    for mut instructions in all_instructions {
+        let mut report = CompilationReport::default();
+        platform::build(&mut instructions, |instructions| {
+            backend = run_one(instructions, backend.take(), &mut report)?;
+            Ok(())
+        })?;
+        if instructions.compilation_error_terminates {
+            batch_errors.extend(report.errors);
+        } else {
+            report.errors.iter().for_each(print_failure);
+        }
+    }
+    if !batch_errors.is_empty() {
+        return Err(Error::BatchError(batch_errors));
+    }
+    Ok(())
+}
+
+/// Like `run`, but hands back what happened to every shader.  Compile errors never make this
+/// fail, whatever `compilation_error_terminates` says; they are in the report instead.
+pub fn run_report(instructions: Instructions) -> Result<CompilationReport> {
+    run_all_report(vec![instructions])
+}
+
+/// Like `run_all`, but with one report covering every set of instructions, as `run_report`.
+pub fn run_all_report(
+    all_instructions: impl IntoIterator<Item = Instructions>,
+) -> Result<CompilationReport> {
+    let mut backend = None;
+    let mut report = CompilationReport::default();
+    for mut instructions in all_instructions {
+        platform::build(&mut instructions, |instructions| {
+            backend = run_one(instructions, backend.take(), &mut report)?;
+            Ok(())
+        })?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "shaderc")]
+    #[test]
+    fn package_includes_come_from_their_package() {
+        let dir = std::env::temp_dir().join(format!("wrangler-packages-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for sub in ["pbr-2.1", "include"].iter() {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+            fs::write(dir.join(sub).join("lighting.glsl"), *sub).unwrap();
+        }
+        let include_dirs = [dir.join("include")];
+        let packages: BTreeMap<_, _> =
+            std::iter::once(("pbr".to_owned(), dir.join("pbr-2.1"))).collect();
+        let resolve = |requested, include_type| {
+            resolve_include(
+                &include_dirs,
+                &packages,
+                requested,
+                include_type,
+                "sky.frag",
+            )
+            .map(|include| include.content)
+        };
+        use shaderc::IncludeType::{Relative, Standard};
+        assert_eq!(resolve("pbr:lighting.glsl", Standard).unwrap(), "pbr-2.1");
+        assert_eq!(resolve("lighting.glsl", Standard).unwrap(), "include");
+        // Unknown packages are searched for like any other name, and, here, not found.
+        assert!(resolve("ssao:lighting.glsl", Standard).is_err());
+        // shaderc asks again as though it were `<bracketed>`.
+        assert!(resolve("pbr:lighting.glsl", Relative).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn shaders_sharing_an_artifact_are_refused() {
+        let shader = |path: &str, shader_kind| CompilationCandidate {
+            location: Path::new("shaders").join(path),
+            shader_kind,
+        };
+        let candidates = [
+            shader("fx/sky.frag", ShaderKind::Fragment),
+            shader("sky.vert", ShaderKind::Vertex),
+            shader("ui/sky.frag", ShaderKind::Fragment),
+        ];
+        let mut instructions = Instructions {
+            search_root: "shaders".into(),
+            output_root: "out".into(),
+            ..Default::default()
+        };
+        check_collisions(&instructions, &candidates).unwrap();
+        instructions.rename_policy = RenamePolicy::Flatten;
+        match check_collisions(&instructions, &candidates) {
+            Err(Error::OutputCollision {
+                first,
+                second,
+                output,
+            }) => {
+                assert_eq!(first, Path::new("shaders/fx/sky.frag"));
+                assert_eq!(second, Path::new("shaders/ui/sky.frag"));
+                assert_eq!(output, Path::new("out/sky.spv_frag"));
+            }
+            other => panic!("{:?}", other),
+        }
+        instructions.rename_policy = RenamePolicy::HashedNames;
+        check_collisions(&instructions, &candidates).unwrap();
+    }
+
+    #[test]
+    fn hashed_names_follow_the_contents_and_are_looked_up_through_the_manifest() {
+        let dir = std::env::temp_dir().join(format!("wrangler-hashed-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src/fx")).unwrap();
+        fs::create_dir_all(dir.join("out/fx")).unwrap();
+        let mut instructions = Instructions {
+            search_root: dir.join("src"),
+            output_root: dir.join("out"),
+            record_path: dir.join("record"),
+            hashed_output_names: true,
+            ..Default::default()
+        };
+        let tail = Path::new("fx/sky.spv_frag");
+        let hash = &blake3::hash(b"v1").to_hex()[..8];
+        let named = hashed::insert_hash(tail, b"v1");
+        assert_eq!(
+            named,
+            Path::new("fx").join(format!("sky.{}.spv_frag", hash))
+        );
+        assert_ne!(hashed::insert_hash(tail, b"v2"), named);
+
+        let candidate = CompilationCandidate {
+            location: dir.join("src/fx/sky.frag"),
+            shader_kind: ShaderKind::Fragment,
+        };
+        fs::write(&candidate.location, "").unwrap();
+        let artifact = dir.join("out").join(&named);
+        fs::write(&artifact, "v1").unwrap();
+        let mut record = Record::try_load(&instructions).unwrap();
+        record
+            .log(&candidate.location, &[], std::slice::from_ref(&artifact))
+            .unwrap();
+        write_hashed_names(&instructions, std::slice::from_ref(&candidate), &record).unwrap();
+
+        let names = hashed::load(&instructions.output_root);
+        assert_eq!(names["fx/sky.spv_frag"], hashed::key(&named));
+        let current = |instructions: &Instructions| {
+            current_artifact(instructions, &names, &candidate).unwrap()
+        };
+        assert_eq!(current(&instructions), artifact);
+        instructions.hashed_output_names = false;
+        assert_eq!(current(&instructions), dir.join("out").join(tail));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,249 @@
+// Links the artifacts of several shaders into one module each, for `linked_programs`, by running
+// SPIRV-Tools' `spirv-link`, which the spirv-tools crate doesn't bind.  Every unit is compiled to
+// its own artifact as usual, so an edit only recompiles the unit it touches, and a program is
+// linked again whenever the artifacts it's made of change.  Units export and import functions
+// and variables through `LinkageAttributes` decorations, which SPIR-V assembly can declare.
+//
+// What each program was last linked from is kept in a JSON file beside the record, mapping the
+// program's path under `output_root` to a hash of its units and their artifacts.
+
+use crate::record::Record;
+use crate::{
+    is_spvasm, target_env, CompilationCandidate, Error, Instructions, IoContext, Result, TargetEnv,
+};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A module linked from the artifacts of several shaders, for `Instructions::linked_programs`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LinkedProgram {
+    /// Where the linked module is written, relative to `output_root`.
+    pub output: PathBuf,
+    /// The shaders it's linked from, as paths under `search_root`, like `fx/lighting.comp`.
+    /// Each still gets its own artifact.
+    pub units: Vec<PathBuf>,
+}
+
+fn links_path(instructions: &Instructions) -> PathBuf {
+    instructions.record_path.with_extension("links")
+}
+
+/// What `--target-env` is given for `env`.
+fn env_name(env: TargetEnv) -> &'static str {
+    match env {
+        TargetEnv::Vulkan1_0 => "vulkan1.0",
+        TargetEnv::Vulkan1_1 => "vulkan1.1",
+        TargetEnv::Vulkan1_2 => "vulkan1.2",
+        TargetEnv::Vulkan1_3 => "vulkan1.3",
+        TargetEnv::OpenGl4_5 => "opengl4.5",
+    }
+}
+
+/// The binary artifact `unit` was last compiled to, and the environment it was compiled for.
+fn unit_artifact(
+    instructions: &Instructions,
+    candidates: &[CompilationCandidate],
+    record: &Record,
+    unit: &Path,
+) -> std::result::Result<(PathBuf, TargetEnv), String> {
+    let location = instructions.search_root.join(unit);
+    let candidate = candidates
+        .iter()
+        .find(|c| c.location == location)
+        .ok_or_else(|| format!("`{}` isn't a shader under search_root", unit.display()))?;
+    let artifact = record
+        .outputs(&location)
+        .into_iter()
+        .find(|path| !is_spvasm(path))
+        .ok_or_else(|| format!("`{}` hasn't compiled", unit.display()))?;
+    Ok((artifact, target_env(instructions, candidate.shader_kind)))
+}
+
+/// Runs `spirv-link` on `artifacts`, writing the linked module to `dest` by way of a temporary
+/// file.
+fn run_linker(
+    instructions: &Instructions,
+    env: TargetEnv,
+    artifacts: &[PathBuf],
+    dest: &Path,
+) -> std::result::Result<(), String> {
+    let program = instructions
+        .spirv_link
+        .as_deref()
+        .unwrap_or_else(|| Path::new("spirv-link"));
+    let mut temp = dest.to_owned().into_os_string();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    let output = Command::new(program)
+        .arg("--target-env")
+        .arg(env_name(env))
+        .arg("-o")
+        .arg(&temp)
+        .args(artifacts)
+        .output()
+        .map_err(|e| format!("couldn't run `{}`: {}", program.display(), e))?;
+    if !output.status.success() {
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        text.push_str(&String::from_utf8_lossy(&output.stderr));
+        return Err(format!(
+            "`{}` exited with {:?}: {}",
+            program.display(),
+            output.status.code(),
+            text.trim()
+        ));
+    }
+    fs::rename(&temp, dest).map_err(|e| format!("couldn't replace it: {}", e))
+}
+
+/// Links every program in `linked_programs` whose units changed since it was last linked, or
+/// whose module is missing.  A program that can't be linked is added to `errors` and tried again
+/// next run.
+pub(crate) fn link_all(
+    instructions: &Instructions,
+    candidates: &[CompilationCandidate],
+    record: &Record,
+    errors: &mut Vec<Error>,
+) -> Result<()> {
+    let path = links_path(instructions);
+    let mut links: BTreeMap<String, String> = fs::read(&path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default();
+    let before = links.clone();
+    for program in instructions.linked_programs.iter() {
+        let key = program.output.to_string_lossy().replace('\\', "/");
+        let dest = instructions.output_root.join(&program.output);
+        let mut hasher = blake3::Hasher::new();
+        let mut artifacts = Vec::new();
+        let mut env = instructions.target_env;
+        let result = program.units.iter().try_for_each(|unit| {
+            let (artifact, unit_env) = unit_artifact(instructions, candidates, record, unit)?;
+            let bytes = fs::read(&artifact)
+                .map_err(|e| format!("couldn't read `{}`: {}", artifact.display(), e))?;
+            hasher.update(unit.to_string_lossy().as_bytes());
+            hasher.update(blake3::hash(&bytes).as_bytes());
+            // A unit raised to Vulkan 1.2 for its stage raises the whole program with it.
+            if unit_env as u8 > env as u8 {
+                env = unit_env;
+            }
+            artifacts.push(artifact);
+            Ok(())
+        });
+        let hash = hasher.finalize().to_hex().to_string();
+        if result.is_ok() && links.get(&key) == Some(&hash) && dest.exists() {
+            continue;
+        }
+        let result = result.and_then(|()| {
+            if let Some(dir) = dest.parent() {
+                fs::create_dir_all(dir)
+                    .map_err(|e| format!("couldn't create `{}`: {}", dir.display(), e))?;
+            }
+            run_linker(instructions, env, &artifacts, &dest)
+        });
+        match result {
+            Ok(()) => {
+                links.insert(key, hash);
+            }
+            Err(message) => {
+                links.remove(&key);
+                errors.push(Error::Link(dest, message));
+            }
+        }
+    }
+    if links != before {
+        let mut temp = path.clone().into_os_string();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let text = serde_json::to_string_pretty(&links).context("encode", &path)?;
+        fs::write(&temp, text).context("write", &temp)?;
+        fs::rename(&temp, &path).context("replace", &path)?;
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::ShaderKind;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// Stands in for `spirv-link`: logs its arguments beside itself and concatenates its inputs.
+    const FAKE_LINKER: &str = "#!/bin/sh
+echo \"$@\" >> \"$(dirname \"$0\")/calls\"
+while [ \"$1\" != -o ]; do shift; done
+out=$2
+shift 2
+cat \"$@\" > \"$out\"
+";
+
+    #[test]
+    fn programs_are_linked_again_only_when_their_units_change() {
+        let root = std::env::temp_dir().join(format!("wrangler-link-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        let linker = root.join("spirv-link");
+        fs::write(&linker, FAKE_LINKER).unwrap();
+        fs::set_permissions(&linker, fs::Permissions::from_mode(0o755)).unwrap();
+        let instructions = Instructions {
+            search_root: root.join("src"),
+            output_root: root.join("out"),
+            record_path: root.join("record"),
+            linked_programs: vec![
+                LinkedProgram {
+                    output: "lighting.spv".into(),
+                    units: vec!["main.comp.spvasm".into(), "brdf.comp.spvasm".into()],
+                },
+                LinkedProgram {
+                    output: "broken.spv".into(),
+                    units: vec!["missing.comp.spvasm".into()],
+                },
+            ],
+            spirv_link: Some(linker),
+            ..Default::default()
+        };
+        let mut record = Record::try_load(&instructions).unwrap();
+        let mut candidates = Vec::new();
+        for (unit, contents) in [("main", "main;"), ("brdf", "brdf;")] {
+            let source = root.join("src").join(format!("{}.comp.spvasm", unit));
+            let artifact = root.join("out").join(format!("{}.spv_comp", unit));
+            fs::write(&source, "").unwrap();
+            fs::write(&artifact, contents).unwrap();
+            record.log(&source, &[], &[artifact]).unwrap();
+            candidates.push(CompilationCandidate {
+                location: source,
+                shader_kind: ShaderKind::Compute,
+            });
+        }
+        let linked = root.join("out/lighting.spv");
+        let calls = || {
+            fs::read_to_string(root.join("calls"))
+                .unwrap()
+                .lines()
+                .count()
+        };
+
+        let mut errors = Vec::new();
+        link_all(&instructions, &candidates, &record, &mut errors).unwrap();
+        assert_eq!(fs::read_to_string(&linked).unwrap(), "main;brdf;");
+        assert!(fs::read_to_string(root.join("calls"))
+            .unwrap()
+            .starts_with("--target-env vulkan1.0 -o "));
+        assert!(matches!(&errors[..], [Error::Link(path, _)] if path.ends_with("broken.spv")));
+
+        link_all(&instructions, &candidates, &record, &mut Vec::new()).unwrap();
+        assert_eq!(calls(), 1);
+
+        fs::write(root.join("out/brdf.spv_comp"), "brdf v2;").unwrap();
+        link_all(&instructions, &candidates, &record, &mut Vec::new()).unwrap();
+        assert_eq!(calls(), 2);
+        assert_eq!(fs::read_to_string(&linked).unwrap(), "main;brdf v2;");
+
+        fs::remove_file(&linked).unwrap();
+        link_all(&instructions, &candidates, &record, &mut Vec::new()).unwrap();
+        assert_eq!(calls(), 3);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
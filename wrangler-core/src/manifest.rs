@@ -0,0 +1,594 @@
+// Writes `manifest.json` in `output_root`, listing every shader that currently has an artifact
+// along with what an asset packer would otherwise have to work out from the files themselves.
+// It is rebuilt from the record after every run, so shaders that were up to date are in it too.
+// Before it is, the old one is read back to work out what changed since, as a `Churn`; `compare`
+// does the same for any two manifests.
+//
+// Each shader's entry also hashes its source along with every file it included, so that
+// `ship_from` can tell whether the artifact is still what the source would compile to.
+//
+// With a key, the manifest is signed: `signature` is the keyed BLAKE3 hash, in hex, of the
+// manifest without it, as compact JSON, under a key derived from that one.  Since the manifest
+// lists every artifact's hash, checking it is enough to check the artifacts, and the pack.
+
+use crate::record::Record;
+use crate::trace::event;
+use crate::{
+    hashed, kind_ext, CompilationCandidate, Error, Instructions, IoContext, Result, ShaderPack,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The manifest's file name, in `output_root`.
+pub(crate) const MANIFEST: &str = "manifest.json";
+
+/// Where the key to sign the manifest with comes from, without `Instructions::manifest_key`.
+const KEY_VAR: &str = "WRANGLER_MANIFEST_KEY";
+
+#[derive(Serialize)]
+struct Entry {
+    /// Relative to `search_root`.
+    source: PathBuf,
+    kind: &'static str,
+    entry_point: String,
+    /// Relative to `output_root`.
+    output: PathBuf,
+    size: u64,
+    /// The BLAKE3 hash of the artifact, in hex.
+    hash: String,
+    /// The hash of the source and its includes, from `source_hash`, unless one can't be read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_hash: Option<String>,
+    /// What the source included, relative to `search_root` if they're under it.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    includes: Vec<PathBuf>,
+    /// How the module is compressed in the pack, like `zstd-19`, if it is.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compression: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Manifest {
+    shaders: Vec<Entry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+}
+
+/// The key to sign the manifest with, if there is one.
+pub(crate) fn key(instructions: &Instructions) -> Result<Option<[u8; 32]>> {
+    if let Some(key) = instructions.manifest_key {
+        return Ok(Some(key));
+    }
+    match std::env::var(KEY_VAR) {
+        // Written the same way as a BLAKE3 hash.
+        Ok(hex) => match blake3::Hash::from_hex(hex.trim()) {
+            Ok(key) => Ok(Some(*key.as_bytes())),
+            Err(_) => Err(Error::BadManifestKey(format!(
+                "`{}` isn't 64 hex digits",
+                KEY_VAR
+            ))),
+        },
+        Err(_) => Ok(None),
+    }
+}
+
+/// The BLAKE3 hash, in hex, of `source` followed by `includes`, each preceded by its length.
+pub(crate) fn source_hash(source: &Path, includes: &[PathBuf]) -> Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    for path in std::iter::once(source).chain(includes.iter().map(PathBuf::as_path)) {
+        let bytes = fs::read(path).context("read", path)?;
+        hasher.update(&(bytes.len() as u64).to_le_bytes());
+        hasher.update(&bytes);
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Signs `manifest`, a manifest without a signature.
+fn sign(manifest: &serde_json::Value, key: &[u8; 32]) -> blake3::Hash {
+    let key = blake3::derive_key("wrangler manifest signature", key);
+    blake3::keyed_hash(&key, &serde_json::to_vec(manifest).unwrap())
+}
+
+/// What's read back from a manifest; the rest of each entry is ignored.
+#[derive(Deserialize)]
+struct ReadManifest {
+    shaders: Vec<ManifestShader>,
+}
+
+/// A shader's artifact as a manifest lists it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestShader {
+    /// Relative to `search_root`.
+    pub source: PathBuf,
+    pub size: u64,
+    /// The BLAKE3 hash of the artifact, in hex.
+    pub hash: String,
+}
+
+/// A shader whose artifact differs between two manifests.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ChangedShader {
+    pub source: PathBuf,
+    pub old_hash: String,
+    pub new_hash: String,
+    pub old_size: u64,
+    pub new_size: u64,
+}
+
+impl ChangedShader {
+    /// How many bytes the artifact grew by, or shrank by if negative.
+    pub fn size_delta(&self) -> i64 {
+        self.new_size as i64 - self.old_size as i64
+    }
+}
+
+/// How one manifest differs from another, from `compare`.  Each list is sorted by source.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ManifestDiff {
+    /// Shaders only in the second manifest.
+    pub added: Vec<ManifestShader>,
+    /// Shaders only in the first manifest.
+    pub removed: Vec<ManifestShader>,
+    pub changed: Vec<ChangedShader>,
+    pub unchanged: usize,
+}
+
+impl ManifestDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// How many bytes of artifacts the second manifest has over the first.
+    pub fn size_delta(&self) -> i64 {
+        let added: u64 = self.added.iter().map(|s| s.size).sum();
+        let removed: u64 = self.removed.iter().map(|s| s.size).sum();
+        let changed: i64 = self.changed.iter().map(ChangedShader::size_delta).sum();
+        added as i64 - removed as i64 + changed
+    }
+}
+
+/// Reads the shaders listed in the manifest at `path`.
+fn read(path: &Path) -> Result<Vec<ManifestShader>> {
+    let text = fs::read_to_string(path).context("read", path)?;
+    serde_json::from_str::<ReadManifest>(&text)
+        .map(|manifest| manifest.shaders)
+        .map_err(|e| Error::BadManifest(path.to_owned(), e.to_string()))
+}
+
+/// A manifest whose signature checked out, from `verify_manifest`.
+#[derive(Clone, Debug)]
+pub struct VerifiedManifest {
+    path: PathBuf,
+    /// By the name the pack gives each shader.
+    shaders: BTreeMap<String, ManifestShader>,
+}
+
+impl VerifiedManifest {
+    /// Every shader the manifest lists, sorted by source.
+    pub fn shaders(&self) -> impl Iterator<Item = &ManifestShader> {
+        self.shaders.values()
+    }
+
+    /// The shader built from `source`, relative to `search_root`.
+    pub fn get(&self, source: &Path) -> Option<&ManifestShader> {
+        self.shaders.get(&hashed::key(source))
+    }
+
+    fn tampered(&self, reason: String) -> Error {
+        Error::BadManifest(self.path.clone(), reason)
+    }
+
+    /// Checks that `artifact` is the artifact the manifest lists for `source`.
+    pub fn check(&self, source: &Path, artifact: &[u8]) -> Result<()> {
+        let shader = self
+            .get(source)
+            .ok_or_else(|| self.tampered(format!("`{}` isn't in it", source.display())))?;
+        if artifact.len() as u64 != shader.size
+            || blake3::hash(artifact).to_hex().as_str() != shader.hash
+        {
+            return Err(self.tampered(format!(
+                "the artifact of `{}` doesn't match it",
+                source.display()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks that every module in `pack` is the artifact the manifest lists for it.  Encrypted
+    /// packs check their own modules as they're opened and key their hashes, so only which
+    /// modules they hold is checked.
+    pub fn check_pack(&self, pack: &ShaderPack) -> Result<()> {
+        for entry in pack.entries() {
+            let shader = self.shaders.get(entry.name).ok_or_else(|| {
+                self.tampered(format!("`{}` is in the pack but not in it", entry.name))
+            })?;
+            if !pack.is_encrypted()
+                && blake3::Hash::from(*entry.hash).to_hex().as_str() != shader.hash
+            {
+                return Err(self.tampered(format!("`{}` in the pack doesn't match it", entry.name)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the manifest at `path` and checks that it was signed with `key`, as given to
+/// `Instructions::manifest_key`, so that nothing it lists was changed since the build.  Check
+/// artifacts and packs against the result with `check` and `check_pack` as they're loaded.
+pub fn verify_manifest(path: &Path, key: &[u8; 32]) -> Result<VerifiedManifest> {
+    let text = fs::read_to_string(path).context("read", path)?;
+    let bad = |reason: &str| Error::BadManifest(path.to_owned(), reason.to_owned());
+    let mut manifest: serde_json::Value =
+        serde_json::from_str(&text).map_err(|e| bad(&e.to_string()))?;
+    let signature = match manifest.as_object_mut().and_then(|m| m.remove("signature")) {
+        Some(serde_json::Value::String(signature)) => signature,
+        _ => return Err(bad("it isn't signed")),
+    };
+    let signature = blake3::Hash::from_hex(&signature).map_err(|_| bad("bad signature"))?;
+    // Comparing `Hash`es takes the same time however much of them matches.
+    if sign(&manifest, key) != signature {
+        return Err(bad("its signature doesn't match"));
+    }
+    let shaders = serde_json::from_value::<ReadManifest>(manifest)
+        .map_err(|e| bad(&e.to_string()))?
+        .shaders;
+    Ok(VerifiedManifest {
+        path: path.to_owned(),
+        shaders: shaders
+            .into_iter()
+            .map(|shader| (hashed::key(&shader.source), shader))
+            .collect(),
+    })
+}
+
+/// Compares the manifests at `manifest_a` and `manifest_b`, as written by `write_manifest` in
+/// two builds, to show what the second build changed.
+pub fn compare(manifest_a: &Path, manifest_b: &Path) -> Result<ManifestDiff> {
+    let mut old: HashMap<_, _> = read(manifest_a)?
+        .into_iter()
+        .map(|shader| (shader.source.clone(), shader))
+        .collect();
+    let mut diff = ManifestDiff::default();
+    for new in read(manifest_b)? {
+        match old.remove(&new.source) {
+            Some(old) if old.hash == new.hash && old.size == new.size => diff.unchanged += 1,
+            Some(old) => diff.changed.push(ChangedShader {
+                source: new.source,
+                old_hash: old.hash,
+                new_hash: new.hash,
+                old_size: old.size,
+                new_size: new.size,
+            }),
+            None => diff.added.push(new),
+        }
+    }
+    diff.removed = old.into_values().collect();
+    diff.added.sort_by(|a, b| a.source.cmp(&b.source));
+    diff.removed.sort_by(|a, b| a.source.cmp(&b.source));
+    diff.changed.sort_by(|a, b| a.source.cmp(&b.source));
+    Ok(diff)
+}
+
+/// How a manifest's artifacts differ from the ones in the manifest it replaced, for keeping an
+/// eye on how much shader data each commit makes a patch ship.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Churn {
+    /// The manifest this is about.
+    pub manifest: PathBuf,
+    /// Sources, relative to `search_root`, with no artifact in the previous manifest.  On the
+    /// first run, or if the previous manifest can't be read, that's all of them.
+    pub added: Vec<PathBuf>,
+    /// Sources whose artifact was in the previous manifest but isn't any more.
+    pub removed: Vec<PathBuf>,
+    /// Sources whose artifact hash differs from the previous manifest's.
+    pub changed: Vec<PathBuf>,
+    pub unchanged: usize,
+    /// The size of every added and changed artifact: what a patch would have to carry.
+    pub bytes_changed: u64,
+    /// The size of every artifact in the new manifest.
+    pub bytes_total: u64,
+}
+
+impl Churn {
+    /// Whether the manifest lists exactly the same artifacts as before.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Reads the shaders in the manifest at `path`, by source, if there is one that parses.
+fn previous(path: &Path) -> HashMap<PathBuf, ManifestShader> {
+    if !path.exists() {
+        return HashMap::new();
+    }
+    match read(path) {
+        Ok(shaders) => shaders
+            .into_iter()
+            .map(|shader| (shader.source.clone(), shader))
+            .collect(),
+        Err(e) => {
+            event!(warn, "can't read the previous manifest: {}", e);
+            HashMap::new()
+        }
+    }
+}
+
+/// Compares `shaders`, the entries of a new manifest, with `previous`, which it drains.
+fn churn(
+    manifest: PathBuf,
+    shaders: &[Entry],
+    mut previous: HashMap<PathBuf, ManifestShader>,
+) -> Churn {
+    let mut churn = Churn {
+        manifest,
+        ..Default::default()
+    };
+    for entry in shaders.iter() {
+        churn.bytes_total += entry.size;
+        match previous.remove(&entry.source) {
+            Some(old) if old.hash == entry.hash && old.size == entry.size => churn.unchanged += 1,
+            Some(_) => {
+                churn.changed.push(entry.source.clone());
+                churn.bytes_changed += entry.size;
+            }
+            None => {
+                churn.added.push(entry.source.clone());
+                churn.bytes_changed += entry.size;
+            }
+        }
+    }
+    churn.removed = previous.into_keys().collect();
+    churn.removed.sort();
+    churn
+}
+
+/// Writes the manifest, with `codecs` saying how modules were compressed in the pack, by the
+/// name the pack gives them.  Returns how it differs from the manifest it replaced.
+pub(crate) fn write(
+    instructions: &Instructions,
+    candidates: &[CompilationCandidate],
+    record: &Record,
+    codecs: &BTreeMap<String, String>,
+) -> Result<Churn> {
+    let mut shaders = Vec::new();
+    for candidate in candidates.iter() {
+        let artifact = match record.outputs(&candidate.location).into_iter().next() {
+            Some(artifact) => artifact,
+            None => continue,
+        };
+        // Deleted since it was written; the next run compiles it again.
+        let bytes = match fs::read(&artifact) {
+            Ok(bytes) => bytes,
+            Err(_) => continue,
+        };
+        let source = candidate
+            .location
+            .strip_prefix(&instructions.search_root)
+            .unwrap();
+        let output = artifact.strip_prefix(&instructions.output_root);
+        let includes = record.includes(&candidate.location);
+        shaders.push(Entry {
+            source_hash: source_hash(&candidate.location, &includes).ok(),
+            includes: includes
+                .iter()
+                .map(|include| {
+                    let relative = include.strip_prefix(&instructions.search_root);
+                    relative.unwrap_or(include).to_owned()
+                })
+                .collect(),
+            compression: codecs.get(&hashed::key(source)).cloned(),
+            source: source.to_owned(),
+            kind: kind_ext(&candidate.shader_kind)?,
+            entry_point: instructions.entry_point(candidate.shader_kind).to_owned(),
+            output: output.unwrap_or(&artifact).to_owned(),
+            size: bytes.len() as u64,
+            hash: blake3::hash(&bytes).to_hex().to_string(),
+        });
+    }
+    let path = instructions.output_root.join(MANIFEST);
+    let churn = churn(path.clone(), &shaders, previous(&path));
+    event!(
+        info,
+        "`{}`: {} added, {} changed, {} removed, {} of {} bytes changed",
+        path.display(),
+        churn.added.len(),
+        churn.changed.len(),
+        churn.removed.len(),
+        churn.bytes_changed,
+        churn.bytes_total
+    );
+    let mut manifest = Manifest {
+        shaders,
+        signature: None,
+    };
+    if let Some(key) = key(instructions)? {
+        let unsigned = serde_json::to_value(&manifest).context("encode", &path)?;
+        manifest.signature = Some(sign(&unsigned, &key).to_hex().to_string());
+    }
+    let text = serde_json::to_string_pretty(&manifest).context("encode", &path)?;
+    // A packer reading the manifest as a run finishes never sees half of one.
+    let mut temp = path.clone().into_os_string();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    fs::write(&temp, text).context("write", &temp)?;
+    fs::rename(&temp, &path).context("replace", &path)?;
+    Ok(churn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ShaderKind;
+
+    /// A project in a fresh `dir` with `sky.frag`, which includes `common.glsl`, and `ui/text.vert`,
+    /// each with an artifact, and a record of both.
+    fn project(dir: &Path) -> (Instructions, Vec<CompilationCandidate>, Record) {
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir.join("src/ui")).unwrap();
+        fs::create_dir_all(dir.join("out/ui")).unwrap();
+        let instructions = Instructions {
+            search_root: dir.join("src"),
+            output_root: dir.join("out"),
+            record_path: dir.join("record"),
+            ..Default::default()
+        };
+        let mut record = Record::try_load(&instructions).unwrap();
+        let common = dir.join("src/common.glsl");
+        fs::write(&common, "// common").unwrap();
+        let mut candidates = Vec::new();
+        for (source, kind, artifact, includes) in [
+            (
+                "sky.frag",
+                ShaderKind::Fragment,
+                "sky.spv_frag",
+                vec![common],
+            ),
+            (
+                "ui/text.vert",
+                ShaderKind::Vertex,
+                "ui/text.spv_vert",
+                vec![],
+            ),
+        ] {
+            let source = dir.join("src").join(source);
+            let artifact = dir.join("out").join(artifact);
+            fs::write(&source, "void main() {}").unwrap();
+            fs::write(&artifact, [1u8; 8]).unwrap();
+            record.log(&source, &includes, &[artifact]).unwrap();
+            candidates.push(CompilationCandidate {
+                location: source,
+                shader_kind: kind,
+            });
+        }
+        (instructions, candidates, record)
+    }
+
+    #[test]
+    fn the_manifest_lists_every_artifact() {
+        let dir = std::env::temp_dir().join(format!("wrangler-manifest-{}", std::process::id()));
+        let (instructions, candidates, record) = project(&dir);
+        let codecs = [("sky.frag".to_owned(), "zstd-19".to_owned())].into();
+        write(&instructions, &candidates, &record, &codecs).unwrap();
+        let text = fs::read_to_string(dir.join("out").join(MANIFEST)).unwrap();
+        let manifest: serde_json::Value = serde_json::from_str(&text).unwrap();
+        let sky = &manifest["shaders"][0];
+        assert_eq!(sky["source"], "sky.frag");
+        assert_eq!(sky["kind"], "frag");
+        assert_eq!(sky["entry_point"], "main");
+        assert_eq!(sky["output"], "sky.spv_frag");
+        assert_eq!(sky["size"], 8);
+        assert_eq!(sky["hash"], blake3::hash(&[1u8; 8]).to_hex().as_str());
+        assert_eq!(sky["includes"][0], "common.glsl");
+        assert_eq!(sky["compression"], "zstd-19");
+        let source_hash = source_hash(&dir.join("src/sky.frag"), &[dir.join("src/common.glsl")]);
+        assert_eq!(sky["source_hash"], source_hash.unwrap().as_str());
+        let text = &manifest["shaders"][1];
+        assert_eq!(text["source"], "ui/text.vert");
+        assert!(text.get("includes").is_none() && text.get("compression").is_none());
+        assert!(manifest.get("signature").is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn churn_is_against_the_manifest_replaced() {
+        let dir = std::env::temp_dir().join(format!("wrangler-churn-{}", std::process::id()));
+        let (instructions, mut candidates, record) = project(&dir);
+        let first = write(&instructions, &candidates, &record, &BTreeMap::new()).unwrap();
+        assert_eq!(first.added.len(), 2);
+        assert_eq!((first.bytes_changed, first.bytes_total), (16, 16));
+
+        fs::write(dir.join("out/sky.spv_frag"), [2u8; 12]).unwrap();
+        candidates.pop();
+        let second = write(&instructions, &candidates, &record, &BTreeMap::new()).unwrap();
+        assert!(second.added.is_empty());
+        assert_eq!(second.changed, [PathBuf::from("sky.frag")]);
+        assert_eq!(second.removed, [PathBuf::from("ui/text.vert")]);
+        assert_eq!((second.bytes_changed, second.bytes_total), (12, 12));
+
+        let third = write(&instructions, &candidates, &record, &BTreeMap::new()).unwrap();
+        assert_eq!(third.unchanged, 1);
+        assert_eq!(third.bytes_changed, 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_signed_manifest_verifies_only_untouched_and_with_its_key() {
+        let dir = std::env::temp_dir().join(format!("wrangler-sign-{}", std::process::id()));
+        let (mut instructions, candidates, record) = project(&dir);
+        instructions.manifest_key = Some([7; 32]);
+        write(&instructions, &candidates, &record, &BTreeMap::new()).unwrap();
+        let path = dir.join("out").join(MANIFEST);
+
+        let verified = verify_manifest(&path, &[7; 32]).unwrap();
+        assert_eq!(verified.shaders().count(), 2);
+        verified.check(Path::new("sky.frag"), &[1; 8]).unwrap();
+        assert!(verified.check(Path::new("sky.frag"), &[2; 8]).is_err());
+        assert!(verified.check(Path::new("gone.frag"), &[1; 8]).is_err());
+        assert!(matches!(
+            verify_manifest(&path, &[8; 32]),
+            Err(Error::BadManifest(..))
+        ));
+
+        let text = fs::read_to_string(&path).unwrap();
+        let tampered = text.replacen("\"size\": 8", "\"size\": 9", 1);
+        assert_ne!(tampered, text);
+        fs::write(&path, tampered).unwrap();
+        assert!(matches!(
+            verify_manifest(&path, &[7; 32]),
+            Err(Error::BadManifest(..))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn comparing_manifests_sorts_shaders_into_what_changed() {
+        let dir = std::env::temp_dir().join(format!("wrangler-compare-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let manifest = |name: &str, shaders: &[(&str, u64, &str)]| {
+            let shaders: Vec<_> = shaders
+                .iter()
+                .map(|(source, size, hash)| {
+                    serde_json::json!({ "source": source, "size": size, "hash": hash })
+                })
+                .collect();
+            let path = dir.join(name);
+            fs::write(&path, serde_json::json!({ "shaders": shaders }).to_string()).unwrap();
+            path
+        };
+        let a = manifest(
+            "a.json",
+            &[
+                ("sky.frag", 100, "aa"),
+                ("old.vert", 40, "bb"),
+                ("ui/text.vert", 8, "cc"),
+            ],
+        );
+        let b = manifest(
+            "b.json",
+            &[
+                ("ui/text.vert", 8, "cc"),
+                ("sky.frag", 130, "ab"),
+                ("new.comp", 64, "dd"),
+            ],
+        );
+
+        let diff = compare(&a, &b).unwrap();
+        assert_eq!(diff.unchanged, 1);
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].source, Path::new("new.comp"));
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].source, Path::new("old.vert"));
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].source, Path::new("sky.frag"));
+        assert_eq!(diff.changed[0].size_delta(), 30);
+        assert_eq!(diff.size_delta(), 64 - 40 + 30);
+        assert!(compare(&a, &a).unwrap().is_empty());
+
+        fs::write(&b, "{}").unwrap();
+        assert!(matches!(compare(&a, &b), Err(Error::BadManifest(..))));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+// Checks discovered shaders against a project's naming conventions.
+
+use crate::{warn, CompilationCandidate, Error, Instructions, Result, Severity, ShaderKind};
+use regex::Regex;
+
+/// A convention every matching shader has to follow.
+pub struct NamingRule {
+    /// The kinds of shader the rule covers; empty means all of them.
+    pub kinds: Vec<ShaderKind>,
+    /// A regex the file name, extension included, has to match.
+    pub file_name: Option<String>,
+    /// A regex the directory containing the shader, relative to `search_root` and written with
+    /// `/` separators, has to match.  Shaders directly in `search_root` have an empty directory.
+    pub directory: Option<String>,
+    pub severity: Severity,
+}
+
+fn compile_pattern(pattern: Option<&str>) -> Result<Option<Regex>> {
+    pattern
+        .map(|p| Regex::new(p).map_err(|_| Error::BadNamingPattern(p.to_owned())))
+        .transpose()
+}
+
+/// Warns about, or pushes an error onto `errors` for, every candidate breaking a rule.
+pub(crate) fn check(
+    instructions: &Instructions,
+    candidates: &[CompilationCandidate],
+    errors: &mut Vec<Error>,
+) -> Result<()> {
+    for rule in instructions.naming_rules.iter() {
+        let file_name = compile_pattern(rule.file_name.as_deref())?;
+        let directory = compile_pattern(rule.directory.as_deref())?;
+        for candidate in candidates.iter() {
+            if !rule.kinds.is_empty() && !rule.kinds.contains(&candidate.shader_kind) {
+                continue;
+            }
+            let location = &candidate.location;
+            let name = location.file_name().unwrap_or_default().to_string_lossy();
+            let dir = location
+                .parent()
+                .and_then(|dir| dir.strip_prefix(&instructions.search_root).ok())
+                .map(|dir| dir.to_string_lossy().replace('\\', "/"))
+                .unwrap_or_default();
+            let broken = match (&file_name, &directory) {
+                (Some(re), _) if !re.is_match(&name) => re.as_str(),
+                (_, Some(re)) if !re.is_match(&dir) => re.as_str(),
+                _ => continue,
+            };
+            let violation = Error::NamingViolation {
+                path: location.clone(),
+                pattern: broken.to_owned(),
+            };
+            match rule.severity {
+                Severity::Warning => warn(violation),
+                Severity::Error => errors.push(violation),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn candidate(path: &str, shader_kind: ShaderKind) -> CompilationCandidate {
+        CompilationCandidate {
+            location: Path::new("src").join(path),
+            shader_kind,
+        }
+    }
+
+    #[test]
+    fn only_shaders_breaking_an_error_rule_are_errors() {
+        let instructions = Instructions {
+            search_root: "src".into(),
+            naming_rules: vec![
+                NamingRule {
+                    kinds: vec![ShaderKind::Compute],
+                    file_name: Some("^cs_".to_owned()),
+                    directory: None,
+                    severity: Severity::Error,
+                },
+                NamingRule {
+                    kinds: Vec::new(),
+                    file_name: None,
+                    directory: Some("^(fx|ui)(/|$)".to_owned()),
+                    severity: Severity::Error,
+                },
+                // Only ever printed.
+                NamingRule {
+                    kinds: Vec::new(),
+                    file_name: Some("^never$".to_owned()),
+                    directory: None,
+                    severity: Severity::Warning,
+                },
+            ],
+            ..Default::default()
+        };
+        let candidates = [
+            candidate("fx/cs_blur.comp", ShaderKind::Compute),
+            candidate("fx/blur.comp", ShaderKind::Compute),
+            // The compute rule doesn't cover fragment shaders.
+            candidate("ui/blur.frag", ShaderKind::Fragment),
+            candidate("misc/sky.frag", ShaderKind::Fragment),
+        ];
+        let mut errors = Vec::new();
+        check(&instructions, &candidates, &mut errors).unwrap();
+        let broken: Vec<_> = errors
+            .iter()
+            .map(|e| match e {
+                Error::NamingViolation { path, pattern } => (path.clone(), pattern.as_str()),
+                e => panic!("{}", e),
+            })
+            .collect();
+        assert_eq!(
+            broken,
+            [
+                (Path::new("src/fx/blur.comp").to_owned(), "^cs_"),
+                (Path::new("src/misc/sky.frag").to_owned(), "^(fx|ui)(/|$)"),
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_patterns_are_refused() {
+        let instructions = Instructions {
+            naming_rules: vec![NamingRule {
+                kinds: Vec::new(),
+                file_name: Some("(".to_owned()),
+                directory: None,
+                severity: Severity::Warning,
+            }],
+            ..Default::default()
+        };
+        let result = check(&instructions, &[], &mut Vec::new());
+        assert!(matches!(result, Err(Error::BadNamingPattern(p)) if p == "("));
+    }
+}
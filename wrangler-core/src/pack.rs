@@ -0,0 +1,933 @@
+// Packs every artifact into a single `.spvpak` file, for shipping one blob instead of hundreds
+// of loose files, and reads packs back with `ShaderPack`.  The loose artifacts stay in
+// `output_root`, where they are the cache each run's pack is rebuilt from.
+//
+// Everything is little-endian, and every field and module starts on a multiple of four bytes:
+//
+//     magic     8 bytes, `WRSPVPAK`
+//     version   u32
+//     count     u32
+//     flags     u32, bit 0 set if the modules are encrypted
+//     key id    u32, `PackKey::id` of the key they are encrypted with, or 0
+//     dictionary offset u32 and length u32, 0 if there is no dictionary
+//     dictionary tag 32 bytes, zeroes unless the pack is encrypted
+//     index     `count` entries, each:
+//                   name length u32, kind length u32, offset u32, length u32,
+//                   unpacked length u32, 0 unless the module is compressed,
+//                   BLAKE3 hash of the uncompressed module 32 bytes,
+//                   tag 32 bytes, zeroes unless the module is encrypted,
+//                   name, then kind, in UTF-8, zero-padded to a multiple of four bytes
+//     dictionary  the zstd dictionary modules are compressed with, if any, zero-padded to a
+//               multiple of four bytes
+//     modules   each at its entry's offset from the start of the file, `length` bytes long,
+//               zero-padded to a multiple of four bytes if compressed or encrypted
+//
+// Names are source paths relative to `search_root` with forward slashes, like `fx/sky.frag`,
+// and kinds are stage extensions like `frag`.  Entries are sorted by name.  Modules are stored
+// in the byte order `endianness` asks for, and compressed ones are zstd frames of that, made
+// with the dictionary if the pack has one.
+//
+// Encrypted modules are sealed with AES-256-GCM-SIV under a key BLAKE3 derives from the pack
+// key, with the module's name as associated data.  The nonce is always zero: GCM-SIV stays
+// secure when nonces repeat, giving away only which sealed modules are identical, and this way
+// the same artifacts make the same pack.  A tag is GCM-SIV's 16 bytes followed by 16 zeroes.
+// The dictionary is sealed the same way, with an empty name.  In encrypted packs, the hash of
+// each module is keyed with another key derived from the pack key, so it says nothing about
+// what's inside.
+//
+// Readers turn down versions newer than theirs.  Version 1 had no unpacked length, versions 1
+// and 2 had no flags, key id or tags, and versions 1 to 3 had no dictionary.  Version 4 had a
+// cipher of its own, so its encrypted packs aren't read any more.
+
+use crate::record::Record;
+#[cfg(feature = "zstd")]
+use crate::trace::event;
+#[cfg(feature = "zstd")]
+use crate::PackCompression;
+use crate::{
+    hashed, is_spvasm, kind_ext, CompilationCandidate, Error, Instructions, IoContext, PackKey,
+    Result,
+};
+use aes_gcm_siv::aead::{AeadInOut, KeyInit};
+use aes_gcm_siv::{Aes256GcmSiv, Nonce, Tag};
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"WRSPVPAK";
+const VERSION: u32 = 5;
+
+/// Set in the header's flags when the modules are encrypted.
+const ENCRYPTED: u32 = 1;
+
+/// Where the index starts in packs of the given version.
+fn header_len(version: u32) -> usize {
+    match version {
+        1 | 2 => 16,
+        3 => 24,
+        _ => 64,
+    }
+}
+
+fn padded_len(len: usize) -> usize {
+    len.div_ceil(4) * 4
+}
+
+fn pad(bytes: &mut Vec<u8>) {
+    bytes.resize(padded_len(bytes.len()), 0);
+}
+
+struct Packed {
+    name: String,
+    kind: &'static str,
+    hash: blake3::Hash,
+    /// The module as stored, which is compressed if `unpacked_len` isn't 0.
+    module: Vec<u8>,
+    unpacked_len: usize,
+    tag: [u8; 32],
+}
+
+/// Supplies the keys to read encrypted packs with, by `PackKey::id`.  Closures taking an id
+/// are key providers, and so is a `PackKey`, which knows only its own id.
+pub trait KeyProvider {
+    fn key(&self, id: u32) -> Option<[u8; 32]>;
+}
+
+impl<F: Fn(u32) -> Option<[u8; 32]>> KeyProvider for F {
+    fn key(&self, id: u32) -> Option<[u8; 32]> {
+        self(id)
+    }
+}
+
+impl KeyProvider for PackKey {
+    fn key(&self, id: u32) -> Option<[u8; 32]> {
+        match id == self.id {
+            true => Some(self.key),
+            false => None,
+        }
+    }
+}
+
+/// What a pack key seals modules and keys their hashes with.
+struct Keys {
+    cipher: Aes256GcmSiv,
+    authentication: [u8; 32],
+}
+
+impl Keys {
+    fn new(key: &[u8; 32]) -> Keys {
+        let encryption = blake3::derive_key("wrangler pack encryption", key);
+        Keys {
+            cipher: Aes256GcmSiv::new(&encryption.into()),
+            authentication: blake3::derive_key("wrangler pack authentication", key),
+        }
+    }
+
+    fn hash(&self, module: &[u8]) -> blake3::Hash {
+        blake3::keyed_hash(&self.authentication, module)
+    }
+
+    fn seal(&self, name: &str, mut stored: Vec<u8>) -> (Vec<u8>, [u8; 32]) {
+        let sealed = self
+            .cipher
+            .encrypt_inout_detached(
+                &Nonce::default(),
+                name.as_bytes(),
+                stored.as_mut_slice().into(),
+            )
+            .expect("modules are far shorter than GCM-SIV's limit");
+        let mut tag = [0; 32];
+        tag[..16].copy_from_slice(&sealed);
+        (stored, tag)
+    }
+
+    /// Decrypts `stored` in place, returning false if it wasn't sealed under `name` with this
+    /// key, or was changed since.
+    fn open(&self, name: &str, stored: &mut [u8], tag: &[u8; 32]) -> bool {
+        let sealed: Tag = tag[..16].try_into().unwrap();
+        tag[16..] == [0; 16]
+            && self
+                .cipher
+                .decrypt_inout_detached(&Nonce::default(), name.as_bytes(), stored.into(), &sealed)
+                .is_ok()
+    }
+}
+
+/// The levels `PackCompression::Auto` tries, in order.
+#[cfg(feature = "zstd")]
+const AUTO_LEVELS: &[i32] = &[1, 3, 6, 9, 12, 15, 19, 22];
+
+/// Compresses `module` at `level`, with `dictionary` if there is one.
+#[cfg(feature = "zstd")]
+fn compress_at(module: &[u8], level: i32, dictionary: &[u8]) -> std::io::Result<Vec<u8>> {
+    match dictionary.is_empty() {
+        true => zstd::bulk::compress(module, level),
+        false => zstd::bulk::Compressor::with_dictionary(level, dictionary)?.compress(module),
+    }
+}
+
+/// Compresses `module` at the lowest level in `AUTO_LEVELS` that meets `ratio`, stopping early
+/// at a level slower than `max_time`, and returns the smallest result with its level.
+#[cfg(feature = "zstd")]
+fn compress_auto(
+    module: &[u8],
+    dictionary: &[u8],
+    ratio: f64,
+    max_time: std::time::Duration,
+) -> std::io::Result<(Vec<u8>, i32)> {
+    let mut best: Option<(Vec<u8>, i32)> = None;
+    for &level in AUTO_LEVELS {
+        let started = std::time::Instant::now();
+        let compressed = compress_at(module, level, dictionary)?;
+        let took = started.elapsed();
+        let fits = compressed.len() as f64 <= module.len() as f64 * ratio;
+        let smallest = match &best {
+            Some((b, _)) => compressed.len() < b.len(),
+            None => true,
+        };
+        if smallest {
+            best = Some((compressed, level));
+        }
+        if fits || took > max_time {
+            break;
+        }
+    }
+    Ok(best.unwrap())
+}
+
+/// Compresses `module` if `instructions.pack_compression` says to and it makes it smaller, and
+/// returns what to store along with its unpacked length and the zstd level, if compressed.
+#[cfg(feature = "zstd")]
+fn compress(
+    instructions: &Instructions,
+    module: Vec<u8>,
+    dictionary: &[u8],
+    artifact: &Path,
+) -> Result<(Vec<u8>, usize, Option<i32>)> {
+    let (compressed, level) = match instructions.pack_compression {
+        Some(PackCompression::Level(level)) => (
+            compress_at(&module, level, dictionary).context("compress", artifact)?,
+            level,
+        ),
+        Some(PackCompression::Auto { ratio, max_time }) => {
+            compress_auto(&module, dictionary, ratio, max_time).context("compress", artifact)?
+        }
+        None => return Ok((module, 0, None)),
+    };
+    if compressed.len() < module.len() {
+        let len = module.len();
+        Ok((compressed, len, Some(level)))
+    } else {
+        Ok((module, 0, None))
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+fn compress(
+    _: &Instructions,
+    module: Vec<u8>,
+    _: &[u8],
+    _: &Path,
+) -> Result<(Vec<u8>, usize, Option<i32>)> {
+    Ok((module, 0, None))
+}
+
+/// Trains a zstd dictionary over `modules` if `instructions.pack_dictionary` asks for one and
+/// the modules are to be compressed.  Too few or too small modules to train over just mean no
+/// dictionary.
+#[cfg(feature = "zstd")]
+fn train(instructions: &Instructions, modules: &[Vec<u8>]) -> Vec<u8> {
+    let max_size = match (instructions.pack_compression, instructions.pack_dictionary) {
+        (Some(_), Some(max_size)) => max_size,
+        _ => return Vec::new(),
+    };
+    match zstd::dict::from_samples(modules, max_size) {
+        Ok(dictionary) => dictionary,
+        Err(e) => {
+            event!(
+                warn,
+                "packing without a dictionary, which didn't train: {}",
+                e
+            );
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+fn train(_: &Instructions, _: &[Vec<u8>]) -> Vec<u8> {
+    Vec::new()
+}
+
+/// Writes a pack of the artifact of every shader in `candidates` the record knows of to `path`.
+/// Returns how each compressed module was compressed, like `zstd-19`, by name.
+pub(crate) fn write(
+    instructions: &Instructions,
+    candidates: &[CompilationCandidate],
+    record: &Record,
+    path: &Path,
+) -> Result<BTreeMap<String, String>> {
+    let keys = instructions.pack_key.map(|k| Keys::new(&k.key));
+    let mut found = Vec::new();
+    let mut modules = Vec::new();
+    for candidate in candidates.iter() {
+        let artifact = match record.outputs(&candidate.location).into_iter().next() {
+            Some(artifact) if !is_spvasm(&artifact) => artifact,
+            // Never compiled, or only written as assembly.
+            _ => continue,
+        };
+        let module = match fs::read(&artifact) {
+            Ok(module) => module,
+            Err(_) => continue,
+        };
+        found.push((candidate, artifact));
+        modules.push(module);
+    }
+    // The dictionary is trained over every module before any is compressed with it.
+    let dictionary = train(instructions, &modules);
+
+    let mut packed = Vec::new();
+    let mut codecs = BTreeMap::new();
+    for ((candidate, artifact), module) in found.into_iter().zip(modules) {
+        let tail = candidate
+            .location
+            .strip_prefix(&instructions.search_root)
+            .unwrap();
+        let hash = match &keys {
+            Some(keys) => keys.hash(&module),
+            None => blake3::hash(&module),
+        };
+        let (module, unpacked_len, level) = compress(instructions, module, &dictionary, &artifact)?;
+        let name = hashed::key(tail);
+        if let Some(level) = level {
+            let codec = match dictionary.is_empty() {
+                true => format!("zstd-{}", level),
+                false => format!("zstd-{}+dict", level),
+            };
+            codecs.insert(name.clone(), codec);
+        }
+        let (module, tag) = match &keys {
+            Some(keys) => keys.seal(&name, module),
+            None => (module, [0; 32]),
+        };
+        packed.push(Packed {
+            name,
+            kind: kind_ext(&candidate.shader_kind)?,
+            hash,
+            module,
+            unpacked_len,
+            tag,
+        });
+    }
+    packed.sort_by(|a, b| a.name.cmp(&b.name));
+    // No module has an empty name, so the dictionary's tag can't be mistaken for one's.
+    let (dictionary, dictionary_tag) = match &keys {
+        Some(keys) if !dictionary.is_empty() => keys.seal("", dictionary),
+        _ => (dictionary, [0; 32]),
+    };
+
+    let bytes = encode(&packed, &dictionary, &dictionary_tag, instructions.pack_key);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("create directory", parent)?;
+    }
+    let mut temp = path.to_owned().into_os_string();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    fs::write(&temp, bytes).context("write", &temp)?;
+    fs::rename(&temp, path).context("replace", path)?;
+    Ok(codecs)
+}
+
+/// Lays out a pack of `packed`, which is sorted by name, with `dictionary` already sealed if the
+/// modules are, under `key`.
+fn encode(
+    packed: &[Packed],
+    dictionary: &[u8],
+    dictionary_tag: &[u8; 32],
+    key: Option<PackKey>,
+) -> Vec<u8> {
+    let index_len: usize = packed
+        .iter()
+        .map(|p| 84 + padded_len(p.name.len() + p.kind.len()))
+        .sum();
+    let mut offset = header_len(VERSION) + index_len;
+    let mut bytes = Vec::with_capacity(offset);
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(packed.len() as u32).to_le_bytes());
+    let (flags, key_id) = match key {
+        Some(key) => (ENCRYPTED, key.id),
+        None => (0, 0),
+    };
+    bytes.extend_from_slice(&flags.to_le_bytes());
+    bytes.extend_from_slice(&key_id.to_le_bytes());
+    bytes.extend_from_slice(&(offset as u32).to_le_bytes());
+    bytes.extend_from_slice(&(dictionary.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(dictionary_tag);
+    offset += padded_len(dictionary.len());
+    for p in packed.iter() {
+        bytes.extend_from_slice(&(p.name.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(p.kind.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(offset as u32).to_le_bytes());
+        bytes.extend_from_slice(&(p.module.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(p.unpacked_len as u32).to_le_bytes());
+        bytes.extend_from_slice(p.hash.as_bytes());
+        bytes.extend_from_slice(&p.tag);
+        bytes.extend_from_slice(p.name.as_bytes());
+        bytes.extend_from_slice(p.kind.as_bytes());
+        pad(&mut bytes);
+        offset += padded_len(p.module.len());
+    }
+    bytes.extend_from_slice(dictionary);
+    pad(&mut bytes);
+    for p in packed.iter() {
+        bytes.extend_from_slice(&p.module);
+        pad(&mut bytes);
+    }
+    bytes
+}
+
+/// A module in a `ShaderPack`.
+#[derive(Clone, Copy, Debug)]
+pub struct PackEntry<'a> {
+    /// The source's path relative to `search_root`, with forward slashes, e.g. `fx/sky.frag`.
+    pub name: &'a str,
+    /// The stage's extension, e.g. `frag`.
+    pub kind: &'a str,
+    pub spirv: &'a [u32],
+    /// The BLAKE3 hash of the module as it was packed, before any compression, keyed if the
+    /// pack is encrypted.
+    pub hash: &'a [u8; 32],
+}
+
+struct Indexed {
+    name: String,
+    kind: String,
+    /// Where the module is stored in the file, without any padding.
+    stored: Range<usize>,
+    unpacked_len: usize,
+    /// Where the module's words are in `ShaderPack::words`, once it's been read.
+    words: Range<usize>,
+    hash: [u8; 32],
+    tag: [u8; 32],
+}
+
+/// A `.spvpak` file written because of `Instructions::pack_output`, read into memory.  Opening
+/// a pack checks its index and every module's hash, so a truncated or corrupted pack is turned
+/// down as a whole rather than failing later.  Modules come back decompressed and in the
+/// machine's byte order, whichever one they were packed in, using the pack's dictionary if it
+/// was written with one.  Encrypted packs, written because
+/// of `Instructions::pack_key`, are opened with `open_with_key`, which also turns down any
+/// module that was tampered with or doesn't decrypt with the key.
+pub struct ShaderPack {
+    words: Vec<u32>,
+    index: Vec<Indexed>,
+    encrypted: bool,
+}
+
+/// Reads a little-endian `u32` at `at`, if `bytes` is long enough.
+fn read_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    let field = bytes.get(at..at.checked_add(4)?)?;
+    Some(u32::from_le_bytes([field[0], field[1], field[2], field[3]]))
+}
+
+fn bad(reason: impl Into<String>) -> Error {
+    Error::BadPack(reason.into())
+}
+
+/// Reads the index entry at `at` in a pack of the given version and moves `at` past it.
+fn read_entry(bytes: &[u8], version: u32, at: &mut usize) -> Option<Indexed> {
+    let name_len = read_u32(bytes, *at)? as usize;
+    let kind_len = read_u32(bytes, *at + 4)? as usize;
+    let offset = read_u32(bytes, *at + 8)? as usize;
+    let length = read_u32(bytes, *at + 12)? as usize;
+    let (unpacked_len, hash_at, fixed) = match version {
+        1 => (0, 16, 48),
+        2 => (read_u32(bytes, *at + 16)? as usize, 20, 52),
+        _ => (read_u32(bytes, *at + 16)? as usize, 20, 84),
+    };
+    let hash: [u8; 32] = bytes
+        .get(*at + hash_at..*at + hash_at + 32)?
+        .try_into()
+        .ok()?;
+    let tag: [u8; 32] = match version {
+        1 | 2 => [0; 32],
+        _ => bytes.get(*at + 52..*at + 84)?.try_into().ok()?,
+    };
+    let text = bytes.get(*at + fixed..*at + fixed + name_len.checked_add(kind_len)?)?;
+    let name = std::str::from_utf8(&text[..name_len]).ok()?.to_owned();
+    let kind = std::str::from_utf8(&text[name_len..]).ok()?.to_owned();
+    *at += fixed + padded_len(name_len + kind_len);
+    if offset & 3 != 0 || offset.checked_add(length)? > bytes.len() {
+        return None;
+    }
+    Some(Indexed {
+        name,
+        kind,
+        stored: offset..offset + length,
+        unpacked_len,
+        words: 0..0,
+        hash,
+        tag,
+    })
+}
+
+/// Reads little-endian words from `bytes`, a whole number of them, then swaps their bytes if
+/// they are a module written in the other byte order, as its magic number shows.
+fn native_words(bytes: &[u8]) -> Vec<u32> {
+    let mut words: Vec<u32> = bytes
+        .chunks_exact(4)
+        .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+        .collect();
+    if words.first() == Some(&0x0302_2307) {
+        words.iter_mut().for_each(|w| *w = w.swap_bytes());
+    }
+    words
+}
+
+/// Decompresses a zstd-compressed module, such as one from a pack, into words in the machine's
+/// byte order.  Decompressing into a `Vec<u32>` keeps the module aligned for handing straight to
+/// a graphics API.
+#[cfg(feature = "zstd")]
+pub fn decompress(compressed: &[u8]) -> Result<Vec<u32>> {
+    let bytes = zstd::stream::decode_all(compressed).map_err(Error::Decompression)?;
+    if bytes.len() & 3 != 0 {
+        return Err(Error::Decompression(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "not a whole number of words",
+        )));
+    }
+    Ok(native_words(&bytes))
+}
+
+/// Decompresses a module stored in a pack, with the pack's dictionary if it has one.  It has to
+/// come out at its unpacked length; no more than a byte past that is ever decompressed, so a
+/// damaged or hostile pack can't make it balloon.
+#[cfg(feature = "zstd")]
+fn unpack(stored: &[u8], dictionary: &[u8], entry: &Indexed) -> Result<Vec<u8>> {
+    use std::io::Read;
+    let undecodable =
+        |e: std::io::Error| bad(format!("`{}` doesn't decompress: {}", entry.name, e));
+    // The unpacked length isn't trusted until the module comes out at it, so it isn't allocated
+    // up front.
+    let mut module = Vec::new();
+    let decoder =
+        zstd::stream::Decoder::with_dictionary(stored, dictionary).map_err(undecodable)?;
+    decoder
+        .take(entry.unpacked_len as u64 + 1)
+        .read_to_end(&mut module)
+        .map_err(undecodable)?;
+    if module.len() != entry.unpacked_len {
+        return Err(bad(format!(
+            "`{}` doesn't decompress to its unpacked length of {} bytes",
+            entry.name, entry.unpacked_len
+        )));
+    }
+    Ok(module)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn unpack(_: &[u8], _: &[u8], entry: &Indexed) -> Result<Vec<u8>> {
+    Err(bad(format!(
+        "`{}` is compressed, and reading it needs the `zstd` feature",
+        entry.name
+    )))
+}
+
+impl ShaderPack {
+    pub fn open(path: impl AsRef<Path>) -> Result<ShaderPack> {
+        let path = path.as_ref();
+        ShaderPack::from_bytes(&fs::read(path).context("read", path)?)
+    }
+
+    /// Opens a pack that may be encrypted, asking `keys` for the key it was encrypted with.
+    pub fn open_with_key(path: impl AsRef<Path>, keys: impl KeyProvider) -> Result<ShaderPack> {
+        let path = path.as_ref();
+        ShaderPack::from_bytes_with_key(&fs::read(path).context("read", path)?, keys)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<ShaderPack> {
+        ShaderPack::read(bytes, None)
+    }
+
+    pub fn from_bytes_with_key(bytes: &[u8], keys: impl KeyProvider) -> Result<ShaderPack> {
+        ShaderPack::read(bytes, Some(&keys))
+    }
+
+    fn read(bytes: &[u8], provider: Option<&dyn KeyProvider>) -> Result<ShaderPack> {
+        if bytes.len() < 16 || &bytes[..8] != MAGIC {
+            return Err(bad("not a shader pack"));
+        }
+        if bytes.len() & 3 != 0 {
+            return Err(bad("length isn't a whole number of words"));
+        }
+        let version = read_u32(bytes, 8).unwrap();
+        if version == 0 {
+            return Err(bad("not a shader pack"));
+        }
+        if version > VERSION {
+            return Err(bad(format!(
+                "version {} is newer than the {} this wrangler reads",
+                version, VERSION
+            )));
+        }
+        let mut at = header_len(version);
+        if bytes.len() < at {
+            return Err(bad("the header is truncated"));
+        }
+        let count = read_u32(bytes, 12).unwrap() as usize;
+        let (flags, key_id) = match version {
+            1 | 2 => (0, 0),
+            _ => (read_u32(bytes, 16).unwrap(), read_u32(bytes, 20).unwrap()),
+        };
+        let (dictionary, dictionary_tag): (Range<usize>, [u8; 32]) = match version {
+            1..=3 => (0..0, [0; 32]),
+            _ => {
+                let offset = read_u32(bytes, 24).unwrap() as usize;
+                let len = read_u32(bytes, 28).unwrap() as usize;
+                match offset.checked_add(len) {
+                    Some(end) if offset & 3 == 0 && end <= bytes.len() => {
+                        (offset..end, bytes[32..64].try_into().unwrap())
+                    }
+                    _ => return Err(bad("the dictionary is truncated")),
+                }
+            }
+        };
+        let keys =
+            match (flags & ENCRYPTED != 0, provider) {
+                (false, _) => None,
+                (true, _) if version < 5 => {
+                    return Err(bad(format!(
+                        "it's encrypted as version {} packs were, which is no longer read",
+                        version
+                    )))
+                }
+                (true, None) => return Err(bad(
+                    "it's encrypted, and reading it needs a key, with `ShaderPack::open_with_key`",
+                )),
+                (true, Some(provider)) => match provider.key(key_id) {
+                    Some(key) => Some(Keys::new(&key)),
+                    None => return Err(bad(format!("there's no key with id {}", key_id))),
+                },
+            };
+        let mut pack = ShaderPack {
+            words: Vec::with_capacity(bytes.len() / 4),
+            index: Vec::new(),
+            encrypted: keys.is_some(),
+        };
+        let mut dictionary = bytes[dictionary].to_owned();
+        if let (Some(keys), false) = (&keys, dictionary.is_empty()) {
+            if !keys.open("", &mut dictionary, &dictionary_tag) {
+                return Err(bad(format!(
+                    "the dictionary doesn't decrypt with key {}",
+                    key_id
+                )));
+            }
+        }
+        for _ in 0..count {
+            let entry = read_entry(bytes, version, &mut at)
+                .ok_or_else(|| bad("the index is truncated or damaged"))?;
+            if let Some(last) = pack.index.last() {
+                if last.name >= entry.name {
+                    return Err(bad(format!(
+                        "`{}` is out of order in the index",
+                        entry.name
+                    )));
+                }
+            }
+            pack.index.push(entry);
+        }
+        // Each module is copied out into `words`, which keeps them all aligned.
+        for entry in pack.index.iter_mut() {
+            let mut stored = bytes[entry.stored.clone()].to_owned();
+            if let Some(keys) = &keys {
+                if !keys.open(&entry.name, &mut stored, &entry.tag) {
+                    return Err(bad(format!(
+                        "`{}` doesn't decrypt with key {}",
+                        entry.name, key_id
+                    )));
+                }
+            }
+            let module = match entry.unpacked_len {
+                0 => stored,
+                _ => unpack(&stored, &dictionary, entry)?,
+            };
+            let hash = match &keys {
+                Some(keys) => keys.hash(&module),
+                None => blake3::hash(&module),
+            };
+            if hash.as_bytes() != &entry.hash {
+                return Err(bad(format!("`{}` doesn't match its hash", entry.name)));
+            }
+            if module.len() & 3 != 0 {
+                return Err(bad(format!(
+                    "`{}` isn't a whole number of words",
+                    entry.name
+                )));
+            }
+            let start = pack.words.len();
+            pack.words.extend(native_words(&module));
+            entry.words = start..pack.words.len();
+        }
+        Ok(pack)
+    }
+
+    /// The module packed under `name`, e.g. `fx/sky.frag`.
+    pub fn get(&self, name: &str) -> Option<&[u32]> {
+        let i = self
+            .index
+            .binary_search_by(|e| e.name.as_str().cmp(name))
+            .ok()?;
+        Some(&self.words[self.index[i].words.clone()])
+    }
+
+    /// Every module in the pack, sorted by name.
+    pub fn entries(&self) -> impl Iterator<Item = PackEntry<'_>> {
+        self.index.iter().map(move |e| PackEntry {
+            name: &e.name,
+            kind: &e.kind,
+            spirv: &self.words[e.words.clone()],
+            hash: &e.hash,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Whether the modules were encrypted, which makes their hashes keyed.
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A made-up module `len` words long, as little-endian bytes.
+    fn module(len: u32, seed: u32) -> Vec<u8> {
+        let words = std::iter::once(0x0723_0203).chain((1..len).map(|i| i.wrapping_mul(seed)));
+        words.flat_map(u32::to_le_bytes).collect()
+    }
+
+    fn packed(name: &str, module: Vec<u8>) -> Packed {
+        Packed {
+            name: name.to_owned(),
+            kind: "frag",
+            hash: blake3::hash(&module),
+            module,
+            unpacked_len: 0,
+            tag: [0; 32],
+        }
+    }
+
+    fn plain(packed: &[Packed]) -> Vec<u8> {
+        encode(packed, &[], &[0; 32], None)
+    }
+
+    /// Why opening `bytes` fails.
+    fn refusal(bytes: &[u8], keys: Option<&dyn KeyProvider>) -> String {
+        match ShaderPack::read(bytes, keys) {
+            Err(Error::BadPack(reason)) => reason,
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("opened a bad pack"),
+        }
+    }
+
+    #[test]
+    fn reads_back_what_was_packed() {
+        let (sky, water) = (module(8, 3), module(5, 7));
+        let bytes = plain(&[
+            packed("fx/sky.frag", sky.clone()),
+            packed("water.frag", water.clone()),
+        ]);
+        let pack = ShaderPack::from_bytes(&bytes).unwrap();
+        assert_eq!(pack.len(), 2);
+        assert!(!pack.is_encrypted());
+        assert_eq!(pack.get("fx/sky.frag"), Some(&native_words(&sky)[..]));
+        assert_eq!(pack.get("water.frag"), Some(&native_words(&water)[..]));
+        assert_eq!(pack.get("sky.frag"), None);
+        let names: Vec<_> = pack.entries().map(|e| (e.name, e.kind)).collect();
+        assert_eq!(names, [("fx/sky.frag", "frag"), ("water.frag", "frag")]);
+        assert_eq!(
+            pack.entries().next().unwrap().hash,
+            blake3::hash(&sky).as_bytes()
+        );
+    }
+
+    #[test]
+    fn swaps_modules_packed_in_the_other_byte_order() {
+        let native = module(4, 5);
+        let swapped = native
+            .chunks(4)
+            .flat_map(|w| [w[3], w[2], w[1], w[0]])
+            .collect();
+        let pack = ShaderPack::from_bytes(&plain(&[packed("sky.frag", swapped)])).unwrap();
+        assert_eq!(pack.get("sky.frag"), Some(&native_words(&native)[..]));
+    }
+
+    #[test]
+    fn turns_down_damaged_packs() {
+        let bytes = plain(&[packed("sky.frag", module(8, 3))]);
+        assert_eq!(refusal(b"not a pack at all", None), "not a shader pack");
+        assert_eq!(
+            refusal(&bytes[..header_len(VERSION) + 8], None),
+            "the dictionary is truncated"
+        );
+        assert_eq!(
+            refusal(&bytes[..bytes.len() - 4], None),
+            "the index is truncated or damaged"
+        );
+        let mut newer = bytes.clone();
+        newer[8..12].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(refusal(&newer, None).contains("newer than"));
+        let mut corrupt = bytes.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert_eq!(refusal(&corrupt, None), "`sky.frag` doesn't match its hash");
+        let unsorted = plain(&[
+            packed("b.frag", module(2, 1)),
+            packed("a.frag", module(2, 1)),
+        ]);
+        assert_eq!(
+            refusal(&unsorted, None),
+            "`a.frag` is out of order in the index"
+        );
+    }
+
+    fn sealed(keys: &Keys, name: &str, module: Vec<u8>) -> Packed {
+        let hash = keys.hash(&module);
+        let (module, tag) = keys.seal(name, module);
+        Packed {
+            tag,
+            hash,
+            ..packed(name, module)
+        }
+    }
+
+    #[test]
+    fn encrypted_packs_open_only_with_their_key() {
+        let key = PackKey {
+            id: 7,
+            key: [9; 32],
+        };
+        let sky = module(32, 3);
+        let bytes = encode(
+            &[sealed(&Keys::new(&key.key), "sky.frag", sky.clone())],
+            &[],
+            &[0; 32],
+            Some(key),
+        );
+        assert!(!bytes.windows(sky.len()).any(|w| w == sky.as_slice()));
+        let pack = ShaderPack::from_bytes_with_key(&bytes, key).unwrap();
+        assert!(pack.is_encrypted());
+        assert_eq!(pack.get("sky.frag"), Some(&native_words(&sky)[..]));
+
+        assert!(refusal(&bytes, None).contains("needs a key"));
+        let other = PackKey { id: 8, ..key };
+        assert_eq!(refusal(&bytes, Some(&other)), "there's no key with id 7");
+        let wrong = |id: u32| (id == 7).then_some([1; 32]);
+        assert_eq!(
+            refusal(&bytes, Some(&wrong)),
+            "`sky.frag` doesn't decrypt with key 7"
+        );
+        let mut tampered = bytes.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            refusal(&tampered, Some(&key)),
+            "`sky.frag` doesn't decrypt with key 7"
+        );
+    }
+
+    #[test]
+    fn sealing_is_deterministic_and_bound_to_the_name() {
+        let keys = Keys::new(&[3; 32]);
+        let sky = module(8, 1);
+        let (sealed, tag) = keys.seal("sky.frag", sky.clone());
+        assert_eq!(keys.seal("sky.frag", sky.clone()), (sealed.clone(), tag));
+        assert_ne!(keys.seal("sea.frag", sky.clone()).0, sealed);
+        assert!(!keys.open("sea.frag", &mut sealed.clone(), &tag));
+        let mut opened = sealed;
+        assert!(keys.open("sky.frag", &mut opened, &tag));
+        assert_eq!(opened, sky);
+    }
+
+    #[test]
+    fn turns_down_packs_encrypted_by_version_4() {
+        let key = PackKey {
+            id: 7,
+            key: [9; 32],
+        };
+        let mut bytes = encode(
+            &[sealed(&Keys::new(&key.key), "sky.frag", module(8, 1))],
+            &[],
+            &[0; 32],
+            Some(key),
+        );
+        bytes[8..12].copy_from_slice(&4u32.to_le_bytes());
+        assert_eq!(
+            refusal(&bytes, Some(&key)),
+            "it's encrypted as version 4 packs were, which is no longer read"
+        );
+    }
+
+    #[cfg(feature = "zstd")]
+    fn compressed(name: &str, module: Vec<u8>, dictionary: &[u8]) -> Packed {
+        Packed {
+            unpacked_len: module.len(),
+            hash: blake3::hash(&module),
+            ..packed(name, compress_at(&module, 3, dictionary).unwrap())
+        }
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn decompresses_modules() {
+        let sky = module(256, 1);
+        let bytes = plain(&[compressed("sky.frag", sky.clone(), &[])]);
+        assert!(bytes.len() < sky.len());
+        let pack = ShaderPack::from_bytes(&bytes).unwrap();
+        assert_eq!(pack.get("sky.frag"), Some(&native_words(&sky)[..]));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn turns_down_modules_of_the_wrong_unpacked_length() {
+        let sky = module(256, 1);
+        for unpacked_len in [sky.len() - 4, sky.len() + 4, u32::MAX as usize] {
+            let bytes = plain(&[Packed {
+                unpacked_len,
+                ..compressed("sky.frag", sky.clone(), &[])
+            }]);
+            assert_eq!(
+                refusal(&bytes, None),
+                format!(
+                    "`sky.frag` doesn't decompress to its unpacked length of {} bytes",
+                    unpacked_len
+                )
+            );
+        }
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn decompresses_modules_with_the_dictionary() {
+        let dictionary = module(64, 5);
+        let sky = module(128, 5);
+        let bytes = encode(
+            &[compressed("sky.frag", sky.clone(), &dictionary)],
+            &dictionary,
+            &[0; 32],
+            None,
+        );
+        let pack = ShaderPack::from_bytes(&bytes).unwrap();
+        assert_eq!(pack.get("sky.frag"), Some(&native_words(&sky)[..]));
+    }
+}
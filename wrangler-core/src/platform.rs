@@ -0,0 +1,264 @@
+// Building one set of instructions for several platforms, for `Instructions::platforms`.  Each
+// platform's settings are swapped into the instructions for as long as its run lasts, and the
+// shared ones swapped back afterwards, so everything downstream of `run_one` sees plain
+// instructions and needs no idea of platforms.  The paths swapped in give every platform an output
+// tree, record, and reports of its own.
+
+#[cfg(any(feature = "cross", feature = "wgsl-out"))]
+use crate::CrossTarget;
+use crate::{codegen, Error, Instructions, Result, SpirvVersion, TargetEnv};
+use std::path::{Path, PathBuf};
+
+/// One target of a platform matrix, for `Instructions::platforms`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Platform {
+    /// Names the platform's directory under `output_root`, and goes in the names of its record
+    /// and other outputs, so it can only be letters, digits, `-` and `_`.
+    pub name: String,
+    /// Macros defined for this platform, after the shared `defines`.
+    pub defines: Vec<(String, Option<String>)>,
+    /// Replaces `target_env`, if set.
+    pub target_env: Option<TargetEnv>,
+    /// Replaces `spirv_version`, if set.
+    pub spirv_version: Option<SpirvVersion>,
+    /// Device profiles this platform's artifacts are checked against, besides the shared
+    /// `device_profiles`.
+    pub device_profiles: Vec<PathBuf>,
+    /// The `cfg` predicate under which `rust_module` embeds this platform's artifacts, like
+    /// `target_os = "android"`, if not `feature = "<name>"`.
+    pub rust_cfg: Option<String>,
+}
+
+/// `path` with `.name` before its extension, as in `shaders.mobile.spvpak`, or with `-name` on
+/// the end if it has none, so that the files named after the record with their own extension,
+/// like its history, stay apart too.
+pub(crate) fn tagged(path: &Path, name: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file = match path.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, name, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, name),
+    };
+    path.with_file_name(file)
+}
+
+/// The settings that differ between platforms, either a platform's or the shared ones while a
+/// platform's are swapped in.
+struct Settings {
+    output_root: PathBuf,
+    record_path: PathBuf,
+    defines: Vec<(String, Option<String>)>,
+    target_env: TargetEnv,
+    spirv_version: Option<SpirvVersion>,
+    device_profiles: Vec<PathBuf>,
+    pack_output: Option<PathBuf>,
+    rust_module: Option<PathBuf>,
+    resource_report: Option<PathBuf>,
+    sarif_output: Option<PathBuf>,
+    junit_output: Option<PathBuf>,
+    json_report: Option<PathBuf>,
+    #[cfg(feature = "ship")]
+    ship_from: Option<PathBuf>,
+    #[cfg(any(feature = "cross", feature = "wgsl-out"))]
+    cross_compile: Vec<(CrossTarget, PathBuf)>,
+}
+
+impl Settings {
+    fn new(instructions: &Instructions, platform: &Platform) -> Settings {
+        let name = &platform.name;
+        let tag = |path: &Option<PathBuf>| path.as_deref().map(|path| tagged(path, name));
+        Settings {
+            output_root: instructions.output_root.join(name),
+            record_path: tagged(&instructions.record_path, name),
+            defines: instructions
+                .defines
+                .iter()
+                .chain(platform.defines.iter())
+                .cloned()
+                .collect(),
+            target_env: platform.target_env.unwrap_or(instructions.target_env),
+            spirv_version: platform.spirv_version.or(instructions.spirv_version),
+            device_profiles: instructions
+                .device_profiles
+                .iter()
+                .chain(platform.device_profiles.iter())
+                .cloned()
+                .collect(),
+            pack_output: tag(&instructions.pack_output),
+            rust_module: tag(&instructions.rust_module),
+            resource_report: tag(&instructions.resource_report),
+            sarif_output: tag(&instructions.sarif_output),
+            junit_output: tag(&instructions.junit_output),
+            json_report: tag(&instructions.json_report),
+            #[cfg(feature = "ship")]
+            ship_from: instructions.ship_from.as_ref().map(|dir| dir.join(name)),
+            #[cfg(any(feature = "cross", feature = "wgsl-out"))]
+            cross_compile: instructions
+                .cross_compile
+                .iter()
+                .map(|(target, dir)| (*target, dir.join(name)))
+                .collect(),
+        }
+    }
+
+    /// Trades these settings for the ones in `instructions`; doing it twice puts them back.
+    fn swap(&mut self, instructions: &mut Instructions) {
+        use std::mem::swap;
+        swap(&mut self.output_root, &mut instructions.output_root);
+        swap(&mut self.record_path, &mut instructions.record_path);
+        swap(&mut self.defines, &mut instructions.defines);
+        swap(&mut self.target_env, &mut instructions.target_env);
+        swap(&mut self.spirv_version, &mut instructions.spirv_version);
+        swap(&mut self.device_profiles, &mut instructions.device_profiles);
+        swap(&mut self.pack_output, &mut instructions.pack_output);
+        swap(&mut self.rust_module, &mut instructions.rust_module);
+        swap(&mut self.resource_report, &mut instructions.resource_report);
+        swap(&mut self.sarif_output, &mut instructions.sarif_output);
+        swap(&mut self.junit_output, &mut instructions.junit_output);
+        swap(&mut self.json_report, &mut instructions.json_report);
+        #[cfg(feature = "ship")]
+        swap(&mut self.ship_from, &mut instructions.ship_from);
+        #[cfg(any(feature = "cross", feature = "wgsl-out"))]
+        swap(&mut self.cross_compile, &mut instructions.cross_compile);
+    }
+}
+
+fn check_names(platforms: &[Platform]) -> Result<()> {
+    for (i, platform) in platforms.iter().enumerate() {
+        let name = &platform.name;
+        let allowed = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if name.is_empty() || !name.chars().all(allowed) {
+            return Err(Error::BadPlatform(name.clone()));
+        }
+        if platforms[..i].iter().any(|other| other.name == *name) {
+            return Err(Error::BadPlatform(name.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Calls `run` with `instructions` set up for each of its platforms in turn, or just once as
+/// they are if there are none.  `instructions` is as it was afterwards, whatever `run` returns.
+pub(crate) fn each(
+    instructions: &mut Instructions,
+    mut run: impl FnMut(&Instructions) -> Result<()>,
+) -> Result<()> {
+    if instructions.platforms.is_empty() {
+        return run(instructions);
+    }
+    check_names(&instructions.platforms)?;
+    let platforms = std::mem::take(&mut instructions.platforms);
+    let result = platforms.iter().try_for_each(|platform| {
+        let mut settings = Settings::new(instructions, platform);
+        settings.swap(instructions);
+        let result = run(instructions);
+        settings.swap(instructions);
+        result
+    });
+    instructions.platforms = platforms;
+    result
+}
+
+/// Runs `run` for each platform like `each`, then writes the `rust_module` choosing between the
+/// ones each platform got.
+pub(crate) fn build(
+    instructions: &mut Instructions,
+    run: impl FnMut(&Instructions) -> Result<()>,
+) -> Result<()> {
+    each(instructions, run)?;
+    match &instructions.rust_module {
+        Some(path) if !instructions.platforms.is_empty() => {
+            codegen::write_platforms(&instructions.platforms, path)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn platforms_get_their_own_settings_and_paths() {
+        let mut instructions = Instructions {
+            output_root: "out".into(),
+            record_path: "shaders.record".into(),
+            defines: vec![("QUALITY".to_owned(), Some("2".to_owned()))],
+            pack_output: Some("assets/shaders.spvpak".into()),
+            platforms: vec![
+                Platform {
+                    name: "desktop".to_owned(),
+                    ..Default::default()
+                },
+                Platform {
+                    name: "mobile".to_owned(),
+                    defines: vec![("MOBILE".to_owned(), None)],
+                    target_env: Some(TargetEnv::Vulkan1_1),
+                    device_profiles: vec!["profiles/mali.json".into()],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let mut seen = Vec::new();
+        each(&mut instructions, |i| {
+            assert!(i.platforms.is_empty());
+            seen.push((
+                i.output_root.clone(),
+                i.record_path.clone(),
+                i.pack_output.clone().unwrap(),
+                i.defines.len(),
+                i.target_env,
+                i.device_profiles.clone(),
+            ));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            seen,
+            [
+                (
+                    PathBuf::from("out/desktop"),
+                    PathBuf::from("shaders.desktop.record"),
+                    PathBuf::from("assets/shaders.desktop.spvpak"),
+                    1,
+                    TargetEnv::Vulkan1_0,
+                    vec![],
+                ),
+                (
+                    PathBuf::from("out/mobile"),
+                    PathBuf::from("shaders.mobile.record"),
+                    PathBuf::from("assets/shaders.mobile.spvpak"),
+                    2,
+                    TargetEnv::Vulkan1_1,
+                    vec![PathBuf::from("profiles/mali.json")],
+                ),
+            ]
+        );
+        // Everything is put back once the platforms are done.
+        assert_eq!(instructions.output_root, Path::new("out"));
+        assert_eq!(instructions.defines.len(), 1);
+        assert_eq!(instructions.platforms.len(), 2);
+        assert_eq!(
+            tagged(Path::new("out/record"), "deck"),
+            Path::new("out/record-deck")
+        );
+    }
+
+    #[test]
+    fn platform_names_are_checked() {
+        for names in [&["mobile", "mobile"][..], &[""], &["../desktop"]] {
+            let mut instructions = Instructions {
+                platforms: names
+                    .iter()
+                    .map(|name| Platform {
+                        name: name.to_string(),
+                        ..Default::default()
+                    })
+                    .collect(),
+                ..Default::default()
+            };
+            let result = each(&mut instructions, |_| Ok(()));
+            assert!(matches!(result, Err(Error::BadPlatform(_))), "{:?}", names);
+        }
+    }
+}
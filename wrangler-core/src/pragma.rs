@@ -0,0 +1,108 @@
+// Per-file overrides of the compile options, written in a shader's leading comments:
+//
+//     //! wrangler: opt-level=zero, define=DEBUG_NORMALS, define=MAX_LIGHTS=4
+//
+// Only the comment block at the top of the file is looked at, along with a `#version` line if
+// there is one; the first other line of code ends it.  Recognized options are `opt-level`
+// (`zero`, `size`, or `performance`), `define` (`NAME` or `NAME=VALUE`), and `debug-info` (on
+// its own, or `=true`/`=false`).
+
+use crate::{CompileSettings, Error, OptLevel, Result};
+use std::path::Path;
+
+const PREFIX: &str = "//! wrangler:";
+
+fn apply_option(option: &str, settings: &mut CompileSettings) -> Option<()> {
+    let (key, value) = match option.split_once('=') {
+        Some((key, value)) => (key.trim(), Some(value.trim())),
+        None => (option, None),
+    };
+    match (key, value) {
+        ("opt-level", Some("zero")) => settings.opt_level = OptLevel::Zero,
+        ("opt-level", Some("size")) => settings.opt_level = OptLevel::Size,
+        ("opt-level", Some("performance")) => settings.opt_level = OptLevel::Performance,
+        ("define", Some(define)) if !define.is_empty() => {
+            let define = match define.split_once('=') {
+                Some((name, value)) => (name.trim().to_owned(), Some(value.trim().to_owned())),
+                None => (define.to_owned(), None),
+            };
+            settings.defines.push(define);
+        }
+        ("debug-info", None) | ("debug-info", Some("true")) => settings.debug_info = true,
+        ("debug-info", Some("false")) => settings.debug_info = false,
+        _ => return None,
+    }
+    Some(())
+}
+
+/// Merges the options in `source`'s header comments into `settings`.
+pub(crate) fn apply(path: &Path, source: &str, settings: &mut CompileSettings) -> Result<()> {
+    for line in source.lines().map(str::trim) {
+        if let Some(options) = line.strip_prefix(PREFIX) {
+            for option in options.split(',').map(str::trim) {
+                if option.is_empty() {
+                    continue;
+                }
+                apply_option(option, settings).ok_or_else(|| Error::BadPragma {
+                    path: path.to_owned(),
+                    option: option.to_owned(),
+                })?;
+            }
+        } else if !line.is_empty() && !line.starts_with("//") && !line.starts_with("#version") {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(source: &str) -> Result<CompileSettings> {
+        let mut settings = CompileSettings::default();
+        apply(Path::new("sky.frag"), source, &mut settings)?;
+        Ok(settings)
+    }
+
+    #[test]
+    fn reads_options_from_the_header() {
+        let settings = settings(
+            "#version 450\n\
+             // A sky.\n\
+             //! wrangler: opt-level=performance, define=DEBUG_NORMALS\n\
+             //! wrangler: define=MAX_LIGHTS = 4, debug-info\n\
+             \n\
+             void main() {}\n",
+        )
+        .unwrap();
+        assert_eq!(settings.opt_level, OptLevel::Performance);
+        assert_eq!(
+            settings.defines,
+            [
+                ("DEBUG_NORMALS".to_owned(), None),
+                ("MAX_LIGHTS".to_owned(), Some("4".to_owned())),
+            ]
+        );
+        assert!(settings.debug_info);
+    }
+
+    #[test]
+    fn stops_at_the_first_line_of_code() {
+        let settings = settings("layout(location = 0) out vec4 color;\n//! wrangler: debug-info\n");
+        assert!(!settings.unwrap().debug_info);
+    }
+
+    #[test]
+    fn turns_down_unknown_options() {
+        match settings("//! wrangler: opt-level=fast") {
+            Err(Error::BadPragma { path, option }) => {
+                assert_eq!(path, Path::new("sky.frag"));
+                assert_eq!(option, "opt-level=fast");
+            }
+            _ => panic!("expected a bad pragma"),
+        }
+        assert!(settings("//! wrangler: define=").is_err());
+        assert!(settings("//! wrangler: speed").is_err());
+    }
+}
@@ -0,0 +1,705 @@
+// The record remembers when each shader was last compiled, along with when each file it included
+// was last modified, so unchanged ones can be skipped.  With `hash_contents` it also keeps a hash
+// of each of those files, and a file whose modification time moved is only taken as changed if
+// its hash did too.
+//
+// On disk it is a journal: a snapshot of the whole record followed by one entry per shader
+// compiled since the snapshot was taken, appended as soon as the shader's output is written.  A
+// run that dies halfway through therefore keeps everything it finished, and a torn final entry is
+// simply dropped on the next load.  Once enough entries pile up the journal is compacted into a
+// fresh snapshot, which is written beside the record and renamed over it so readers only ever
+// see a complete file.
+//
+// Paths are stored relative to `search_root`, and artifacts relative to `output_root`, so a
+// record still works when the project is checked out somewhere else, e.g. restored from a CI
+// cache.  Files outside those directories, like includes from elsewhere, are stored as absolute,
+// canonical paths.  Each key knows whether its root was stripped, and only those are joined back
+// onto it; a path relative to the working directory would otherwise be taken for one under the
+// root.
+//
+// Each snapshot is preceded by the version of the format it is written in.  Records from before
+// versions were written count as version 0, which differs from version 1 only in lacking the
+// header.  Versions before 2 stored full paths and are converted when loaded.  A record newer
+// than this build understands is set aside and everything is compiled, since guessing at its
+// layout could skip shaders that are out of date.
+//
+// The record is MessagePack unless `record_format` asks for JSON, in which case each value goes
+// on a line of its own.  Loading works out which one a file is from its first byte, and a record
+// found in the other format is rewritten in the chosen one the next time it is saved.
+//
+// Runs sharing a record take turns through an advisory lock on a `.lock` file beside it, held
+// from before the record is loaded until the last artifact is written.
+
+use crate::{warn, Error, Instructions, IoContext, LockPolicy, Reason, RecordFormat, Result};
+use fs2::FileExt;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The version of the format records are written in.
+pub const VERSION: u32 = 2;
+
+/// Compaction never happens with fewer entries than this behind the snapshot.
+const MIN_COMPACTION_ENTRIES: usize = 64;
+
+/// Keeps other runs away from a record until it is dropped.
+pub(crate) struct Lock {
+    _file: fs::File,
+}
+
+/// Takes the lock on the record at `instructions.record_path`, waiting for it or not as
+/// `instructions.lock` says.
+pub(crate) fn lock(instructions: &Instructions) -> Result<Lock> {
+    let mut path = instructions.record_path.clone().into_os_string();
+    path.push(".lock");
+    let path = PathBuf::from(path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("create directory", parent)?;
+    }
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(&path)
+        .context("open", &path)?;
+    match instructions.lock {
+        LockPolicy::Wait => file.lock_exclusive().context("lock", &path)?,
+        LockPolicy::Fail => file
+            .try_lock_exclusive()
+            .map_err(|_| Error::Locked(path.clone()))?,
+    }
+    Ok(Lock { _file: file })
+}
+
+fn modified_time(path: &Path) -> Result<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .context("read the modification time of", path)
+}
+
+/// Guesses the format of a record from its first byte.  A JSON record starts with its version
+/// number, which in MessagePack would read as a version in the fifties or higher.
+fn detect(bytes: &[u8]) -> RecordFormat {
+    match bytes.first() {
+        Some(b'0'..=b'9') | Some(b'{') => RecordFormat::Json,
+        _ => RecordFormat::MessagePack,
+    }
+}
+
+fn encode(
+    format: RecordFormat,
+    mut writer: impl Write,
+    value: &impl Serialize,
+) -> std::io::Result<()> {
+    match format {
+        RecordFormat::MessagePack => {
+            rmp_serde::encode::write(&mut writer, value).map_err(std::io::Error::other)?
+        }
+        RecordFormat::Json => {
+            serde_json::to_writer(&mut writer, value).map_err(std::io::Error::other)?;
+            writer.write_all(b"\n")?;
+        }
+    }
+    Ok(())
+}
+
+/// Decodes the contents of a record file one value at a time.
+struct Values {
+    bytes: Vec<u8>,
+    format: RecordFormat,
+    /// Where the value after the last one decoded starts.
+    offset: usize,
+}
+
+impl Values {
+    fn new(bytes: Vec<u8>) -> Values {
+        Values {
+            format: detect(&bytes),
+            bytes,
+            offset: 0,
+        }
+    }
+
+    /// Decodes the next value, staying put if it isn't a `T`.
+    fn next<T: DeserializeOwned>(&mut self) -> std::result::Result<T, String> {
+        let rest = &self.bytes[self.offset..];
+        match self.format {
+            RecordFormat::MessagePack => {
+                let mut cursor = Cursor::new(rest);
+                let value = rmp_serde::from_read(&mut cursor).map_err(|e| e.to_string())?;
+                self.offset += cursor.position() as usize;
+                Ok(value)
+            }
+            RecordFormat::Json => {
+                let mut stream = serde_json::Deserializer::from_slice(rest).into_iter();
+                match stream.next() {
+                    Some(Ok(value)) => {
+                        self.offset += stream.byte_offset();
+                        Ok(value)
+                    }
+                    Some(Err(e)) => Err(e.to_string()),
+                    None => Err("unexpected end of file".to_owned()),
+                }
+            }
+        }
+    }
+
+    /// Reads the version header, or takes the record to be version 0 if it has none.
+    fn version(&mut self) -> u32 {
+        self.next().unwrap_or(0)
+    }
+
+    /// Whether everything has been decoded, such that nothing torn or garbled is left over.
+    fn finished(&self) -> bool {
+        let rest = &self.bytes[self.offset..];
+        match self.format {
+            RecordFormat::MessagePack => rest.is_empty(),
+            RecordFormat::Json => rest.iter().all(u8::is_ascii_whitespace),
+        }
+    }
+}
+
+/// A path as the record stores it.  On disk it's just the path: a relative one had its root
+/// stripped, since any other is absolute.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "PathBuf", into = "PathBuf")]
+struct Key {
+    path: PathBuf,
+    /// Whether `path` is relative to the root, rather than absolute.
+    stripped: bool,
+}
+
+impl Key {
+    /// `path` relative to `root` if it is under it, and otherwise absolute and canonical.
+    fn new(root: &Path, path: &Path) -> Key {
+        match path.strip_prefix(root) {
+            Ok(relative) => Key {
+                path: relative.to_owned(),
+                stripped: true,
+            },
+            // A file that's gone can't be canonicalized, but its key still has to be absolute.
+            Err(_) => Key {
+                path: fs::canonicalize(path)
+                    .or_else(|_| std::path::absolute(path))
+                    .unwrap_or_else(|_| path.to_owned()),
+                stripped: false,
+            },
+        }
+    }
+
+    /// Where the key points, given the root it was stored against.
+    fn resolve(&self, root: &Path) -> PathBuf {
+        match self.stripped {
+            true => root.join(&self.path),
+            false => self.path.clone(),
+        }
+    }
+}
+
+impl From<PathBuf> for Key {
+    fn from(path: PathBuf) -> Key {
+        Key {
+            stripped: path.is_relative(),
+            path,
+        }
+    }
+}
+
+impl From<Key> for PathBuf {
+    fn from(key: Key) -> PathBuf {
+        key.path
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    path: Key,
+    modified: SystemTime,
+    includes: Vec<(Key, SystemTime)>,
+    #[serde(default)]
+    hashes: Vec<(Key, [u8; 32])>,
+    #[serde(default)]
+    outputs: Vec<Key>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub(crate) struct Record {
+    modified_times: HashMap<Key, SystemTime>,
+    /// The files each shader included when it was compiled, and when they were modified then.
+    #[serde(default)]
+    includes: HashMap<Key, Vec<(Key, SystemTime)>>,
+    /// A fingerprint of the options everything in the record was compiled with.
+    #[serde(default)]
+    options: String,
+    /// The contents of every shader and include as of when it was logged, if `hash_contents`
+    /// was set then.
+    #[serde(default)]
+    hashes: HashMap<Key, [u8; 32]>,
+    /// The artifacts written for each shader when it was last compiled.
+    #[serde(default)]
+    outputs: HashMap<Key, Vec<Key>>,
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(skip)]
+    search_root: PathBuf,
+    #[serde(skip)]
+    output_root: PathBuf,
+    #[serde(skip)]
+    hash_contents: bool,
+    #[serde(skip)]
+    format: RecordFormat,
+    #[serde(skip)]
+    journal: Option<fs::File>,
+    /// Entries on disk behind the snapshot.
+    #[serde(skip)]
+    journaled: usize,
+    /// Whether the file on disk is missing, has garbage in it, or still has shaders that were
+    /// forgotten, so that appending to it would be pointless until a fresh snapshot is written.
+    #[serde(skip)]
+    stale: bool,
+}
+
+impl Record {
+    pub fn try_load(instructions: &Instructions) -> Result<Record> {
+        let path = instructions.record_path.clone();
+        let options = instructions.options_hash().to_hex().to_string();
+        let fresh = Record {
+            path: path.clone(),
+            options: options.clone(),
+            search_root: instructions.search_root.clone(),
+            output_root: instructions.output_root.clone(),
+            hash_contents: instructions.hash_contents,
+            format: instructions.record_format,
+            stale: true,
+            ..Default::default()
+        };
+        if !path.exists() {
+            return Ok(fresh);
+        }
+        let mut values = Values::new(fs::read(&path).context("read", &path)?);
+        let version = values.version();
+        if version > VERSION {
+            warn(format_args!(
+                "the record at `{}` is version {}, newer than the {} this wrangler reads, so every \
+                 shader will be compiled",
+                path.display(),
+                version,
+                VERSION
+            ));
+            return Ok(fresh);
+        }
+        let mut record: Record = match values.next() {
+            Ok(record) => record,
+            Err(e) => {
+                warn(format_args!(
+                    "couldn't read the record at `{}`, so every shader will be compiled: {}",
+                    path.display(),
+                    e
+                ));
+                return Ok(fresh);
+            }
+        };
+        // Nothing compiled with other options, say for another target, is of any use.
+        if record.options != options {
+            return Ok(fresh);
+        }
+        record.path = path;
+        record.search_root = fresh.search_root;
+        record.output_root = fresh.output_root;
+        record.hash_contents = instructions.hash_contents;
+        record.format = instructions.record_format;
+        while let Ok(entry) = values.next::<Entry>() {
+            record
+                .modified_times
+                .insert(entry.path.clone(), entry.modified);
+            record.set_includes(entry.path.clone(), entry.includes);
+            record.hashes.extend(entry.hashes);
+            record.outputs.insert(entry.path.clone(), entry.outputs);
+            record.journaled += 1;
+        }
+        record.stale = !values.finished() || values.format != record.format;
+        if version < 2 {
+            record.relativize();
+            record.stale = true;
+        }
+        Ok(record)
+    }
+
+    /// Converts the full paths records before version 2 stored into keys.  They were stored as
+    /// given, so a relative one is from the working directory rather than stripped.
+    fn relativize(&mut self) {
+        let (root, output_root) = (&self.search_root, &self.output_root);
+        let key = |root: &Path, key: Key| Key::new(root, &key.path);
+        self.modified_times = self
+            .modified_times
+            .drain()
+            .map(|(path, modified)| (key(root, path), modified))
+            .collect();
+        self.includes = self
+            .includes
+            .drain()
+            .map(|(path, includes)| {
+                let includes = includes
+                    .into_iter()
+                    .map(|(path, modified)| (key(root, path), modified))
+                    .collect();
+                (key(root, path), includes)
+            })
+            .collect();
+        self.hashes = self
+            .hashes
+            .drain()
+            .map(|(path, hash)| (key(root, path), hash))
+            .collect();
+        self.outputs = self
+            .outputs
+            .drain()
+            .map(|(path, outputs)| {
+                let outputs = outputs.into_iter().map(|o| key(output_root, o)).collect();
+                (key(root, path), outputs)
+            })
+            .collect();
+    }
+
+    /// Why `shader` needs compiling, if it does: it was never compiled, or it or a file it
+    /// included has changed since.
+    pub fn change(&self, shader: &Path) -> Result<Option<Reason>> {
+        let key = Key::new(&self.search_root, shader);
+        let modified = match self.modified_times.get(&key) {
+            Some(&modified) => modified,
+            None => return Ok(Some(Reason::New)),
+        };
+        if modified_time(shader)? != modified && self.contents_changed(&key) {
+            return Ok(Some(Reason::Modified));
+        }
+        let includes = match self.includes.get(&key) {
+            Some(includes) => includes,
+            None => return Ok(None),
+        };
+        let changed = includes.iter().any(|(key, modified)| {
+            let path = key.resolve(&self.search_root);
+            fs::metadata(path).and_then(|m| m.modified()).ok() != Some(*modified)
+                && self.contents_changed(key)
+        });
+        Ok(changed.then_some(Reason::IncludeModified))
+    }
+
+    /// Whether the contents of the file stored as `key` differ from when it was logged, taking
+    /// any file that wasn't hashed then as changed.
+    fn contents_changed(&self, key: &Key) -> bool {
+        match (
+            self.hashes.get(key),
+            fs::read(key.resolve(&self.search_root)),
+        ) {
+            (Some(hash), Ok(contents)) => blake3::hash(&contents).as_bytes() != hash,
+            _ => true,
+        }
+    }
+
+    fn set_includes(&mut self, shader: Key, includes: Vec<(Key, SystemTime)>) {
+        if includes.is_empty() {
+            self.includes.remove(&shader);
+        } else {
+            self.includes.insert(shader, includes);
+        }
+    }
+
+    /// The files `shader` included when it was last compiled.
+    pub fn includes(&self, shader: &Path) -> Vec<PathBuf> {
+        let key = Key::new(&self.search_root, shader);
+        let includes = self
+            .includes
+            .get(&key)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        includes
+            .iter()
+            .map(|(key, _)| key.resolve(&self.search_root))
+            .collect()
+    }
+
+    /// Every shader in the record whose source no longer exists.
+    pub fn orphans(&self) -> Vec<PathBuf> {
+        self.modified_times
+            .keys()
+            .map(|key| key.resolve(&self.search_root))
+            .filter(|shader| !shader.exists())
+            .collect()
+    }
+
+    /// Every shader in the record.
+    pub fn shaders(&self) -> Vec<PathBuf> {
+        self.modified_times
+            .keys()
+            .map(|key| key.resolve(&self.search_root))
+            .collect()
+    }
+
+    /// Every file any shader in the record included.
+    #[cfg(feature = "watch")]
+    pub fn included(&self) -> Vec<PathBuf> {
+        let mut included: Vec<_> = self
+            .includes
+            .values()
+            .flatten()
+            .map(|(key, _)| key.resolve(&self.search_root))
+            .collect();
+        included.sort();
+        included.dedup();
+        included
+    }
+
+    /// The artifacts `shader` was last compiled to.
+    pub fn outputs(&self, shader: &Path) -> Vec<PathBuf> {
+        let key = Key::new(&self.search_root, shader);
+        let outputs = self
+            .outputs
+            .get(&key)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        outputs
+            .iter()
+            .map(|o| o.resolve(&self.output_root))
+            .collect()
+    }
+
+    /// Drops `shader` from the record and returns the artifacts it was last compiled to.  The
+    /// change is only saved by the next compaction.
+    pub fn forget(&mut self, shader: &Path) -> Vec<PathBuf> {
+        let key = Key::new(&self.search_root, shader);
+        self.modified_times.remove(&key);
+        self.includes.remove(&key);
+        self.hashes.remove(&key);
+        self.stale = true;
+        let outputs = self.outputs.remove(&key).unwrap_or_default();
+        outputs
+            .iter()
+            .map(|o| o.resolve(&self.output_root))
+            .collect()
+    }
+
+    pub fn log(
+        &mut self,
+        file: impl AsRef<Path>,
+        includes: &[PathBuf],
+        outputs: &[PathBuf],
+    ) -> Result<()> {
+        let file: &Path = file.as_ref();
+        let key = Key::new(&self.search_root, file);
+        let modified = modified_time(file)?;
+        self.modified_times.insert(key.clone(), modified);
+        let includes = includes
+            .iter()
+            .map(|path| {
+                let modified = modified_time(path)?;
+                Ok((Key::new(&self.search_root, path), modified))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.set_includes(key.clone(), includes.clone());
+        let mut hashes = Vec::new();
+        if self.hash_contents {
+            for key in std::iter::once(&key).chain(includes.iter().map(|(key, _)| key)) {
+                let path = key.resolve(&self.search_root);
+                let hash = blake3::hash(&fs::read(&path).context("read", &path)?);
+                hashes.push((key.clone(), *hash.as_bytes()));
+            }
+            self.hashes.extend(hashes.iter().cloned());
+        }
+        let outputs: Vec<_> = outputs
+            .iter()
+            .map(|o| Key::new(&self.output_root, o))
+            .collect();
+        self.outputs.insert(key.clone(), outputs.clone());
+        if self.stale {
+            return self.compact();
+        }
+        if self.journal.is_none() {
+            self.journal = Some(
+                fs::OpenOptions::new()
+                    .append(true)
+                    .open(&self.path)
+                    .context("open", &self.path)?,
+            );
+        }
+        let entry = Entry {
+            path: key,
+            modified,
+            includes,
+            hashes,
+            outputs,
+        };
+        let journal = self.journal.as_mut().unwrap();
+        encode(self.format, &mut *journal, &entry)
+            .and_then(|()| journal.flush())
+            .context("write", &self.path)?;
+        self.journaled += 1;
+        Ok(())
+    }
+
+    /// Compacts the journal if it has grown longer than the snapshot it follows.
+    pub fn write(&mut self) -> Result<()> {
+        if self.stale || self.journaled > self.modified_times.len().max(MIN_COMPACTION_ENTRIES) {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        self.journal = None;
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("create directory", parent)?;
+        }
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let file = fs::File::create(&temp).context("create", &temp)?;
+        let mut file = std::io::BufWriter::new(file);
+        encode(self.format, &mut file, &VERSION)
+            .and_then(|()| encode(self.format, &mut file, self))
+            .and_then(|()| file.into_inner().map_err(|e| e.into_error()))
+            .and_then(|file| file.sync_all())
+            .context("write", &temp)?;
+        fs::rename(&temp, &self.path).context("replace", &self.path)?;
+        // The rename itself isn't safe from a crash until the directory holding it is synced.
+        #[cfg(unix)]
+        {
+            let dir = match self.path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            fs::File::open(dir)
+                .and_then(|dir| dir.sync_all())
+                .context("sync", dir)?;
+        }
+        self.journaled = 0;
+        self.stale = false;
+        Ok(())
+    }
+}
+
+/// The format version of the record at `path`, or `None` if there is no record there.
+pub fn version(path: &Path) -> Result<Option<u32>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(
+        Values::new(fs::read(path).context("read", path)?).version(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A snapshot in `format` with one shader, preceded by `version` if there is one, and
+    /// followed by a journal entry for another.
+    fn record(format: RecordFormat, version: Option<u32>) -> Vec<u8> {
+        let mut record = Record::default();
+        let key = Key::new(Path::new("src"), Path::new("src/sky.frag"));
+        record.modified_times.insert(key, SystemTime::UNIX_EPOCH);
+        let entry = Entry {
+            path: Key::new(Path::new("src"), Path::new("src/water.frag")),
+            modified: SystemTime::UNIX_EPOCH,
+            includes: Vec::new(),
+            hashes: Vec::new(),
+            outputs: Vec::new(),
+        };
+        let mut bytes = Vec::new();
+        if let Some(version) = version {
+            encode(format, &mut bytes, &version).unwrap();
+        }
+        encode(format, &mut bytes, &record).unwrap();
+        encode(format, &mut bytes, &entry).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn reads_the_version_then_the_snapshot_then_entries() {
+        for format in [RecordFormat::MessagePack, RecordFormat::Json] {
+            let mut values = Values::new(record(format, Some(VERSION)));
+            assert_eq!(values.format, format);
+            assert_eq!(values.version(), VERSION);
+            let snapshot: Record = values.next().unwrap();
+            assert_eq!(snapshot.modified_times.len(), 1);
+            let entry: Entry = values.next().unwrap();
+            assert_eq!(entry.path.path, Path::new("water.frag"));
+            assert!(entry.path.stripped);
+            assert!(values.next::<Entry>().is_err());
+            assert!(values.finished());
+        }
+    }
+
+    #[test]
+    fn records_without_a_header_are_version_0() {
+        for format in [RecordFormat::MessagePack, RecordFormat::Json] {
+            let mut values = Values::new(record(format, None));
+            assert_eq!(values.version(), 0);
+            // Not finding a version leaves the snapshot to be read.
+            assert!(values.next::<Record>().is_ok());
+        }
+    }
+
+    #[test]
+    fn torn_entries_are_left_over() {
+        for format in [RecordFormat::MessagePack, RecordFormat::Json] {
+            let mut bytes = record(format, Some(VERSION));
+            bytes.truncate(bytes.len() - 4);
+            let mut values = Values::new(bytes);
+            values.version();
+            assert!(values.next::<Record>().is_ok());
+            assert!(values.next::<Entry>().is_err());
+            assert!(!values.finished());
+        }
+    }
+
+    #[test]
+    fn keys_outside_the_root_are_absolute() {
+        let inside = Key::new(Path::new("src"), Path::new("src/fx/sky.frag"));
+        assert_eq!(inside.path, Path::new("fx/sky.frag"));
+        assert_eq!(
+            inside.resolve(Path::new("elsewhere")),
+            Path::new("elsewhere/fx/sky.frag")
+        );
+        let outside = Key::new(Path::new("src"), Path::new("include/common.glsl"));
+        assert!(!outside.stripped);
+        assert!(outside.path.is_absolute());
+        assert!(outside.path.ends_with("include/common.glsl"));
+        assert_eq!(outside.resolve(Path::new("elsewhere")), outside.path);
+        // Stored as bare paths, which say which they were.
+        assert_eq!(Key::from(PathBuf::from(inside.clone())), inside);
+        assert_eq!(Key::from(PathBuf::from(outside.clone())), outside);
+    }
+
+    #[test]
+    fn a_record_for_another_target_is_thrown_away() {
+        let dir =
+            std::env::temp_dir().join(format!("wrangler-record-target-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let shader = dir.join("sky.frag");
+        fs::write(&shader, "").unwrap();
+        let mut instructions = Instructions {
+            search_root: dir.clone(),
+            output_root: dir.clone(),
+            record_path: dir.join("record"),
+            ..Default::default()
+        };
+        let mut record = Record::try_load(&instructions).unwrap();
+        record
+            .log(&shader, &[], &[dir.join("sky.spv_frag")])
+            .unwrap();
+        record.write().unwrap();
+        assert_eq!(Record::try_load(&instructions).unwrap().shaders(), [shader]);
+
+        instructions.target_env = crate::TargetEnv::Vulkan1_3;
+        assert!(Record::try_load(&instructions)
+            .unwrap()
+            .shaders()
+            .is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,415 @@
+// A tiny protocol for handing compiles off to a machine that has shaderc installed.  Messages are
+// MessagePack values over a TCP stream, each preceded by its length as a little-endian u32 and
+// no longer than `MAX_FRAME`.  Requests are addressed by a hash of everything that determines the
+// output, so the client only uploads source the server hasn't seen.
+//
+// Before anything else, the server sends a challenge and the client answers with its BLAKE3 hash
+// keyed with the key they share.  A connection that answers wrong, or not in time, is dropped
+// without a byte of it being decoded.  Challenges are never reused, so a recorded answer is no
+// good later.  Nothing after that is encrypted or authenticated, so don't serve across a network
+// you don't trust.
+
+use crate::{kind_ext, kind_from_ext, CompileSettings, Error, Result, ShaderKind};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+type Hash = [u8; 32];
+
+/// The longest message either side sends or takes, in bytes.
+const MAX_FRAME: usize = 64 << 20;
+/// How many bytes of compiled modules and warnings the server keeps, between all connections.
+const CACHE_BYTES: usize = 256 << 20;
+/// How long a client has to answer the challenge.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize)]
+struct Request {
+    hash: Hash,
+    name: String,
+    kind: String,
+    entry_point: String,
+    settings: CompileSettings,
+    source: Option<String>,
+}
+
+/// Mirrors `shaderc::Error` so a failure on the server looks the same as one on the client.
+#[derive(Serialize, Deserialize)]
+enum Failure {
+    Compilation(u32, String),
+    Internal(String),
+    InvalidStage(String),
+    InvalidAssembly(String),
+    NullResultObject(String),
+}
+
+impl From<shaderc::Error> for Failure {
+    fn from(e: shaderc::Error) -> Failure {
+        match e {
+            shaderc::Error::CompilationError(n, s) => Failure::Compilation(n, s),
+            shaderc::Error::InternalError(s) => Failure::Internal(s),
+            shaderc::Error::InvalidStage(s) => Failure::InvalidStage(s),
+            shaderc::Error::InvalidAssembly(s) => Failure::InvalidAssembly(s),
+            shaderc::Error::NullResultObject(s) => Failure::NullResultObject(s),
+        }
+    }
+}
+
+impl From<Failure> for shaderc::Error {
+    fn from(f: Failure) -> shaderc::Error {
+        match f {
+            Failure::Compilation(n, s) => shaderc::Error::CompilationError(n, s),
+            Failure::Internal(s) => shaderc::Error::InternalError(s),
+            Failure::InvalidStage(s) => shaderc::Error::InvalidStage(s),
+            Failure::InvalidAssembly(s) => shaderc::Error::InvalidAssembly(s),
+            Failure::NullResultObject(s) => shaderc::Error::NullResultObject(s),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+enum Response {
+    /// The module and the text of the compiler's warnings.
+    Compiled(Vec<u32>, String),
+    Failed(Failure),
+    /// The server has nothing cached under the hash; send the request again with its source.
+    NeedSource,
+    /// The request itself made no sense to the server.
+    Rejected(String),
+}
+
+fn request_hash(kind: &str, entry_point: &str, settings: &CompileSettings, source: &str) -> Hash {
+    let mut hasher = blake3::Hasher::new();
+    let settings = format!("{:?}", settings);
+    for part in [kind, entry_point, &settings, source].iter() {
+        hasher.update(part.as_bytes());
+        hasher.update(&[0]);
+    }
+    *hasher.finalize().as_bytes()
+}
+
+fn send(stream: &mut TcpStream, message: &impl Serialize) -> Result<()> {
+    let bytes = rmp_serde::to_vec(message).map_err(|e| Error::Remote(e.to_string()))?;
+    if bytes.len() > MAX_FRAME {
+        return Err(Error::Remote(format!(
+            "a message of {} bytes is longer than the limit of {}",
+            bytes.len(),
+            MAX_FRAME
+        )));
+    }
+    let lost = |e: std::io::Error| Error::Remote(e.to_string());
+    stream
+        .write_all(&(bytes.len() as u32).to_le_bytes())
+        .map_err(lost)?;
+    stream.write_all(&bytes).map_err(lost)
+}
+
+fn receive<T: for<'de> Deserialize<'de>>(stream: &mut TcpStream) -> Result<T> {
+    let lost = |e: std::io::Error| Error::Remote(e.to_string());
+    let mut len = [0; 4];
+    stream.read_exact(&mut len).map_err(lost)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME {
+        return Err(Error::Remote(format!(
+            "a message of {} bytes is longer than the limit of {}",
+            len, MAX_FRAME
+        )));
+    }
+    let mut bytes = vec![0; len];
+    stream.read_exact(&mut bytes).map_err(lost)?;
+    rmp_serde::from_slice(&bytes).map_err(|e| Error::Remote(e.to_string()))
+}
+
+/// A challenge no other connection to this server has been or will be sent.
+fn challenge() -> Hash {
+    use std::hash::{BuildHasher, Hasher};
+    static SENT: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = blake3::Hasher::new();
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    hasher.update(&now.as_nanos().to_le_bytes());
+    hasher.update(&SENT.fetch_add(1, Ordering::Relaxed).to_le_bytes());
+    // Seeded randomly for each process.
+    let seed = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    hasher.update(&seed.to_le_bytes());
+    *hasher.finalize().as_bytes()
+}
+
+/// Answers the server's challenge with `key`.
+fn answer(stream: &mut TcpStream, key: &[u8; 32]) -> Result<()> {
+    let lost = |e: std::io::Error| Error::Remote(format!("the handshake failed: {}", e));
+    let mut challenge = [0; 32];
+    stream.read_exact(&mut challenge).map_err(lost)?;
+    stream
+        .write_all(blake3::keyed_hash(key, &challenge).as_bytes())
+        .map_err(lost)
+}
+
+/// Challenges a client to prove it has `key`.
+fn authenticate(stream: &mut TcpStream, key: &[u8; 32]) -> Result<()> {
+    let lost = |e: std::io::Error| Error::Remote(format!("the handshake failed: {}", e));
+    let challenge = challenge();
+    stream
+        .set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+        .map_err(lost)?;
+    stream.write_all(&challenge).map_err(lost)?;
+    let mut answer = [0; 32];
+    stream.read_exact(&mut answer).map_err(lost)?;
+    // Comparing `blake3::Hash`es takes the same time however many bytes match.
+    if blake3::Hash::from(answer) != blake3::keyed_hash(key, &challenge) {
+        return Err(Error::Remote(
+            "a client answered the challenge without the key".into(),
+        ));
+    }
+    stream.set_read_timeout(None).map_err(lost)
+}
+
+/// A connection to a compile server, reused for every file in a run.
+pub(crate) struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    pub fn connect(address: &str, key: &[u8; 32]) -> Result<Client> {
+        let mut stream = TcpStream::connect(address)
+            .map_err(|e| Error::Remote(format!("couldn't connect to {}: {}", address, e)))?;
+        answer(&mut stream, key)?;
+        Ok(Client { stream })
+    }
+
+    pub fn compile(
+        &mut self,
+        source: &str,
+        kind: ShaderKind,
+        name: &str,
+        entry_point: &str,
+        settings: &CompileSettings,
+    ) -> Result<(Vec<u32>, String)> {
+        let kind = kind_ext(&kind)?.to_owned();
+        let mut request = Request {
+            hash: request_hash(&kind, entry_point, settings, source),
+            name: name.to_owned(),
+            kind,
+            entry_point: entry_point.to_owned(),
+            settings: settings.clone(),
+            source: None,
+        };
+        loop {
+            send(&mut self.stream, &request)?;
+            match receive(&mut self.stream)? {
+                Response::Compiled(spirv, warnings) => return Ok((spirv, warnings)),
+                Response::Failed(failure) => return Err(shaderc::Error::from(failure).into()),
+                Response::NeedSource if request.source.is_none() => {
+                    request.source = Some(source.to_owned());
+                }
+                Response::NeedSource => {
+                    return Err(Error::Remote("server ignored the uploaded source".into()))
+                }
+                Response::Rejected(reason) => return Err(Error::Remote(reason)),
+            }
+        }
+    }
+}
+
+/// Compiled modules and their warnings by request, dropping the least recently used once they
+/// take up more than `limit` bytes.
+struct Cache {
+    entries: HashMap<Hash, Cached>,
+    bytes: usize,
+    limit: usize,
+    /// Counts up with every use, for telling which entry was used least recently.
+    clock: u64,
+}
+
+struct Cached {
+    spirv: Vec<u32>,
+    warnings: String,
+    used: u64,
+}
+
+impl Cached {
+    fn bytes(&self) -> usize {
+        self.spirv.len() * 4 + self.warnings.len()
+    }
+}
+
+impl Cache {
+    fn new(limit: usize) -> Cache {
+        Cache {
+            entries: HashMap::new(),
+            bytes: 0,
+            limit,
+            clock: 0,
+        }
+    }
+
+    fn get(&mut self, hash: &Hash) -> Option<(Vec<u32>, String)> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(hash)?;
+        entry.used = self.clock;
+        Some((entry.spirv.clone(), entry.warnings.clone()))
+    }
+
+    fn insert(&mut self, hash: Hash, spirv: Vec<u32>, warnings: String) {
+        self.clock += 1;
+        let entry = Cached {
+            spirv,
+            warnings,
+            used: self.clock,
+        };
+        self.bytes += entry.bytes();
+        if let Some(old) = self.entries.insert(hash, entry) {
+            self.bytes -= old.bytes();
+        }
+        while self.bytes > self.limit {
+            let oldest = match self.entries.iter().min_by_key(|(_, entry)| entry.used) {
+                Some((hash, _)) => *hash,
+                None => break,
+            };
+            let evicted = self.entries.remove(&oldest).unwrap();
+            self.bytes -= evicted.bytes();
+        }
+    }
+}
+
+fn handle(mut stream: TcpStream, key: &[u8; 32], cache: Arc<Mutex<Cache>>) -> Result<()> {
+    authenticate(&mut stream, key)?;
+    let compiler = shaderc::Compiler::new().ok_or(Error::CompilerInit)?;
+    loop {
+        let request: Request = match receive(&mut stream) {
+            Ok(request) => request,
+            // The client hung up.
+            Err(_) => return Ok(()),
+        };
+        let cached = cache.lock().unwrap().get(&request.hash);
+        let response = match (cached, &request.source, kind_from_ext(&request.kind)) {
+            (Some((spirv, warnings)), _, _) => Response::Compiled(spirv, warnings),
+            (None, None, _) => Response::NeedSource,
+            (None, Some(_), None) => Response::Rejected(format!("unknown kind `{}`", request.kind)),
+            (None, Some(source), _)
+                if request_hash(
+                    &request.kind,
+                    &request.entry_point,
+                    &request.settings,
+                    source,
+                ) != request.hash =>
+            {
+                Response::Rejected("source does not match its hash".into())
+            }
+            (None, Some(source), Some(kind)) => {
+                let (packages, resolved) = Default::default();
+                let options = request
+                    .settings
+                    .to_options(&[], &packages, None, &resolved)?;
+                match compiler.compile_into_spirv(
+                    source,
+                    kind,
+                    &request.name,
+                    &request.entry_point,
+                    Some(&options),
+                ) {
+                    Ok(artifact) => {
+                        let spirv = artifact.as_binary().to_vec();
+                        let warnings = artifact.get_warning_messages();
+                        cache
+                            .lock()
+                            .unwrap()
+                            .insert(request.hash, spirv.clone(), warnings.clone());
+                        Response::Compiled(spirv, warnings)
+                    }
+                    Err(e) => Response::Failed(e.into()),
+                }
+            }
+        };
+        send(&mut stream, &response)?;
+    }
+}
+
+/// Serves compile requests from `run` invocations whose `remote_compiler` points here, and whose
+/// `remote_key` is `key`, until the listener fails.  Each connection gets its own thread and
+/// compiler; compiled modules are cached in memory, up to `CACHE_BYTES`, and shared between
+/// connections.
+pub fn serve(address: impl ToSocketAddrs, key: [u8; 32]) -> Result<()> {
+    let listener =
+        TcpListener::bind(address).map_err(|e| Error::Remote(format!("couldn't listen: {}", e)))?;
+    let cache = Arc::new(Mutex::new(Cache::new(CACHE_BYTES)));
+    for stream in listener.incoming() {
+        let stream = stream.map_err(|e| Error::Remote(format!("couldn't accept: {}", e)))?;
+        let cache = cache.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = handle(stream, &key, cache) {
+                crate::warn(format_args!("remote compile connection failed: {}", e));
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Both ends of a loopback connection.
+    fn connection() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (client, listener.accept().unwrap().0)
+    }
+
+    #[test]
+    fn only_clients_with_the_key_get_in() {
+        for (client_key, accepted) in [([7; 32], true), ([8; 32], false)] {
+            let (mut client, mut server) = connection();
+            let answering = std::thread::spawn(move || answer(&mut client, &client_key));
+            assert_eq!(authenticate(&mut server, &[7; 32]).is_ok(), accepted);
+            answering.join().unwrap().unwrap();
+        }
+    }
+
+    #[test]
+    fn challenges_are_never_repeated() {
+        assert_ne!(challenge(), challenge());
+    }
+
+    #[test]
+    fn frames_past_the_limit_are_refused_unread() {
+        let (mut client, mut server) = connection();
+        send(&mut client, &Response::NeedSource).unwrap();
+        assert!(matches!(
+            receive(&mut server).unwrap(),
+            Response::NeedSource
+        ));
+        client
+            .write_all(&(MAX_FRAME as u32 + 1).to_le_bytes())
+            .unwrap();
+        match receive::<Request>(&mut server) {
+            Err(Error::Remote(reason)) => assert!(reason.contains("longer than the limit")),
+            _ => panic!("took an overlong frame"),
+        }
+    }
+
+    #[test]
+    fn the_cache_drops_what_was_used_least_recently() {
+        let mut cache = Cache::new(12);
+        cache.insert([1; 32], vec![1], String::new());
+        cache.insert([2; 32], vec![2], String::new());
+        cache.insert([3; 32], vec![3], String::new());
+        assert!(cache.get(&[1; 32]).is_some());
+        cache.insert([4; 32], vec![4], String::new());
+        assert!(cache.get(&[2; 32]).is_none());
+        assert!(cache.get(&[1; 32]).is_some());
+        assert!(cache.get(&[3; 32]).is_some());
+        assert!(cache.get(&[4; 32]).is_some());
+        assert_eq!(cache.bytes, 12);
+        // An entry bigger than the whole cache doesn't stay.
+        cache.insert([5; 32], vec![0; 4], String::new());
+        assert!(cache.entries.is_empty());
+    }
+}
@@ -0,0 +1,158 @@
+// What `run_report` hands back: the fate of every shader a run looked at.  The same can be
+// written out as JSON for build dashboards, via `json_report` or `CompilationReport::write_json`.
+
+use crate::{Churn, Diagnostic, Error, IoContext, Result, ShaderKind};
+use serde::{Serialize, Serializer};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    Compiled,
+    /// Skipped because the record says nothing changed.
+    UpToDate,
+    Failed,
+    /// Copied from `ship_from` instead of compiled.
+    #[cfg(feature = "ship")]
+    Shipped,
+}
+
+/// One shader found during a run.
+#[derive(Debug, Serialize)]
+pub struct FileReport {
+    pub source: PathBuf,
+    #[serde(serialize_with = "debug")]
+    pub kind: ShaderKind,
+    /// The artifacts the shader was compiled to, this run or, if it was up to date, the last one
+    /// the record knows of.
+    pub outputs: Vec<PathBuf>,
+    pub status: FileStatus,
+    /// Time spent compiling and writing the shader; zero if it was up to date.
+    #[serde(rename = "duration_ms", serialize_with = "milliseconds")]
+    pub duration: Duration,
+    /// What the compiler warned about this run.
+    pub warnings: Vec<Diagnostic>,
+    /// What went wrong, for failures.  Errors that don't come from the compiler are a single
+    /// diagnostic about the source, with no line.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Everything a run did.
+#[derive(Debug, Default, Serialize)]
+pub struct CompilationReport {
+    /// Up-to-date shaders in discovery order, then the rest in the order they were compiled.
+    pub files: Vec<FileReport>,
+    /// Every error the run collected, including ones that aren't about a single shader, like
+    /// naming violations.  These are what `run` would return in a `BatchError`.  They are
+    /// serialized as their messages.
+    #[serde(serialize_with = "messages")]
+    pub errors: Vec<Error>,
+    /// How each manifest the run wrote differs from the one before it, with `write_manifest`.
+    pub churn: Vec<Churn>,
+}
+
+impl CompilationReport {
+    pub fn compiled(&self) -> impl Iterator<Item = &FileReport> {
+        self.with_status(FileStatus::Compiled)
+    }
+
+    pub fn up_to_date(&self) -> impl Iterator<Item = &FileReport> {
+        self.with_status(FileStatus::UpToDate)
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &FileReport> {
+        self.with_status(FileStatus::Failed)
+    }
+
+    /// How many warnings the run's compiles produced, over every shader.
+    pub fn warning_count(&self) -> usize {
+        self.files.iter().map(|f| f.warnings.len()).sum()
+    }
+
+    /// Writes the whole report to `path` as JSON.  Unlike `json_report`, which is written for
+    /// each set of instructions, this covers every set the report was collected over.
+    pub fn write_json(&self, path: &Path) -> Result<()> {
+        write(path, self)
+    }
+
+    fn with_status(&self, status: FileStatus) -> impl Iterator<Item = &FileReport> {
+        self.files.iter().filter(move |f| f.status == status)
+    }
+}
+
+fn debug<S: Serializer>(
+    value: &impl std::fmt::Debug,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_str(&format_args!("{:?}", value))
+}
+
+fn milliseconds<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+}
+
+// serde hands `serialize_with` the field itself.
+#[allow(clippy::ptr_arg)]
+fn messages<S: Serializer>(
+    errors: &Vec<Error>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_seq(errors.iter().map(|e| e.to_string()))
+}
+
+fn write(path: &Path, value: &impl Serialize) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("create directory", parent)?;
+    }
+    let text = serde_json::to_string_pretty(value).context("encode", path)?;
+    fs::write(path, text).context("write", path)?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    wrangler: &'static str,
+    options_hash: &'a str,
+    #[serde(rename = "duration_ms", serialize_with = "milliseconds")]
+    duration: Duration,
+    discovered: usize,
+    up_to_date: usize,
+    compiled: usize,
+    failed: usize,
+    #[cfg(feature = "ship")]
+    shipped: usize,
+    files: &'a [FileReport],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    churn: &'a [Churn],
+}
+
+/// Writes `files`, the shaders one set of instructions found, to `path` as JSON, along with how
+/// its manifest changed.
+pub(crate) fn write_json(
+    path: &Path,
+    files: &[FileReport],
+    churn: &[Churn],
+    options_hash: &str,
+    duration: Duration,
+) -> Result<()> {
+    let count = |status| files.iter().filter(|f| f.status == status).count();
+    let report = JsonReport {
+        wrangler: env!("CARGO_PKG_VERSION"),
+        options_hash,
+        duration,
+        discovered: files.len(),
+        up_to_date: count(FileStatus::UpToDate),
+        compiled: count(FileStatus::Compiled),
+        failed: count(FileStatus::Failed),
+        #[cfg(feature = "ship")]
+        shipped: count(FileStatus::Shipped),
+        files,
+        churn,
+    };
+    write(path, &report)
+}
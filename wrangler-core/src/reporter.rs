@@ -0,0 +1,105 @@
+// Hooks for showing a run's progress, e.g. in a custom build system's UI.  With the `indicatif`
+// feature, `ProgressBar` is a ready-made one for the terminal.
+
+use crate::FileStatus;
+#[cfg(feature = "indicatif")]
+use std::cell::Cell;
+use std::path::Path;
+
+/// Hears about a run as it goes.  Every method does nothing by default, and like `Telemetry`'s
+/// they take `&self`, so implementations that keep state need interior mutability.
+///
+/// A run calls `on_discovered` once the search is done and `on_pending` once it knows which
+/// shaders are out of date, then `on_compile_start` and `on_compile_finish` around each of
+/// those, and `on_run_finish` at the end.  Runs that `run_all` chains together each do all of
+/// this.  `compile_iter` only calls the compile hooks.
+pub trait Reporter {
+    /// `count` shaders were found under `search_root`.
+    fn on_discovered(&self, _count: usize) {}
+    /// `count` of the shaders found will be compiled.
+    fn on_pending(&self, _count: usize) {}
+    fn on_compile_start(&self, _path: &Path) {}
+    /// `status` is never `UpToDate`.
+    fn on_compile_finish(&self, _path: &Path, _status: FileStatus) {}
+    fn on_run_finish(&self) {}
+}
+
+/// Draws a progress bar on stderr with the shader being compiled and how many have failed so
+/// far.  Nothing is drawn when stderr isn't a terminal, or for runs with nothing to compile.
+#[cfg(feature = "indicatif")]
+pub struct ProgressBar {
+    bar: indicatif::ProgressBar,
+    failed: Cell<usize>,
+}
+
+#[cfg(feature = "indicatif")]
+impl ProgressBar {
+    pub fn new() -> ProgressBar {
+        let bar = indicatif::ProgressBar::hidden();
+        let style = indicatif::ProgressStyle::with_template("[{bar:30}] {pos}/{len} {wide_msg}")
+            .unwrap()
+            .progress_chars("=> ");
+        bar.set_style(style);
+        ProgressBar {
+            bar,
+            failed: Cell::new(0),
+        }
+    }
+
+    fn show(&self, shader: &str) {
+        match self.failed.get() {
+            0 => self.bar.set_message(shader.to_owned()),
+            failed => self
+                .bar
+                .set_message(format!("{} ({} failed)", shader, failed)),
+        }
+    }
+}
+
+#[cfg(feature = "indicatif")]
+impl Default for ProgressBar {
+    fn default() -> ProgressBar {
+        ProgressBar::new()
+    }
+}
+
+#[cfg(feature = "indicatif")]
+impl Reporter for ProgressBar {
+    fn on_pending(&self, count: usize) {
+        if count == 0 {
+            return;
+        }
+        // Each set of instructions `run_all` goes through gets a bar of its own.
+        self.bar.reset();
+        self.bar.set_length(count as u64);
+        self.bar
+            .set_draw_target(indicatif::ProgressDrawTarget::stderr());
+        self.failed.set(0);
+    }
+
+    fn on_compile_start(&self, path: &Path) {
+        self.show(&path.to_string_lossy());
+    }
+
+    fn on_compile_finish(&self, path: &Path, status: FileStatus) {
+        if status == FileStatus::Failed {
+            self.failed.set(self.failed.get() + 1);
+        }
+        self.show(&path.to_string_lossy());
+        self.bar.inc(1);
+    }
+
+    fn on_run_finish(&self) {
+        if self.bar.is_hidden() {
+            return;
+        }
+        match self.failed.get() {
+            0 => self.bar.finish_with_message("done"),
+            failed => self
+                .bar
+                .finish_with_message(format!("done, {} failed", failed)),
+        }
+        self.bar
+            .set_draw_target(indicatif::ProgressDrawTarget::hidden());
+    }
+}
@@ -0,0 +1,449 @@
+// Collects the descriptor bindings of every artifact in `output_root` into one report, so the
+// binding space a project uses can be audited in one place rather than shader by shader.
+//
+// Artifacts are read back from disk rather than taken from the run that just finished, which
+// keeps the report complete when only a few shaders were out of date.
+//
+// The same bindings, along with push constant and workgroup sizes, are checked against device
+// profiles: JSON files giving some of a device's `VkPhysicalDeviceLimits`, by their Vulkan
+// names, under `limits`:
+//
+//     { "name": "mobile", "limits": { "maxPushConstantsSize": 128,
+//                                     "maxComputeWorkGroupSize": [256, 256, 64] } }
+//
+// Limits a profile leaves out aren't checked.
+
+use crate::spirv::{self, Binding, DescriptorKind};
+use crate::{
+    current_artifact, find_shaders, hashed, kind_ext, warn, Error, Instructions, IoContext, Result,
+    ShaderKind,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize)]
+struct ShaderResources {
+    path: PathBuf,
+    kind: &'static str,
+    bindings: Vec<Binding>,
+}
+
+/// Everything bound at one set and binding, across all shaders.
+#[derive(Serialize)]
+struct Slot {
+    set: u32,
+    binding: u32,
+    kinds: Vec<DescriptorKind>,
+    shaders: Vec<PathBuf>,
+}
+
+#[derive(Serialize)]
+struct Report {
+    shaders: Vec<ShaderResources>,
+    slots: Vec<Slot>,
+}
+
+/// Reads an artifact back into words, whichever byte order it was written in.
+pub(crate) fn read_words(path: &Path) -> Option<Vec<u32>> {
+    let bytes = fs::read(path).ok()?;
+    if bytes.len() < 4 || bytes.len() % 4 != 0 {
+        return None;
+    }
+    let chunks = bytes.chunks_exact(4).map(|c| [c[0], c[1], c[2], c[3]]);
+    if u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) == spirv::MAGIC {
+        Some(chunks.map(u32::from_le_bytes).collect())
+    } else {
+        Some(chunks.map(u32::from_be_bytes).collect())
+    }
+}
+
+/// How many descriptors of any of `kinds` `bindings` take, or `None` if a runtime-sized array
+/// means it could be any number.
+fn used(bindings: &[Binding], kinds: &[DescriptorKind]) -> Option<u32> {
+    let bindings = bindings.iter().filter(|b| kinds.contains(&b.kind));
+    if bindings.clone().any(|b| b.count == 0) {
+        None
+    } else {
+        Some(bindings.map(|b| b.count).sum::<u32>())
+    }
+}
+
+fn describe(used: Option<u32>) -> String {
+    used.map_or_else(|| "an unbounded number of".into(), |n| n.to_string())
+}
+
+fn check_budget(instructions: &Instructions, shader: &ShaderResources) {
+    for &(kind, limit) in instructions.descriptor_budget.iter() {
+        // A runtime-sized array could be any size, so it always counts as over budget.
+        let used = used(&shader.bindings, &[kind]);
+        if !matches!(used, Some(used) if used <= limit) {
+            warn(format_args!(
+                "{} uses {} {:?} descriptors, over the budget of {}",
+                shader.path.display(),
+                describe(used),
+                kind,
+                limit
+            ));
+        }
+    }
+}
+
+/// The limits in a device profile, named as in `VkPhysicalDeviceLimits`.
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Limits {
+    max_bound_descriptor_sets: Option<u32>,
+    max_push_constants_size: Option<u32>,
+    max_per_stage_descriptor_samplers: Option<u32>,
+    max_per_stage_descriptor_uniform_buffers: Option<u32>,
+    max_per_stage_descriptor_storage_buffers: Option<u32>,
+    max_per_stage_descriptor_sampled_images: Option<u32>,
+    max_per_stage_descriptor_storage_images: Option<u32>,
+    max_compute_work_group_size: Option<[u32; 3]>,
+    max_compute_work_group_invocations: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct Profile {
+    name: Option<String>,
+    #[serde(default)]
+    limits: Limits,
+}
+
+fn load_profile(path: &Path) -> Result<Profile> {
+    let text = fs::read_to_string(path).context("read", path)?;
+    let mut profile: Profile = serde_json::from_str(&text)
+        .map_err(|e| Error::BadProfile(path.to_owned(), e.to_string()))?;
+    if profile.name.is_none() {
+        profile.name = Some(path.display().to_string());
+    }
+    Ok(profile)
+}
+
+/// Lists how a module with `words` as its artifact goes over `limits`.
+fn over_limits(kind: ShaderKind, words: &[u32], limits: &Limits) -> Vec<String> {
+    use DescriptorKind::*;
+    let mut over = Vec::new();
+    let bindings = spirv::bindings(words);
+    if let Some(limit) = limits.max_bound_descriptor_sets {
+        if let Some(set) = bindings.iter().map(|b| b.set).max() {
+            if set >= limit {
+                over.push(format!(
+                    "set {}, past maxBoundDescriptorSets {}",
+                    set, limit
+                ));
+            }
+        }
+    }
+    if let Some(limit) = limits.max_push_constants_size {
+        let size = spirv::push_constant_size(words);
+        if size > limit {
+            over.push(format!(
+                "{} bytes of push constants, over maxPushConstantsSize {}",
+                size, limit
+            ));
+        }
+    }
+    // Which kinds count against each limit, as the Vulkan spec has it.
+    let per_stage = [
+        (
+            limits.max_per_stage_descriptor_samplers,
+            &[Sampler, CombinedImageSampler][..],
+            "maxPerStageDescriptorSamplers",
+        ),
+        (
+            limits.max_per_stage_descriptor_uniform_buffers,
+            &[UniformBuffer][..],
+            "maxPerStageDescriptorUniformBuffers",
+        ),
+        (
+            limits.max_per_stage_descriptor_storage_buffers,
+            &[StorageBuffer][..],
+            "maxPerStageDescriptorStorageBuffers",
+        ),
+        (
+            limits.max_per_stage_descriptor_sampled_images,
+            &[CombinedImageSampler, SampledImage, UniformTexelBuffer][..],
+            "maxPerStageDescriptorSampledImages",
+        ),
+        (
+            limits.max_per_stage_descriptor_storage_images,
+            &[StorageImage, StorageTexelBuffer][..],
+            "maxPerStageDescriptorStorageImages",
+        ),
+    ];
+    for (limit, kinds, name) in per_stage.iter() {
+        let limit = match limit {
+            Some(limit) => *limit,
+            None => continue,
+        };
+        let used = used(&bindings, kinds);
+        if !matches!(used, Some(used) if used <= limit) {
+            over.push(format!(
+                "{} descriptors, over {} {}",
+                describe(used),
+                name,
+                limit
+            ));
+        }
+    }
+    if kind != ShaderKind::Compute {
+        return over;
+    }
+    if let Some(size) = spirv::workgroup_size(words) {
+        if let Some(max) = limits.max_compute_work_group_size {
+            if size.iter().zip(max.iter()).any(|(n, max)| n > max) {
+                over.push(format!(
+                    "a workgroup of {:?}, over maxComputeWorkGroupSize {:?}",
+                    size, max
+                ));
+            }
+        }
+        if let Some(limit) = limits.max_compute_work_group_invocations {
+            let invocations = size.iter().map(|&n| n as u64).product::<u64>();
+            if invocations > limit as u64 {
+                over.push(format!(
+                    "{} invocations a workgroup, over maxComputeWorkGroupInvocations {}",
+                    invocations, limit
+                ));
+            }
+        }
+    }
+    over
+}
+
+/// Checks every artifact in `output_root` against each of `instructions.device_profiles`,
+/// pushing an error onto `errors` for every shader that goes over one.
+pub(crate) fn check_profiles(instructions: &Instructions, errors: &mut Vec<Error>) -> Result<()> {
+    let profiles = instructions
+        .device_profiles
+        .iter()
+        .map(|path| load_profile(path))
+        .collect::<Result<Vec<_>>>()?;
+    let names = hashed::load(&instructions.output_root);
+    for candidate in find_shaders(instructions)? {
+        let artifact = current_artifact(instructions, &names, &candidate)?;
+        let words = match read_words(&artifact) {
+            Some(words) => words,
+            None => continue,
+        };
+        for profile in profiles.iter() {
+            let over = over_limits(candidate.shader_kind, &words, &profile.limits);
+            if !over.is_empty() {
+                errors.push(Error::OverLimits {
+                    path: candidate.location.clone(),
+                    profile: profile.name.clone().unwrap_or_default(),
+                    over: over.join(", "),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes the binding report for every compiled shader to `path` as JSON, warning about any
+/// shader that goes over `descriptor_budget`.
+pub(crate) fn write(instructions: &Instructions, path: &Path) -> Result<()> {
+    let mut shaders = Vec::new();
+    let mut slots = BTreeMap::<(u32, u32), Slot>::new();
+    let names = hashed::load(&instructions.output_root);
+    for candidate in find_shaders(instructions)? {
+        let artifact = current_artifact(instructions, &names, &candidate)?;
+        let words = match read_words(&artifact) {
+            Some(words) => words,
+            None => continue,
+        };
+        let shader = ShaderResources {
+            path: candidate.location,
+            kind: kind_ext(&candidate.shader_kind)?,
+            bindings: spirv::bindings(&words),
+        };
+        check_budget(instructions, &shader);
+        for binding in shader.bindings.iter() {
+            let slot = slots
+                .entry((binding.set, binding.binding))
+                .or_insert_with(|| Slot {
+                    set: binding.set,
+                    binding: binding.binding,
+                    kinds: Vec::new(),
+                    shaders: Vec::new(),
+                });
+            if !slot.kinds.contains(&binding.kind) {
+                slot.kinds.push(binding.kind);
+            }
+            slot.shaders.push(shader.path.clone());
+        }
+        shaders.push(shader);
+    }
+    let report = Report {
+        shaders,
+        slots: slots.into_values().collect(),
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("create directory", parent)?;
+    }
+    let text = serde_json::to_string_pretty(&report).context("encode", path)?;
+    fs::write(path, text).context("write", path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::spirv::{Decoration, ExecutionMode, Op, StorageClass};
+
+    fn push(words: &mut Vec<u32>, op: Op, operands: &[u32]) {
+        words.push((operands.len() as u32 + 1) << 16 | op as u32);
+        words.extend_from_slice(operands);
+    }
+
+    /// A compute module with a sampler at set 2, binding 0, a uniform buffer at set 0, binding
+    /// 1, 32 bytes of push constants, and a workgroup of 512 by 1 by 1.
+    fn module() -> Vec<u32> {
+        let mut words = vec![spirv::MAGIC, 0x0001_0000, 0, 20, 0];
+        let w = &mut words;
+        push(
+            w,
+            Op::ExecutionMode,
+            &[19, ExecutionMode::LocalSize as u32, 512, 1, 1],
+        );
+        push(w, Op::Decorate, &[3, Decoration::DescriptorSet as u32, 2]);
+        push(w, Op::Decorate, &[3, Decoration::Binding as u32, 0]);
+        push(w, Op::Decorate, &[5, Decoration::Block as u32]);
+        push(w, Op::Decorate, &[7, Decoration::DescriptorSet as u32, 0]);
+        push(w, Op::Decorate, &[7, Decoration::Binding as u32, 1]);
+        push(w, Op::MemberDecorate, &[8, 0, Decoration::Offset as u32, 0]);
+        push(
+            w,
+            Op::MemberDecorate,
+            &[8, 1, Decoration::Offset as u32, 16],
+        );
+        push(w, Op::TypeSampler, &[1]);
+        push(
+            w,
+            Op::TypePointer,
+            &[2, StorageClass::UniformConstant as u32, 1],
+        );
+        push(w, Op::TypeFloat, &[4, 32]);
+        push(w, Op::TypeStruct, &[5, 4]);
+        push(w, Op::TypePointer, &[6, StorageClass::Uniform as u32, 5]);
+        push(w, Op::TypeVector, &[9, 4, 4]);
+        push(w, Op::TypeStruct, &[8, 9, 9]);
+        push(
+            w,
+            Op::TypePointer,
+            &[10, StorageClass::PushConstant as u32, 8],
+        );
+        push(
+            w,
+            Op::Variable,
+            &[2, 3, StorageClass::UniformConstant as u32],
+        );
+        push(w, Op::Variable, &[6, 7, StorageClass::Uniform as u32]);
+        push(
+            w,
+            Op::Variable,
+            &[10, 11, StorageClass::PushConstant as u32],
+        );
+        words
+    }
+
+    /// Instructions finding `fx/blur.comp` under a fresh `dir`, with `module()` as its artifact.
+    fn project(dir: &Path) -> Instructions {
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir.join("src/fx")).unwrap();
+        fs::create_dir_all(dir.join("out/fx")).unwrap();
+        fs::write(dir.join("src/fx/blur.comp"), "").unwrap();
+        let bytes: Vec<u8> = module().iter().flat_map(|w| w.to_le_bytes()).collect();
+        fs::write(dir.join("out/fx/blur.spv_comp"), bytes).unwrap();
+        Instructions {
+            to_compile: vec![ShaderKind::Compute],
+            search_root: dir.join("src"),
+            output_root: dir.join("out"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn the_report_lists_bindings_by_shader_and_by_slot() {
+        let dir = std::env::temp_dir().join(format!("wrangler-resources-{}", std::process::id()));
+        let instructions = project(&dir);
+        let path = dir.join("resources.json");
+        write(&instructions, &path).unwrap();
+        let report: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let shader = &report["shaders"][0];
+        assert_eq!(shader["kind"], "comp");
+        let bindings: Vec<_> = shader["bindings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|b| (b["set"].as_u64().unwrap(), b["kind"].as_str().unwrap()))
+            .collect();
+        assert_eq!(bindings, [(0, "UniformBuffer"), (2, "Sampler")]);
+        let slots = report["slots"].as_array().unwrap();
+        assert_eq!(slots.len(), 2);
+        assert_eq!(slots[1]["set"], 2);
+        assert_eq!(slots[1]["shaders"][0], shader["path"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn limits_are_checked_as_far_as_a_profile_goes() {
+        let words = module();
+        let limits = Limits {
+            max_bound_descriptor_sets: Some(2),
+            max_push_constants_size: Some(16),
+            max_per_stage_descriptor_samplers: Some(0),
+            max_per_stage_descriptor_uniform_buffers: Some(1),
+            max_compute_work_group_size: Some([256, 256, 64]),
+            max_compute_work_group_invocations: Some(256),
+            ..Default::default()
+        };
+        assert_eq!(
+            over_limits(ShaderKind::Compute, &words, &limits),
+            [
+                "set 2, past maxBoundDescriptorSets 2",
+                "32 bytes of push constants, over maxPushConstantsSize 16",
+                "1 descriptors, over maxPerStageDescriptorSamplers 0",
+                "a workgroup of [512, 1, 1], over maxComputeWorkGroupSize [256, 256, 64]",
+                "512 invocations a workgroup, over maxComputeWorkGroupInvocations 256",
+            ]
+        );
+        // Workgroups only matter to compute shaders, and limits left out aren't checked.
+        assert_eq!(over_limits(ShaderKind::Fragment, &words, &limits).len(), 3);
+        assert!(over_limits(ShaderKind::Compute, &words, &Limits::default()).is_empty());
+    }
+
+    #[test]
+    fn shaders_over_a_profile_are_errors() {
+        let dir = std::env::temp_dir().join(format!("wrangler-profiles-{}", std::process::id()));
+        let mut instructions = project(&dir);
+        let mobile = dir.join("mobile.json");
+        let desktop = dir.join("desktop.json");
+        let broken = dir.join("broken.json");
+        fs::write(
+            &mobile,
+            r#"{"name": "mobile", "limits": {"maxPushConstantsSize": 16}}"#,
+        )
+        .unwrap();
+        fs::write(&desktop, r#"{"limits": {"maxPushConstantsSize": 256}}"#).unwrap();
+        fs::write(&broken, r#"{"limits": {"maxPushConstantsSize": "lots"}}"#).unwrap();
+        instructions.device_profiles = vec![mobile, desktop];
+        let mut errors = Vec::new();
+        check_profiles(&instructions, &mut errors).unwrap();
+        match &errors[..] {
+            [Error::OverLimits { path, profile, .. }] => {
+                assert!(path.ends_with("fx/blur.comp"));
+                assert_eq!(profile, "mobile");
+            }
+            errors => panic!("{:?}", errors),
+        }
+        instructions.device_profiles = vec![broken];
+        let result = check_profiles(&instructions, &mut Vec::new());
+        assert!(matches!(result, Err(Error::BadProfile(..))));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
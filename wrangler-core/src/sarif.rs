@@ -0,0 +1,194 @@
+// Writes a run's diagnostics as a SARIF 2.1.0 log, which GitHub code scanning and most other CI
+// systems read to annotate changes at the lines the compiler complained about.
+
+use crate::{Diagnostic, FileReport, IoContext, Result, Severity};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+#[derive(Serialize)]
+struct Log {
+    #[serde(rename = "$schema")]
+    schema: &'static str,
+    version: &'static str,
+    runs: Vec<Run>,
+}
+
+#[derive(Serialize)]
+struct Run {
+    tool: Tool,
+    results: Vec<SarifResult>,
+}
+
+#[derive(Serialize)]
+struct Tool {
+    driver: Driver,
+}
+
+#[derive(Serialize)]
+struct Driver {
+    name: &'static str,
+    version: &'static str,
+}
+
+#[derive(Serialize)]
+struct SarifResult {
+    level: &'static str,
+    message: Message,
+    locations: Vec<Location>,
+}
+
+#[derive(Serialize)]
+struct Message {
+    text: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Location {
+    physical_location: PhysicalLocation,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PhysicalLocation {
+    artifact_location: ArtifactLocation,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    region: Option<Region>,
+}
+
+#[derive(Serialize)]
+struct ArtifactLocation {
+    uri: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Region {
+    start_line: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start_column: Option<u32>,
+}
+
+/// Relative paths stay relative, with forward slashes, so CI resolves them against the checkout.
+fn uri(path: &Path) -> String {
+    let text = path.to_string_lossy().replace('\\', "/");
+    if path.is_absolute() {
+        format!(
+            "file://{}{}",
+            if text.starts_with('/') { "" } else { "/" },
+            text
+        )
+    } else {
+        text
+    }
+}
+
+fn result(file: &FileReport, diagnostic: &Diagnostic) -> SarifResult {
+    // Messages the compiler didn't place anywhere are pinned to the shader they came from.
+    let path = diagnostic.file.as_deref().unwrap_or(&file.source);
+    SarifResult {
+        level: match diagnostic.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        },
+        message: Message {
+            text: diagnostic.message.clone(),
+        },
+        locations: vec![Location {
+            physical_location: PhysicalLocation {
+                artifact_location: ArtifactLocation { uri: uri(path) },
+                region: diagnostic.line.map(|line| Region {
+                    start_line: line,
+                    start_column: diagnostic.column,
+                }),
+            },
+        }],
+    }
+}
+
+/// Writes the errors and warnings in `files` to `path`.
+pub(crate) fn write(path: &Path, files: &[FileReport]) -> Result<()> {
+    let results = files
+        .iter()
+        .flat_map(|file| {
+            let diagnostics = file.diagnostics.iter().chain(file.warnings.iter());
+            diagnostics.map(move |diagnostic| result(file, diagnostic))
+        })
+        .collect();
+    let log = Log {
+        schema: "https://json.schemastore.org/sarif-2.1.0.json",
+        version: "2.1.0",
+        runs: vec![Run {
+            tool: Tool {
+                driver: Driver {
+                    name: "wrangler",
+                    version: env!("CARGO_PKG_VERSION"),
+                },
+            },
+            results,
+        }],
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("create directory", parent)?;
+    }
+    let text = serde_json::to_string_pretty(&log).context("encode", path)?;
+    fs::write(path, text).context("write", path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FileStatus, ShaderKind};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    #[test]
+    fn errors_and_warnings_become_results_at_their_locations() {
+        let diagnostic = |file: Option<&str>, line, severity, message: &str| Diagnostic {
+            file: file.map(PathBuf::from),
+            line,
+            column: line.map(|_| 7),
+            severity,
+            message: message.to_owned(),
+            hints: Vec::new(),
+        };
+        let files = [FileReport {
+            source: "fx/sky.frag".into(),
+            kind: ShaderKind::Fragment,
+            outputs: Vec::new(),
+            status: FileStatus::Failed,
+            duration: Duration::ZERO,
+            warnings: vec![diagnostic(None, None, Severity::Warning, "unused")],
+            diagnostics: vec![diagnostic(
+                Some("/shared/common.glsl"),
+                Some(12),
+                Severity::Error,
+                "undeclared identifier",
+            )],
+        }];
+        let path = std::env::temp_dir().join(format!("wrangler-sarif-{}.json", std::process::id()));
+        write(&path, &files).unwrap();
+        let log: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(log["version"], "2.1.0");
+        let results = log["runs"][0]["results"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+        let error = &results[0]["locations"][0]["physicalLocation"];
+        assert_eq!(results[0]["level"], "error");
+        assert_eq!(results[0]["message"]["text"], "undeclared identifier");
+        assert_eq!(
+            error["artifactLocation"]["uri"],
+            "file:///shared/common.glsl"
+        );
+        assert_eq!(error["region"]["startLine"], 12);
+        assert_eq!(error["region"]["startColumn"], 7);
+        // A message with no place is pinned to the shader, with no region.
+        let warning = &results[1]["locations"][0]["physicalLocation"];
+        assert_eq!(results[1]["level"], "warning");
+        assert_eq!(warning["artifactLocation"]["uri"], "fx/sky.frag");
+        assert!(warning.get("region").is_none());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+// Copies checked-in artifacts into `output_root` instead of compiling, for `ship_from`, so that
+// crates building their shaders this way don't need shaderc, or any compiler, to build.  The
+// artifacts are the `output_root` of a build with `write_manifest`, checked in whole: its
+// manifest says where each shader's artifact is, what it hashes to, and what the source and its
+// includes hashed to when it was compiled.
+//
+// A shader the manifest doesn't list, or whose source or includes no longer hash the same,
+// fails and someone has to build it with shaderc again; an artifact that doesn't match its hash
+// means the checked-in files were tampered with.  A signed manifest is checked first, if there
+// is a key.  Only artifacts ship, not their debug or assembly siblings, and each is logged in the
+// record like a compile so the pack, manifest, and Rust module are written from it as usual.
+
+use crate::manifest::{self, MANIFEST};
+use crate::record::Record;
+use crate::{
+    CompilationCandidate, CompilationReport, Diagnostic, Error, FileError, FileReport, FileStatus,
+    Instructions, IoContext, Result,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+#[derive(Deserialize)]
+struct Shipped {
+    shaders: Vec<ShippedShader>,
+}
+
+/// What shipping a shader needs from its manifest entry; the rest is ignored.
+#[derive(Deserialize)]
+struct ShippedShader {
+    source: PathBuf,
+    output: PathBuf,
+    size: u64,
+    hash: String,
+    #[serde(default)]
+    source_hash: Option<String>,
+    #[serde(default)]
+    includes: Vec<PathBuf>,
+}
+
+/// Reads the manifest in `dir`, by source, checking its signature if there's a key.
+fn read(instructions: &Instructions, dir: &Path) -> Result<HashMap<PathBuf, ShippedShader>> {
+    let path = dir.join(MANIFEST);
+    if let Some(key) = manifest::key(instructions)? {
+        manifest::verify_manifest(&path, &key)?;
+    }
+    let text = fs::read_to_string(&path).context("read", &path)?;
+    let shipped: Shipped =
+        serde_json::from_str(&text).map_err(|e| Error::BadManifest(path.clone(), e.to_string()))?;
+    Ok(shipped
+        .shaders
+        .into_iter()
+        .map(|shader| (shader.source.clone(), shader))
+        .collect())
+}
+
+/// Checks the artifact `dir` has for the shader at `location` and copies it into `output_root`.
+/// Returns where it went, and the files the source included.
+fn ship_one(
+    instructions: &Instructions,
+    dir: &Path,
+    shader: Option<&ShippedShader>,
+    location: &Path,
+) -> Result<(PathBuf, Vec<PathBuf>)> {
+    let shader = shader.ok_or_else(|| Error::NotShipped(location.to_owned()))?;
+    let includes: Vec<PathBuf> = shader
+        .includes
+        .iter()
+        .map(|include| instructions.search_root.join(include))
+        .collect();
+    // An include that's gone counts as a change.
+    let hash = manifest::source_hash(location, &includes).ok();
+    if hash.is_none() || hash != shader.source_hash {
+        return Err(Error::SourceChanged(location.to_owned()));
+    }
+    let from = dir.join(&shader.output);
+    let bytes = fs::read(&from).context("read", &from)?;
+    if bytes.len() as u64 != shader.size || blake3::hash(&bytes).to_hex().as_str() != shader.hash {
+        return Err(Error::BadManifest(
+            dir.join(MANIFEST),
+            format!("the artifact of `{}` doesn't match it", location.display()),
+        ));
+    }
+    let to = instructions.output_root.join(&shader.output);
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).context("create directory", parent)?;
+    }
+    fs::write(&to, &bytes).context("write", &to)?;
+    Ok((to, includes))
+}
+
+/// Ships every shader in `candidates` from `dir`, logging each in `record`.  Shaders that can't
+/// ship are failures in `report`, as though they didn't compile.
+pub(crate) fn ship(
+    instructions: &Instructions,
+    dir: &Path,
+    candidates: &[CompilationCandidate],
+    record: &mut Record,
+    report: &mut CompilationReport,
+) -> Result<()> {
+    let shipped = read(instructions, dir)?;
+    for candidate in candidates.iter() {
+        let timer = Instant::now();
+        let source = candidate
+            .location
+            .strip_prefix(&instructions.search_root)
+            .unwrap();
+        let mut file = FileReport {
+            source: candidate.location.clone(),
+            kind: candidate.shader_kind,
+            outputs: Vec::new(),
+            status: FileStatus::Failed,
+            duration: Default::default(),
+            warnings: Vec::new(),
+            diagnostics: Vec::new(),
+        };
+        match ship_one(instructions, dir, shipped.get(source), &candidate.location) {
+            Ok((artifact, includes)) => {
+                file.outputs.push(artifact);
+                record.log(&candidate.location, &includes, &file.outputs)?;
+                file.status = FileStatus::Shipped;
+            }
+            Err(e) => {
+                file.diagnostics =
+                    vec![Diagnostic::error(Some(file.source.clone()), e.to_string())];
+                report.errors.push(Error::File(Box::new(FileError {
+                    path: file.source.clone(),
+                    kind: file.kind,
+                    source: e,
+                })));
+            }
+        }
+        file.duration = timer.elapsed();
+        instructions.report(|r| r.on_compile_finish(&file.source, file.status));
+        report.files.push(file);
+    }
+    Ok(())
+}
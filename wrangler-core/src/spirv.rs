@@ -0,0 +1,1364 @@
+// Just enough of a SPIR-V reader to answer questions about compiled modules without dragging in
+// a full reflection library.  Everything here works on the raw words shaderc hands back.
+
+use std::collections::{HashMap, HashSet};
+
+pub(crate) const MAGIC: u32 = 0x0723_0203;
+pub(crate) const HEADER_WORDS: usize = 5;
+
+pub(crate) const OP_SOURCE_CONTINUED: u16 = 2;
+pub(crate) const OP_SOURCE: u16 = 3;
+pub(crate) const OP_SOURCE_EXTENSION: u16 = 4;
+pub(crate) const OP_NAME: u16 = 5;
+pub(crate) const OP_MEMBER_NAME: u16 = 6;
+pub(crate) const OP_STRING: u16 = 7;
+pub(crate) const OP_LINE: u16 = 8;
+pub(crate) const OP_EXTENSION: u16 = 10;
+pub(crate) const OP_EXT_INST_IMPORT: u16 = 11;
+pub(crate) const OP_MEMORY_MODEL: u16 = 14;
+pub(crate) const OP_ENTRY_POINT: u16 = 15;
+pub(crate) const OP_EXECUTION_MODE: u16 = 16;
+pub(crate) const OP_CAPABILITY: u16 = 17;
+pub(crate) const OP_TYPE_BOOL: u16 = 20;
+pub(crate) const OP_TYPE_INT: u16 = 21;
+pub(crate) const OP_TYPE_FLOAT: u16 = 22;
+pub(crate) const OP_TYPE_VECTOR: u16 = 23;
+pub(crate) const OP_TYPE_MATRIX: u16 = 24;
+pub(crate) const OP_TYPE_IMAGE: u16 = 25;
+pub(crate) const OP_TYPE_SAMPLER: u16 = 26;
+pub(crate) const OP_TYPE_SAMPLED_IMAGE: u16 = 27;
+pub(crate) const OP_TYPE_ARRAY: u16 = 28;
+pub(crate) const OP_TYPE_RUNTIME_ARRAY: u16 = 29;
+pub(crate) const OP_TYPE_STRUCT: u16 = 30;
+pub(crate) const OP_TYPE_POINTER: u16 = 32;
+#[cfg_attr(feature = "spirv-tools", allow(dead_code))]
+pub(crate) const OP_CONSTANT_TRUE: u16 = 41;
+#[cfg_attr(feature = "spirv-tools", allow(dead_code))]
+pub(crate) const OP_CONSTANT_FALSE: u16 = 42;
+pub(crate) const OP_CONSTANT: u16 = 43;
+pub(crate) const OP_CONSTANT_COMPOSITE: u16 = 44;
+pub(crate) const OP_SPEC_CONSTANT_TRUE: u16 = 48;
+pub(crate) const OP_SPEC_CONSTANT_FALSE: u16 = 49;
+pub(crate) const OP_SPEC_CONSTANT: u16 = 50;
+pub(crate) const OP_SPEC_CONSTANT_COMPOSITE: u16 = 51;
+#[cfg_attr(feature = "spirv-tools", allow(dead_code))]
+pub(crate) const OP_SPEC_CONSTANT_OP: u16 = 52;
+pub(crate) const OP_FUNCTION: u16 = 54;
+pub(crate) const OP_FUNCTION_END: u16 = 56;
+pub(crate) const OP_FUNCTION_CALL: u16 = 57;
+pub(crate) const OP_VARIABLE: u16 = 59;
+pub(crate) const OP_DECORATE: u16 = 71;
+pub(crate) const OP_MEMBER_DECORATE: u16 = 72;
+pub(crate) const OP_NO_LINE: u16 = 317;
+pub(crate) const OP_MODULE_PROCESSED: u16 = 330;
+pub(crate) const OP_EXECUTION_MODE_ID: u16 = 331;
+pub(crate) const OP_TYPE_ACCELERATION_STRUCTURE: u16 = 5341;
+
+/// Everything that may precede the `OpSource` group, in logical layout order.
+const PREAMBLE: &[u16] = &[
+    OP_CAPABILITY,
+    OP_EXTENSION,
+    OP_EXT_INST_IMPORT,
+    OP_MEMORY_MODEL,
+    OP_ENTRY_POINT,
+    OP_EXECUTION_MODE,
+    OP_EXECUTION_MODE_ID,
+    OP_STRING,
+    OP_SOURCE_EXTENSION,
+    OP_SOURCE,
+    OP_SOURCE_CONTINUED,
+];
+
+/// What may come between the `OpSource` group and the `OpModuleProcessed` group.
+const NAMES: &[u16] = &[OP_NAME, OP_MEMBER_NAME, OP_MODULE_PROCESSED];
+
+const DECORATION_SPEC_ID: u32 = 1;
+const DECORATION_BLOCK: u32 = 2;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ROW_MAJOR: u32 = 4;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const BUILT_IN_WORKGROUP_SIZE: u32 = 25;
+
+const EXECUTION_MODE_LOCAL_SIZE: u32 = 17;
+const EXECUTION_MODE_LOCAL_SIZE_ID: u32 = 38;
+
+const STORAGE_CLASS_INPUT: u32 = 1;
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_OUTPUT: u32 = 3;
+const STORAGE_CLASS_PRIVATE: u32 = 6;
+const STORAGE_CLASS_FUNCTION: u32 = 7;
+const STORAGE_CLASS_PUSH_CONSTANT: u32 = 9;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+
+const DIM_BUFFER: u32 = 5;
+
+/// A single instruction within a module.
+pub(crate) struct Instruction<'a> {
+    pub opcode: u16,
+    pub operands: &'a [u32],
+}
+
+/// Walks the instruction stream of a module, stopping early if the stream is malformed.
+pub(crate) fn instructions(words: &[u32]) -> impl Iterator<Item = Instruction<'_>> {
+    let mut rest = words.get(HEADER_WORDS..).unwrap_or(&[]);
+    std::iter::from_fn(move || {
+        let first = *rest.first()?;
+        let count = (first >> 16) as usize;
+        if count == 0 || count > rest.len() {
+            return None;
+        }
+        let inst = Instruction {
+            opcode: (first & 0xffff) as u16,
+            operands: &rest[1..count],
+        };
+        rest = &rest[count..];
+        Some(inst)
+    })
+}
+
+/// Decodes a nul-terminated literal string, returning it along with the number of words it took.
+pub(crate) fn decode_string(operands: &[u32]) -> (String, usize) {
+    let mut bytes = Vec::new();
+    for (i, word) in operands.iter().enumerate() {
+        for byte in word.to_le_bytes().iter() {
+            if *byte == 0 {
+                return (String::from_utf8_lossy(&bytes).into_owned(), i + 1);
+            }
+            bytes.push(*byte);
+        }
+    }
+    (String::from_utf8_lossy(&bytes).into_owned(), operands.len())
+}
+
+/// Lists the capabilities a module declares, by number.
+pub(crate) fn capabilities(words: &[u32]) -> Vec<u32> {
+    instructions(words)
+        .filter(|inst| inst.opcode == OP_CAPABILITY)
+        .filter_map(|inst| inst.operands.first().copied())
+        .collect()
+}
+
+/// Lists the extensions a module declares.
+pub(crate) fn extensions(words: &[u32]) -> Vec<String> {
+    instructions(words)
+        .filter(|inst| inst.opcode == OP_EXTENSION)
+        .map(|inst| decode_string(inst.operands).0)
+        .collect()
+}
+
+/// Encodes `s` as a nul-terminated literal string padded out to a whole number of words.
+pub(crate) fn encode_string(s: &str) -> Vec<u32> {
+    let mut bytes = s.as_bytes().to_vec();
+    bytes.push(0);
+    while bytes.len() & 3 != 0 {
+        bytes.push(0);
+    }
+    bytes
+        .chunks(4)
+        .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
+
+/// Returns the word offset just past the leading run of instructions whose opcodes are in `ops`.
+fn end_of_section(words: &[u32], ops: &[u16]) -> usize {
+    let mut offset = HEADER_WORDS;
+    for inst in instructions(words) {
+        if !ops.contains(&inst.opcode) {
+            break;
+        }
+        offset += inst.operands.len() + 1;
+    }
+    offset
+}
+
+/// Stamps `note` into the module's debug section.  SPIR-V 1.1 and later have `OpModuleProcessed`
+/// for exactly this; older modules get an `OpSourceExtension` instead.
+pub(crate) fn insert_note(words: &mut Vec<u32>, note: &str) {
+    let version = words.get(1).copied().unwrap_or(0);
+    let (opcode, at) = if version >= 0x0001_0100 {
+        let sections: Vec<u16> = PREAMBLE.iter().chain(NAMES.iter()).copied().collect();
+        (OP_MODULE_PROCESSED, end_of_section(words, &sections))
+    } else {
+        (OP_SOURCE_EXTENSION, end_of_section(words, PREAMBLE))
+    };
+    let literal = encode_string(note);
+    let mut inst = vec![((literal.len() as u32 + 1) << 16) | opcode as u32];
+    inst.extend(literal);
+    words.splice(at..at, inst);
+}
+
+/// Returns `words` without the notes `insert_note` stamped in whose text starts with `prefix`.
+pub(crate) fn remove_notes(words: &[u32], prefix: &str) -> Vec<u32> {
+    let mut out = words[..HEADER_WORDS.min(words.len())].to_vec();
+    for inst in instructions(words) {
+        let note = matches!(inst.opcode, OP_MODULE_PROCESSED | OP_SOURCE_EXTENSION)
+            && decode_string(inst.operands).0.starts_with(prefix);
+        if !note {
+            push_instruction(&mut out, inst.opcode, inst.operands);
+        }
+    }
+    out
+}
+
+fn push_instruction(out: &mut Vec<u32>, opcode: u16, operands: &[u32]) {
+    out.push(((operands.len() as u32 + 1) << 16) | opcode as u32);
+    out.extend_from_slice(operands);
+}
+
+/// A scalar type a constant can have.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Scalar {
+    Bool,
+    Int { width: u32, signed: bool },
+    Float { width: u32 },
+}
+
+/// The value of a constant whose value is known, as a bool or else the bits of an integer,
+/// sign-extended to 64 if its type is signed and zero-extended if not.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg(not(feature = "spirv-tools"))]
+enum Known {
+    Bool(bool),
+    Int(u64),
+}
+
+/// `bits` truncated to `width`, then sign- or zero-extended back to 64 bits.
+fn extend(bits: u64, width: u32, signed: bool) -> u64 {
+    if width >= 64 {
+        return bits;
+    }
+    let masked = bits & ((1 << width) - 1);
+    if signed && masked >> (width - 1) & 1 == 1 {
+        masked | !((1 << width) - 1)
+    } else {
+        masked
+    }
+}
+
+/// The literal words of an integer constant.  Types narrower than 32 bits keep their value in
+/// the low bits, with the rest zero if the type is unsigned and copies of the sign bit if not.
+fn int_literal(bits: u64, width: u32, signed: bool) -> Vec<u32> {
+    let bits = extend(bits, width, signed);
+    if width > 32 {
+        vec![bits as u32, (bits >> 32) as u32]
+    } else {
+        vec![bits as u32]
+    }
+}
+
+/// Reads the literal words of an integer constant of the given type.
+#[cfg(not(feature = "spirv-tools"))]
+fn read_int(literal: &[u32], width: u32, signed: bool) -> Option<u64> {
+    let bits = match literal {
+        [low, high, ..] if width > 32 => *low as u64 | (*high as u64) << 32,
+        [word, ..] => *word as u64,
+        [] => return None,
+    };
+    Some(extend(bits, width, signed))
+}
+
+/// Encodes `value` as the literal of a constant of type `ty`, truncating integers to its width.
+fn encode_scalar(value: SpecValue, ty: Scalar) -> Option<Vec<u32>> {
+    match (value, ty) {
+        (SpecValue::Int(i), Scalar::Int { width, signed }) => {
+            Some(int_literal(i as u64, width, signed))
+        }
+        (SpecValue::Float(f), Scalar::Float { width: 64 }) => {
+            let bits = f.to_bits();
+            Some(vec![bits as u32, (bits >> 32) as u32])
+        }
+        (SpecValue::Float(f), Scalar::Float { width: 32 }) => Some(vec![(f as f32).to_bits()]),
+        _ => None,
+    }
+}
+
+/// Works out an `OpSpecConstantOp` whose operands are all known: the scalar integer and boolean
+/// operations, which is what GLSL's expressions over specialization constants come out as.
+/// Anything else, or anything with undefined results like dividing by zero, is left alone.
+#[cfg(not(feature = "spirv-tools"))]
+fn fold(op: u32, ty: Scalar, args: &[(Known, Scalar)]) -> Option<Known> {
+    fn width_of(ty: Scalar) -> u32 {
+        match ty {
+            Scalar::Int { width, .. } => width,
+            _ => 32,
+        }
+    }
+    let bool_at = |i: usize| match args.get(i) {
+        Some((Known::Bool(b), _)) => Some(*b),
+        _ => None,
+    };
+    // The bits of an integer operand, read as unsigned or signed whatever its type says.
+    let uint_at = |i: usize| match args.get(i) {
+        Some((Known::Int(bits), ty)) => Some(extend(*bits, width_of(*ty), false)),
+        _ => None,
+    };
+    let sint_at = |i: usize| match args.get(i) {
+        Some((Known::Int(bits), ty)) => Some(extend(*bits, width_of(*ty), true) as i64),
+        _ => None,
+    };
+    let (width, signed) = match ty {
+        Scalar::Int { width, signed } => (width, signed),
+        Scalar::Bool => (0, false),
+        Scalar::Float { .. } => return None,
+    };
+    let int = |bits: u64| Some(Known::Int(extend(bits, width, signed)));
+    let shift = |i: usize| uint_at(i).filter(|&n| n < width as u64).map(|n| n as u32);
+    let value = match op {
+        // OpUConvert, OpSConvert
+        113 => int(uint_at(0)?),
+        114 => int(sint_at(0)? as u64),
+        // OpSNegate, OpNot
+        126 => int(sint_at(0)?.wrapping_neg() as u64),
+        200 => int(!uint_at(0)?),
+        // OpIAdd, OpISub, OpIMul
+        128 => int(uint_at(0)?.wrapping_add(uint_at(1)?)),
+        130 => int(uint_at(0)?.wrapping_sub(uint_at(1)?)),
+        132 => int(uint_at(0)?.wrapping_mul(uint_at(1)?)),
+        // OpUDiv, OpSDiv, OpUMod, OpSRem, OpSMod
+        134 => int(uint_at(0)?.checked_div(uint_at(1)?)?),
+        135 => int(sint_at(0)?.checked_div(sint_at(1)?)? as u64),
+        137 => int(uint_at(0)?.checked_rem(uint_at(1)?)?),
+        138 => int(sint_at(0)?.checked_rem(sint_at(1)?)? as u64),
+        139 => {
+            let (a, b) = (sint_at(0)?, sint_at(1)?);
+            let r = a.checked_rem(b)?;
+            // The result takes the sign of the divisor.
+            int(if r != 0 && (r < 0) != (b < 0) {
+                r + b
+            } else {
+                r
+            } as u64)
+        }
+        // OpShiftRightLogical, OpShiftRightArithmetic, OpShiftLeftLogical
+        194 => int(uint_at(0)? >> shift(1)?),
+        195 => int((sint_at(0)? >> shift(1)?) as u64),
+        196 => int(uint_at(0)? << shift(1)?),
+        // OpBitwiseOr, OpBitwiseXor, OpBitwiseAnd
+        197 => int(uint_at(0)? | uint_at(1)?),
+        198 => int(uint_at(0)? ^ uint_at(1)?),
+        199 => int(uint_at(0)? & uint_at(1)?),
+        // OpLogicalEqual, OpLogicalNotEqual, OpLogicalOr, OpLogicalAnd, OpLogicalNot
+        164 => Some(Known::Bool(bool_at(0)? == bool_at(1)?)),
+        165 => Some(Known::Bool(bool_at(0)? != bool_at(1)?)),
+        166 => Some(Known::Bool(bool_at(0)? || bool_at(1)?)),
+        167 => Some(Known::Bool(bool_at(0)? && bool_at(1)?)),
+        168 => Some(Known::Bool(!bool_at(0)?)),
+        // OpSelect
+        169 => match bool_at(0)? {
+            true => args.get(1).map(|(value, _)| *value),
+            false => args.get(2).map(|(value, _)| *value),
+        },
+        // OpIEqual, OpINotEqual
+        170 => Some(Known::Bool(uint_at(0)? == uint_at(1)?)),
+        171 => Some(Known::Bool(uint_at(0)? != uint_at(1)?)),
+        // OpUGreaterThan, OpSGreaterThan, OpUGreaterThanEqual, OpSGreaterThanEqual
+        172 => Some(Known::Bool(uint_at(0)? > uint_at(1)?)),
+        173 => Some(Known::Bool(sint_at(0)? > sint_at(1)?)),
+        174 => Some(Known::Bool(uint_at(0)? >= uint_at(1)?)),
+        175 => Some(Known::Bool(sint_at(0)? >= sint_at(1)?)),
+        // OpULessThan, OpSLessThan, OpULessThanEqual, OpSLessThanEqual
+        176 => Some(Known::Bool(uint_at(0)? < uint_at(1)?)),
+        177 => Some(Known::Bool(sint_at(0)? < sint_at(1)?)),
+        178 => Some(Known::Bool(uint_at(0)? <= uint_at(1)?)),
+        179 => Some(Known::Bool(sint_at(0)? <= sint_at(1)?)),
+        _ => None,
+    }?;
+    // A module asking for, say, a bool from `OpIAdd` is broken; leave it to the validator.
+    match (value, ty) {
+        (Known::Bool(_), Scalar::Bool) | (Known::Int(_), Scalar::Int { .. }) => Some(value),
+        _ => None,
+    }
+}
+
+/// Writes the plain constant `id` of type `ty` holding `value`.
+#[cfg(not(feature = "spirv-tools"))]
+fn push_known(out: &mut Vec<u32>, ty: u32, id: u32, scalar: Scalar, value: Known) {
+    match (value, scalar) {
+        (Known::Bool(true), _) => push_instruction(out, OP_CONSTANT_TRUE, &[ty, id]),
+        (Known::Bool(false), _) => push_instruction(out, OP_CONSTANT_FALSE, &[ty, id]),
+        (Known::Int(bits), Scalar::Int { width, signed }) => {
+            let mut operands = vec![ty, id];
+            operands.extend(int_literal(bits, width, signed));
+            push_instruction(out, OP_CONSTANT, &operands);
+        }
+        // `fold` never makes anything else.
+        (Known::Int(_), _) => unreachable!(),
+    }
+}
+
+/// The scalar types `words` declares, by id.
+fn scalar_types(words: &[u32]) -> HashMap<u32, Scalar> {
+    let mut scalars = HashMap::new();
+    for inst in instructions(words) {
+        match (inst.opcode, inst.operands) {
+            (OP_TYPE_BOOL, [id]) => {
+                scalars.insert(*id, Scalar::Bool);
+            }
+            (OP_TYPE_INT, [id, width, signedness]) => {
+                let (width, signed) = (*width, *signedness == 1);
+                scalars.insert(*id, Scalar::Int { width, signed });
+            }
+            (OP_TYPE_FLOAT, [id, width, ..]) => {
+                scalars.insert(*id, Scalar::Float { width: *width });
+            }
+            _ => {}
+        }
+    }
+    scalars
+}
+
+/// Makes the value given for each specialization constant's `SpecId` in `values` its default,
+/// leaving the constants themselves, and everything else, as they are.  Fails with a description
+/// of the problem if a value doesn't fit its constant's type.
+fn set_spec_defaults(
+    words: &[u32],
+    values: &[(u32, SpecValue)],
+) -> std::result::Result<Vec<u32>, String> {
+    let mut spec_ids = HashMap::<u32, u32>::new();
+    for inst in instructions(words) {
+        if let (OP_DECORATE, [target, DECORATION_SPEC_ID, spec_id]) = (inst.opcode, inst.operands) {
+            spec_ids.insert(*target, *spec_id);
+        }
+    }
+    let scalars = scalar_types(words);
+    let value_of = |id: &u32| {
+        let spec_id = spec_ids.get(id)?;
+        values
+            .iter()
+            .find(|(s, _)| s == spec_id)
+            .map(|(s, v)| (*s, *v))
+    };
+
+    let mut out = words[..HEADER_WORDS].to_vec();
+    for inst in instructions(words) {
+        match (inst.opcode, inst.operands) {
+            (OP_SPEC_CONSTANT_TRUE, [_, id]) | (OP_SPEC_CONSTANT_FALSE, [_, id]) => {
+                let opcode = match value_of(id) {
+                    Some((_, SpecValue::Bool(true))) => OP_SPEC_CONSTANT_TRUE,
+                    Some((_, SpecValue::Bool(false))) => OP_SPEC_CONSTANT_FALSE,
+                    Some((spec_id, v)) => {
+                        return Err(format!("constant {} is a bool, not {:?}", spec_id, v))
+                    }
+                    None => inst.opcode,
+                };
+                push_instruction(&mut out, opcode, inst.operands);
+            }
+            (OP_SPEC_CONSTANT, [ty, id, ..]) if value_of(id).is_some() => {
+                let (spec_id, value) = value_of(id).unwrap();
+                let scalar = scalars.get(ty).copied().unwrap_or(Scalar::Int {
+                    width: 32,
+                    signed: false,
+                });
+                let literal = encode_scalar(value, scalar)
+                    .ok_or_else(|| format!("{:?} doesn't fit constant {}", value, spec_id))?;
+                let mut operands = vec![*ty, *id];
+                operands.extend(literal);
+                push_instruction(&mut out, OP_SPEC_CONSTANT, &operands);
+            }
+            (opcode, operands) => push_instruction(&mut out, opcode, operands),
+        }
+    }
+    Ok(out)
+}
+
+/// Turns every specialization constant into a plain constant, using the value given for its
+/// `SpecId` in `values` or else its default, and drops the now meaningless `SpecId` decorations.
+/// What's computed from them is worked out as well, as far as `fold_spec_constants` or, with the
+/// `spirv-tools` feature, spirv-opt's folding pass can.  Fails with a description of the problem
+/// if a value doesn't fit its constant's type, or if spirv-opt rejects the module.
+pub(crate) fn freeze_spec_constants(
+    words: &[u32],
+    values: &[(u32, SpecValue)],
+) -> std::result::Result<Vec<u32>, String> {
+    if words.len() < HEADER_WORDS {
+        return Err("the module is shorter than a SPIR-V header".into());
+    }
+    let words = set_spec_defaults(words, values)?;
+    #[cfg(feature = "spirv-tools")]
+    return optimize_spec_constants(&words);
+    #[cfg(not(feature = "spirv-tools"))]
+    return Ok(fold_spec_constants(&words));
+}
+
+/// Runs spirv-opt's passes that freeze specialization constants at their defaults and then fold
+/// whatever is computed from them, validating the module as they go.
+#[cfg(feature = "spirv-tools")]
+fn optimize_spec_constants(words: &[u32]) -> std::result::Result<Vec<u32>, String> {
+    use spirv_tools::opt::{Optimizer, Passes};
+    use spirv_tools::TargetEnv;
+
+    // Validate against the SPIR-V version the module says it is, rather than a newer one.
+    let env = match words[1] >> 8 & 0xff {
+        0 => TargetEnv::Universal_1_0,
+        1 => TargetEnv::Universal_1_1,
+        2 => TargetEnv::Universal_1_2,
+        3 => TargetEnv::Universal_1_3,
+        4 => TargetEnv::Universal_1_4,
+        5 => TargetEnv::Universal_1_5,
+        _ => TargetEnv::Universal_1_6,
+    };
+    let mut optimizer = spirv_tools::opt::create(Some(env));
+    optimizer
+        .register_pass(Passes::FreezeSpecConstantValue)
+        .register_pass(Passes::FoldSpecConstantOpAndComposite);
+    let mut messages = Vec::new();
+    let mut collect = |m: spirv_tools::error::Message| messages.push(m.message);
+    match optimizer.optimize(words, &mut collect, None) {
+        Ok(binary) => Ok(binary.as_words().to_vec()),
+        Err(e) if messages.is_empty() => Err(format!("spirv-opt: {}", e)),
+        Err(_) => Err(format!("spirv-opt: {}", messages.join("; "))),
+    }
+}
+
+/// Turns every specialization constant into a plain constant holding its default, and drops the
+/// `SpecId` decorations.  Composites and the scalar operations `fold` knows of are worked out
+/// too; any other `OpSpecConstantOp` is kept, which is fine since everything it's computed from
+/// is constant.
+#[cfg(not(feature = "spirv-tools"))]
+fn fold_spec_constants(words: &[u32]) -> Vec<u32> {
+    let scalars = scalar_types(words);
+    // What every constant so far holds, as far as `fold` cares, and which ones are still specs.
+    let mut known = HashMap::<u32, (Known, Scalar)>::new();
+    let mut unfolded = HashSet::<u32>::new();
+    let mut out = words[..HEADER_WORDS].to_vec();
+    for inst in instructions(words) {
+        match (inst.opcode, inst.operands) {
+            (OP_DECORATE, [_, DECORATION_SPEC_ID, _]) => {}
+            (OP_CONSTANT_TRUE, [_, id]) | (OP_CONSTANT_FALSE, [_, id]) => {
+                let value = Known::Bool(inst.opcode == OP_CONSTANT_TRUE);
+                known.insert(*id, (value, Scalar::Bool));
+                push_instruction(&mut out, inst.opcode, inst.operands);
+            }
+            (OP_CONSTANT, [ty, id, literal @ ..]) => {
+                if let Some(&scalar @ Scalar::Int { width, signed }) = scalars.get(ty) {
+                    if let Some(bits) = read_int(literal, width, signed) {
+                        known.insert(*id, (Known::Int(bits), scalar));
+                    }
+                }
+                push_instruction(&mut out, inst.opcode, inst.operands);
+            }
+            (OP_SPEC_CONSTANT_TRUE, [_, id]) | (OP_SPEC_CONSTANT_FALSE, [_, id]) => {
+                let value = inst.opcode == OP_SPEC_CONSTANT_TRUE;
+                known.insert(*id, (Known::Bool(value), Scalar::Bool));
+                let opcode = if value {
+                    OP_CONSTANT_TRUE
+                } else {
+                    OP_CONSTANT_FALSE
+                };
+                push_instruction(&mut out, opcode, inst.operands);
+            }
+            (OP_SPEC_CONSTANT, [ty, id, literal @ ..]) => {
+                if let Some(&scalar @ Scalar::Int { width, signed }) = scalars.get(ty) {
+                    if let Some(bits) = read_int(literal, width, signed) {
+                        known.insert(*id, (Known::Int(bits), scalar));
+                    }
+                }
+                push_instruction(&mut out, OP_CONSTANT, inst.operands);
+            }
+            (OP_SPEC_CONSTANT_COMPOSITE, [_, id, constituents @ ..]) => {
+                if constituents.iter().any(|c| unfolded.contains(c)) {
+                    unfolded.insert(*id);
+                    push_instruction(&mut out, inst.opcode, inst.operands);
+                } else {
+                    push_instruction(&mut out, OP_CONSTANT_COMPOSITE, inst.operands);
+                }
+            }
+            (OP_SPEC_CONSTANT_OP, [ty, id, op, operands @ ..]) => {
+                let args: Option<Vec<_>> = operands.iter().map(|o| known.get(o).copied()).collect();
+                let folded = match (scalars.get(ty), args) {
+                    (Some(&scalar), Some(args)) => fold(*op, scalar, &args).map(|v| (v, scalar)),
+                    _ => None,
+                };
+                match folded {
+                    Some((value, scalar)) => {
+                        known.insert(*id, (value, scalar));
+                        push_known(&mut out, *ty, *id, scalar, value);
+                    }
+                    None => {
+                        unfolded.insert(*id);
+                        push_instruction(&mut out, inst.opcode, inst.operands);
+                    }
+                }
+            }
+            (opcode, operands) => push_instruction(&mut out, opcode, operands),
+        }
+    }
+    out
+}
+
+/// Replaces the string in every `OpName` and `OpMemberName` with whatever `rename` returns for
+/// it.  Entry point names are left alone, since the runtime has to ask for them.  The source
+/// text, file names, and line numbers of plain debug info would give the names away again, so
+/// they're dropped, and a module with extended debug info, which can't simply be dropped, is
+/// refused.
+pub(crate) fn rename_identifiers(
+    words: &[u32],
+    mut rename: impl FnMut(&str) -> String,
+) -> std::result::Result<Vec<u32>, String> {
+    if words.len() < HEADER_WORDS {
+        return Err("the module is shorter than a SPIR-V header".into());
+    }
+    let mut out = words[..HEADER_WORDS].to_vec();
+    for inst in instructions(words) {
+        let ids = match inst.opcode {
+            OP_SOURCE_CONTINUED | OP_SOURCE | OP_STRING | OP_LINE | OP_NO_LINE => continue,
+            OP_EXT_INST_IMPORT => {
+                let (set, _) = decode_string(inst.operands.get(1..).unwrap_or(&[]));
+                if set.contains("DebugInfo") {
+                    return Err(format!("its `{}` debug info can't be obfuscated", set));
+                }
+                0
+            }
+            OP_NAME => 1,
+            OP_MEMBER_NAME => 2,
+            _ => 0,
+        };
+        if ids == 0 || inst.operands.len() <= ids {
+            push_instruction(&mut out, inst.opcode, inst.operands);
+            continue;
+        }
+        let (name, _) = decode_string(&inst.operands[ids..]);
+        let mut operands = inst.operands[..ids].to_vec();
+        if name.is_empty() {
+            operands.extend(encode_string(""));
+        } else {
+            operands.extend(encode_string(&rename(&name)));
+        }
+        push_instruction(&mut out, inst.opcode, &operands);
+    }
+    Ok(out)
+}
+
+/// A value to bake into a specialization constant.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpecValue {
+    Bool(bool),
+    /// Any integer type; truncated to the constant's width.
+    Int(i64),
+    /// A 32 or 64-bit float.
+    Float(f64),
+}
+
+/// What sort of descriptor a resource binding needs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize)]
+pub enum DescriptorKind {
+    Sampler,
+    CombinedImageSampler,
+    SampledImage,
+    StorageImage,
+    UniformTexelBuffer,
+    StorageTexelBuffer,
+    UniformBuffer,
+    StorageBuffer,
+    AccelerationStructure,
+}
+
+/// A resource a module expects at some set and binding.
+#[derive(Clone, Debug, serde::Serialize)]
+pub(crate) struct Binding {
+    pub set: u32,
+    pub binding: u32,
+    pub kind: DescriptorKind,
+    /// How many descriptors the binding takes; zero for a runtime-sized array.
+    pub count: u32,
+    pub name: String,
+}
+
+/// Lists every descriptor-backed resource a module declares.
+pub(crate) fn bindings(words: &[u32]) -> Vec<Binding> {
+    let mut names = HashMap::<u32, String>::new();
+    let mut sets = HashMap::<u32, u32>::new();
+    let mut slots = HashMap::<u32, u32>::new();
+    let mut blocks = HashMap::<u32, u32>::new();
+    let mut types = HashMap::<u32, (u16, Vec<u32>)>::new();
+    let mut constants = HashMap::<u32, u32>::new();
+    let mut variables = Vec::<(u32, u32)>::new();
+    for inst in instructions(words) {
+        match (inst.opcode, inst.operands) {
+            (OP_NAME, [target, rest @ ..]) => {
+                names.insert(*target, decode_string(rest).0);
+            }
+            (OP_DECORATE, [target, DECORATION_DESCRIPTOR_SET, set]) => {
+                sets.insert(*target, *set);
+            }
+            (OP_DECORATE, [target, DECORATION_BINDING, binding]) => {
+                slots.insert(*target, *binding);
+            }
+            (OP_DECORATE, [target, decoration])
+                if *decoration == DECORATION_BLOCK || *decoration == DECORATION_BUFFER_BLOCK =>
+            {
+                blocks.insert(*target, *decoration);
+            }
+            (OP_CONSTANT, [_, id, value, ..]) => {
+                constants.insert(*id, *value);
+            }
+            (OP_VARIABLE, [ty, id, ..]) => variables.push((*ty, *id)),
+            (opcode, [id, rest @ ..]) if (OP_TYPE_IMAGE..=OP_TYPE_POINTER).contains(&opcode) => {
+                types.insert(*id, (opcode, rest.to_vec()));
+            }
+            (OP_TYPE_ACCELERATION_STRUCTURE, [id]) => {
+                types.insert(*id, (OP_TYPE_ACCELERATION_STRUCTURE, Vec::new()));
+            }
+            _ => {}
+        }
+    }
+
+    let mut out = Vec::new();
+    for (ty, id) in variables {
+        let (set, binding) = match (sets.get(&id), slots.get(&id)) {
+            (Some(set), Some(binding)) => (*set, *binding),
+            _ => continue,
+        };
+        let (class, mut pointee) = match types.get(&ty) {
+            Some((OP_TYPE_POINTER, operands)) if operands.len() == 2 => (operands[0], operands[1]),
+            _ => continue,
+        };
+        let mut count = 1;
+        loop {
+            match types.get(&pointee) {
+                Some((OP_TYPE_ARRAY, operands)) if operands.len() == 2 => {
+                    count *= constants.get(&operands[1]).copied().unwrap_or(1);
+                    pointee = operands[0];
+                }
+                Some((OP_TYPE_RUNTIME_ARRAY, operands)) if !operands.is_empty() => {
+                    count = 0;
+                    pointee = operands[0];
+                }
+                _ => break,
+            }
+        }
+        let kind = match types.get(&pointee) {
+            Some((OP_TYPE_SAMPLER, _)) => DescriptorKind::Sampler,
+            Some((OP_TYPE_SAMPLED_IMAGE, _)) => DescriptorKind::CombinedImageSampler,
+            // sampled type, dim, depth, arrayed, multisampled, sampled, format
+            Some((OP_TYPE_IMAGE, operands)) if operands.len() >= 7 => {
+                match (operands[1] == DIM_BUFFER, operands[5] == 2) {
+                    (true, true) => DescriptorKind::StorageTexelBuffer,
+                    (true, false) => DescriptorKind::UniformTexelBuffer,
+                    (false, true) => DescriptorKind::StorageImage,
+                    (false, false) => DescriptorKind::SampledImage,
+                }
+            }
+            Some((OP_TYPE_ACCELERATION_STRUCTURE, _)) => DescriptorKind::AccelerationStructure,
+            Some((OP_TYPE_STRUCT, _)) if class == STORAGE_CLASS_STORAGE_BUFFER => {
+                DescriptorKind::StorageBuffer
+            }
+            Some((OP_TYPE_STRUCT, _)) if class == STORAGE_CLASS_UNIFORM => {
+                match blocks.get(&pointee) {
+                    Some(&DECORATION_BUFFER_BLOCK) => DescriptorKind::StorageBuffer,
+                    _ => DescriptorKind::UniformBuffer,
+                }
+            }
+            _ => continue,
+        };
+        let name = names
+            .get(&id)
+            .filter(|name| !name.is_empty())
+            .or_else(|| names.get(&pointee))
+            .cloned()
+            .unwrap_or_default();
+        out.push(Binding {
+            set,
+            binding,
+            kind,
+            count,
+            name,
+        });
+    }
+    out.sort_by_key(|b| (b.set, b.binding));
+    out
+}
+
+/// Lists the variables, by name or else id, that hold images, samplers, or acceleration
+/// structures, or aggregates of them, in private or function storage.  HLSL lets shaders copy
+/// resources around like that; Vulkan doesn't, so legalizing a module compiled from HLSL has to
+/// get rid of every one.
+pub(crate) fn opaque_locals(words: &[u32]) -> Vec<String> {
+    let mut names = HashMap::<u32, String>::new();
+    let mut types = HashMap::<u32, (u16, Vec<u32>)>::new();
+    let mut variables = Vec::<(u32, u32)>::new();
+    for inst in instructions(words) {
+        match (inst.opcode, inst.operands) {
+            (OP_NAME, [target, rest @ ..]) => {
+                names.insert(*target, decode_string(rest).0);
+            }
+            (OP_VARIABLE, [ty, id, class, ..])
+                if *class == STORAGE_CLASS_PRIVATE || *class == STORAGE_CLASS_FUNCTION =>
+            {
+                variables.push((*ty, *id));
+            }
+            (opcode, [id, rest @ ..]) if (OP_TYPE_IMAGE..=OP_TYPE_POINTER).contains(&opcode) => {
+                types.insert(*id, (opcode, rest.to_vec()));
+            }
+            (OP_TYPE_ACCELERATION_STRUCTURE, [id]) => {
+                types.insert(*id, (OP_TYPE_ACCELERATION_STRUCTURE, Vec::new()));
+            }
+            _ => {}
+        }
+    }
+
+    fn opaque(types: &HashMap<u32, (u16, Vec<u32>)>, ty: u32) -> bool {
+        match types.get(&ty) {
+            Some((OP_TYPE_IMAGE, _))
+            | Some((OP_TYPE_SAMPLER, _))
+            | Some((OP_TYPE_SAMPLED_IMAGE, _))
+            | Some((OP_TYPE_ACCELERATION_STRUCTURE, _)) => true,
+            Some((OP_TYPE_ARRAY, operands)) | Some((OP_TYPE_RUNTIME_ARRAY, operands)) => operands
+                .first()
+                .is_some_and(|&element| opaque(types, element)),
+            Some((OP_TYPE_STRUCT, members)) => members.iter().any(|&member| opaque(types, member)),
+            _ => false,
+        }
+    }
+
+    let mut out = Vec::new();
+    for (ty, id) in variables {
+        let pointee = match types.get(&ty) {
+            Some((OP_TYPE_POINTER, operands)) if operands.len() == 2 => operands[1],
+            _ => continue,
+        };
+        if opaque(&types, pointee) {
+            let name = names.get(&id).filter(|name| !name.is_empty()).cloned();
+            out.push(name.unwrap_or_else(|| format!("%{}", id)));
+        }
+    }
+    out
+}
+
+/// What `push_constant_size` needs to know about a module's types.
+#[derive(Default)]
+struct Layout {
+    types: HashMap<u32, (u16, Vec<u32>)>,
+    constants: HashMap<u32, u32>,
+    array_strides: HashMap<u32, u32>,
+    /// By struct and member.
+    offsets: HashMap<(u32, u32), u32>,
+    matrix_strides: HashMap<(u32, u32), u32>,
+    row_major: HashSet<(u32, u32)>,
+}
+
+impl Layout {
+    /// How many bytes a value of type `ty` takes, with `member` being the struct member it is,
+    /// if it is one, for its matrix layout.
+    fn size(&self, ty: u32, member: Option<(u32, u32)>) -> u32 {
+        match self.types.get(&ty) {
+            Some((OP_TYPE_INT, operands)) | Some((OP_TYPE_FLOAT, operands)) => {
+                operands.first().map_or(4, |width| width / 8)
+            }
+            Some((OP_TYPE_BOOL, _)) => 4,
+            Some((OP_TYPE_VECTOR, operands)) if operands.len() == 2 => {
+                operands[1] * self.size(operands[0], None)
+            }
+            Some((OP_TYPE_MATRIX, operands)) if operands.len() == 2 => {
+                let (column, columns) = (operands[0], operands[1]);
+                let stride = member.and_then(|m| self.matrix_strides.get(&m));
+                let rows = match self.types.get(&column) {
+                    Some((OP_TYPE_VECTOR, operands)) if operands.len() == 2 => operands[1],
+                    _ => 1,
+                };
+                match stride {
+                    Some(stride) if member.is_some_and(|m| self.row_major.contains(&m)) => {
+                        stride * rows
+                    }
+                    Some(stride) => stride * columns,
+                    None => columns * self.size(column, None),
+                }
+            }
+            Some((OP_TYPE_ARRAY, operands)) if operands.len() == 2 => {
+                let length = self.constants.get(&operands[1]).copied().unwrap_or(0);
+                let stride = match self.array_strides.get(&ty) {
+                    Some(stride) => *stride,
+                    None => self.size(operands[0], member),
+                };
+                length * stride
+            }
+            Some((OP_TYPE_STRUCT, members)) => {
+                let mut end = 0;
+                for (i, &member) in members.iter().enumerate() {
+                    let key = (ty, i as u32);
+                    let offset = self.offsets.get(&key).copied().unwrap_or(end);
+                    end = end.max(offset + self.size(member, Some(key)));
+                }
+                end
+            }
+            _ => 0,
+        }
+    }
+}
+
+/// How many bytes of push constants a module uses, going by the `Offset` decorations of its
+/// push constant block, or 0 if it has none.
+pub(crate) fn push_constant_size(words: &[u32]) -> u32 {
+    let mut layout = Layout::default();
+    let mut variables = Vec::new();
+    for inst in instructions(words) {
+        match (inst.opcode, inst.operands) {
+            (OP_DECORATE, [target, DECORATION_ARRAY_STRIDE, stride]) => {
+                layout.array_strides.insert(*target, *stride);
+            }
+            (OP_MEMBER_DECORATE, [target, member, DECORATION_OFFSET, offset]) => {
+                layout.offsets.insert((*target, *member), *offset);
+            }
+            (OP_MEMBER_DECORATE, [target, member, DECORATION_MATRIX_STRIDE, stride]) => {
+                layout.matrix_strides.insert((*target, *member), *stride);
+            }
+            (OP_MEMBER_DECORATE, [target, member, DECORATION_ROW_MAJOR]) => {
+                layout.row_major.insert((*target, *member));
+            }
+            (OP_CONSTANT, [_, id, value, ..]) | (OP_SPEC_CONSTANT, [_, id, value, ..]) => {
+                layout.constants.insert(*id, *value);
+            }
+            (OP_VARIABLE, [ty, _, STORAGE_CLASS_PUSH_CONSTANT, ..]) => variables.push(*ty),
+            (opcode, [id, rest @ ..]) if (OP_TYPE_BOOL..=OP_TYPE_POINTER).contains(&opcode) => {
+                layout.types.insert(*id, (opcode, rest.to_vec()));
+            }
+            _ => {}
+        }
+    }
+    variables
+        .into_iter()
+        .filter_map(|ty| match layout.types.get(&ty) {
+            Some((OP_TYPE_POINTER, operands)) if operands.len() == 2 => Some(operands[1]),
+            _ => None,
+        })
+        .map(|block| layout.size(block, None))
+        .max()
+        .unwrap_or(0)
+}
+
+/// The workgroup size a compute module declares: that of the `WorkgroupSize` built-in if there
+/// is one, and otherwise of its `LocalSize` or `LocalSizeId` execution mode.  Sizes given by
+/// specialization constants are taken at their defaults.
+pub(crate) fn workgroup_size(words: &[u32]) -> Option<[u32; 3]> {
+    let mut constants = HashMap::<u32, u32>::new();
+    let mut composites = HashMap::<u32, Vec<u32>>::new();
+    let mut built_in = None;
+    let mut local_size = None;
+    let mut local_size_ids = None;
+    for inst in instructions(words) {
+        match (inst.opcode, inst.operands) {
+            (OP_DECORATE, [target, DECORATION_BUILT_IN, BUILT_IN_WORKGROUP_SIZE]) => {
+                built_in = Some(*target);
+            }
+            (OP_EXECUTION_MODE, [_, EXECUTION_MODE_LOCAL_SIZE, x, y, z]) => {
+                local_size = Some([*x, *y, *z]);
+            }
+            (OP_EXECUTION_MODE_ID, [_, EXECUTION_MODE_LOCAL_SIZE_ID, x, y, z]) => {
+                local_size_ids = Some([*x, *y, *z]);
+            }
+            (OP_CONSTANT, [_, id, value, ..]) | (OP_SPEC_CONSTANT, [_, id, value, ..]) => {
+                constants.insert(*id, *value);
+            }
+            (OP_CONSTANT_COMPOSITE, [_, id, parts @ ..])
+            | (OP_SPEC_CONSTANT_COMPOSITE, [_, id, parts @ ..]) => {
+                composites.insert(*id, parts.to_vec());
+            }
+            _ => {}
+        }
+    }
+    let of_ids = |ids: &[u32]| -> Option<[u32; 3]> {
+        match ids {
+            [x, y, z] => Some([*constants.get(x)?, *constants.get(y)?, *constants.get(z)?]),
+            _ => None,
+        }
+    };
+    if let Some(size) = built_in.and_then(|id| of_ids(composites.get(&id)?)) {
+        return Some(size);
+    }
+    local_size.or_else(|| of_ids(&local_size_ids?))
+}
+
+/// Things in a module that nothing ends up using.
+#[derive(Debug, Default)]
+pub(crate) struct DeadCode {
+    pub functions: Vec<String>,
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+}
+
+/// Finds functions unreachable from any entry point and interface variables that no function
+/// body refers to.  Any word in a function body matching a variable's id counts as a use, so this
+/// may miss dead variables but never reports live ones.
+pub(crate) fn find_dead_code(words: &[u32]) -> DeadCode {
+    let mut names = HashMap::<u32, String>::new();
+    let mut roots = Vec::<u32>::new();
+    let mut functions = Vec::<u32>::new();
+    let mut calls = HashMap::<u32, Vec<u32>>::new();
+    let mut interface = Vec::<(u32, u32)>::new();
+    let mut referenced = HashSet::<u32>::new();
+    let mut current: Option<u32> = None;
+    for inst in instructions(words) {
+        match (inst.opcode, inst.operands) {
+            (OP_NAME, [target, rest @ ..]) => {
+                names.insert(*target, decode_string(rest).0);
+            }
+            (OP_ENTRY_POINT, [_, function, ..]) => roots.push(*function),
+            (OP_FUNCTION, [_, id, ..]) => {
+                functions.push(*id);
+                current = Some(*id);
+            }
+            (OP_FUNCTION_END, _) => current = None,
+            (OP_VARIABLE, [_, id, class, ..])
+                if current.is_none()
+                    && (*class == STORAGE_CLASS_INPUT || *class == STORAGE_CLASS_OUTPUT) =>
+            {
+                interface.push((*id, *class));
+            }
+            (opcode, operands) => {
+                if let Some(caller) = current {
+                    if let (OP_FUNCTION_CALL, [_, _, callee, ..]) = (opcode, operands) {
+                        calls.entry(caller).or_default().push(*callee);
+                    }
+                    referenced.extend(operands.iter().copied());
+                }
+            }
+        }
+    }
+
+    let mut reachable = HashSet::<u32>::new();
+    while let Some(function) = roots.pop() {
+        if reachable.insert(function) {
+            roots.extend(calls.get(&function).into_iter().flatten().copied());
+        }
+    }
+
+    let mut dead = DeadCode::default();
+    for function in functions {
+        if !reachable.contains(&function) {
+            let name = names.get(&function).cloned();
+            dead.functions
+                .push(name.unwrap_or_else(|| format!("%{}", function)));
+        }
+    }
+    for (id, class) in interface {
+        let name = names.get(&id).cloned().unwrap_or_default();
+        // Unnamed variables are things like the gl_PerVertex block; nothing to clean up there.
+        if referenced.contains(&id) || name.is_empty() || name.starts_with("gl_") {
+            continue;
+        }
+        if class == STORAGE_CLASS_INPUT {
+            dead.inputs.push(name);
+        } else {
+            dead.outputs.push(name);
+        }
+    }
+    dead
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A SPIR-V 1.0 module of `body`, each instruction an opcode and its operands.
+    fn module(body: &[(u16, Vec<u32>)]) -> Vec<u32> {
+        let mut words = vec![MAGIC, 0x0001_0000, 0, 100, 0];
+        for (opcode, operands) in body.iter() {
+            push_instruction(&mut words, *opcode, operands);
+        }
+        words
+    }
+
+    /// `ids` followed by `s` as a literal string.
+    fn named(ids: &[u32], s: &str) -> Vec<u32> {
+        let mut operands = ids.to_vec();
+        operands.extend(encode_string(s));
+        operands
+    }
+
+    #[test]
+    fn malformed_instructions_end_the_stream() {
+        let mut words = module(&[(OP_CAPABILITY, vec![1])]);
+        // Claims three words with only one left.
+        words.push(3 << 16 | OP_CAPABILITY as u32);
+        assert_eq!(capabilities(&words), vec![1]);
+        assert!(instructions(&words[..3]).next().is_none());
+    }
+
+    #[test]
+    fn dead_code_is_what_no_entry_point_reaches() {
+        let words = module(&[
+            (OP_ENTRY_POINT, named(&[4, 3], "main")),
+            (OP_NAME, named(&[3], "main")),
+            (OP_NAME, named(&[5], "helper")),
+            (OP_NAME, named(&[6], "unused")),
+            (OP_NAME, named(&[10], "v_color")),
+            (OP_NAME, named(&[11], "v_normal")),
+            (OP_VARIABLE, vec![20, 10, STORAGE_CLASS_INPUT]),
+            (OP_VARIABLE, vec![20, 11, STORAGE_CLASS_INPUT]),
+            (OP_FUNCTION, vec![1, 3, 0, 2]),
+            (OP_FUNCTION_CALL, vec![1, 12, 5]),
+            (OP_FUNCTION_END, vec![]),
+            (OP_FUNCTION, vec![1, 5, 0, 2]),
+            (OP_VARIABLE, vec![21, 13, STORAGE_CLASS_FUNCTION, 10]),
+            (OP_FUNCTION_END, vec![]),
+            (OP_FUNCTION, vec![1, 6, 0, 2]),
+            (OP_FUNCTION_END, vec![]),
+        ]);
+        let dead = find_dead_code(&words);
+        assert_eq!(dead.functions, ["unused"]);
+        assert_eq!(dead.inputs, ["v_normal"]);
+        assert!(dead.outputs.is_empty());
+    }
+
+    fn body(words: &[u32]) -> Vec<(u16, Vec<u32>)> {
+        instructions(words)
+            .map(|inst| (inst.opcode, inst.operands.to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn strings_round_trip() {
+        assert_eq!(encode_string("abc").len(), 1);
+        assert_eq!(encode_string("main").len(), 2);
+        assert_eq!(
+            decode_string(&encode_string("main")),
+            ("main".to_owned(), 2)
+        );
+        // A string missing its terminator takes every word it's given.
+        assert_eq!(decode_string(&[u32::from_le_bytes(*b"abcd")]).0, "abcd");
+    }
+
+    #[test]
+    fn notes_go_in_the_debug_section_and_come_back_out() {
+        let original = module(&[
+            (OP_CAPABILITY, vec![1]),
+            (OP_MEMORY_MODEL, vec![0, 1]),
+            (OP_NAME, named(&[4], "main")),
+            (OP_TYPE_BOOL, vec![2]),
+        ]);
+        let mut words = original.clone();
+        insert_note(&mut words, "wrangler: 1234");
+        let opcodes: Vec<u16> = body(&words).iter().map(|(opcode, _)| *opcode).collect();
+        assert_eq!(
+            opcodes,
+            [
+                OP_CAPABILITY,
+                OP_MEMORY_MODEL,
+                OP_SOURCE_EXTENSION,
+                OP_NAME,
+                OP_TYPE_BOOL
+            ]
+        );
+        assert_eq!(remove_notes(&words, "wrangler:"), original);
+        assert_eq!(remove_notes(&words, "other:"), words);
+
+        let mut words = original.clone();
+        words[1] = 0x0001_0300;
+        insert_note(&mut words, "wrangler: 1234");
+        let opcodes: Vec<u16> = body(&words).iter().map(|(opcode, _)| *opcode).collect();
+        assert_eq!(
+            opcodes,
+            [
+                OP_CAPABILITY,
+                OP_MEMORY_MODEL,
+                OP_NAME,
+                OP_MODULE_PROCESSED,
+                OP_TYPE_BOOL
+            ]
+        );
+    }
+
+    /// An `int` spec constant 2 with `SpecId` 7 and a default of 5, and 3 = 2 + 2.
+    #[cfg(not(feature = "spirv-tools"))]
+    fn spec_module() -> Vec<u32> {
+        module(&[
+            (OP_DECORATE, vec![2, DECORATION_SPEC_ID, 7]),
+            (OP_TYPE_INT, vec![1, 32, 1]),
+            (OP_SPEC_CONSTANT, vec![1, 2, 5]),
+            (OP_SPEC_CONSTANT_OP, vec![1, 3, 128, 2, 2]),
+        ])
+    }
+
+    #[test]
+    #[cfg(not(feature = "spirv-tools"))]
+    fn frozen_spec_constants_take_their_values() {
+        let frozen = freeze_spec_constants(&spec_module(), &[(7, SpecValue::Int(-3))]).unwrap();
+        assert_eq!(
+            body(&frozen),
+            [
+                (OP_TYPE_INT, vec![1, 32, 1]),
+                (OP_CONSTANT, vec![1, 2, -3i32 as u32]),
+                (OP_CONSTANT, vec![1, 3, -6i32 as u32]),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "spirv-tools"))]
+    fn frozen_spec_constants_keep_their_defaults() {
+        let frozen = freeze_spec_constants(&spec_module(), &[(8, SpecValue::Int(1))]).unwrap();
+        assert_eq!(
+            body(&frozen)[1..],
+            [(OP_CONSTANT, vec![1, 2, 5]), (OP_CONSTANT, vec![1, 3, 10])]
+        );
+    }
+
+    #[test]
+    fn spec_values_are_truncated_to_their_width() {
+        let words = module(&[
+            (OP_DECORATE, vec![2, DECORATION_SPEC_ID, 0]),
+            (OP_TYPE_INT, vec![1, 16, 0]),
+            (OP_SPEC_CONSTANT, vec![1, 2, 0]),
+        ]);
+        let frozen = set_spec_defaults(&words, &[(0, SpecValue::Int(0x1_2345))]).unwrap();
+        assert_eq!(body(&frozen)[2], (OP_SPEC_CONSTANT, vec![1, 2, 0x2345]));
+    }
+
+    #[test]
+    #[cfg(not(feature = "spirv-tools"))]
+    fn unfoldable_spec_constant_ops_are_kept() {
+        // 3 = 2 / 0 has no defined value.
+        let words = module(&[
+            (OP_TYPE_INT, vec![1, 32, 0]),
+            (OP_CONSTANT, vec![1, 4, 0]),
+            (OP_SPEC_CONSTANT, vec![1, 2, 5]),
+            (OP_SPEC_CONSTANT_OP, vec![1, 3, 134, 2, 4]),
+        ]);
+        let frozen = freeze_spec_constants(&words, &[]).unwrap();
+        assert_eq!(
+            body(&frozen)[3],
+            (OP_SPEC_CONSTANT_OP, vec![1, 3, 134, 2, 4])
+        );
+    }
+
+    #[test]
+    fn frozen_bools_must_be_bools() {
+        let words = module(&[
+            (OP_DECORATE, vec![2, DECORATION_SPEC_ID, 0]),
+            (OP_TYPE_BOOL, vec![1]),
+            (OP_SPEC_CONSTANT_TRUE, vec![1, 2]),
+        ]);
+        let frozen = set_spec_defaults(&words, &[(0, SpecValue::Bool(false))]).unwrap();
+        assert_eq!(body(&frozen)[2], (OP_SPEC_CONSTANT_FALSE, vec![1, 2]));
+        assert!(freeze_spec_constants(&words, &[(0, SpecValue::Int(1))]).is_err());
+    }
+
+    #[test]
+    fn modules_shorter_than_a_header_are_refused() {
+        let words = [MAGIC, 0x0001_0000];
+        assert!(freeze_spec_constants(&words, &[]).is_err());
+        assert!(rename_identifiers(&words, str::to_owned).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "spirv-tools")]
+    fn spirv_opt_freezes_and_folds_spec_constants() {
+        const OP_TYPE_VOID: u16 = 19;
+        const OP_TYPE_FUNCTION: u16 = 33;
+        const OP_LABEL: u16 = 248;
+        const OP_RETURN: u16 = 253;
+        // A compute shader that does nothing, with 4 = 2 + 2 over the spec constant 2.
+        let words = module(&[
+            (OP_CAPABILITY, vec![1]),
+            (OP_MEMORY_MODEL, vec![0, 1]),
+            (OP_ENTRY_POINT, named(&[5, 1], "main")),
+            (OP_EXECUTION_MODE, vec![1, 17, 1, 1, 1]),
+            (OP_DECORATE, vec![2, DECORATION_SPEC_ID, 7]),
+            (OP_TYPE_INT, vec![3, 32, 1]),
+            (OP_SPEC_CONSTANT, vec![3, 2, 5]),
+            (OP_SPEC_CONSTANT_OP, vec![3, 4, 128, 2, 2]),
+            (OP_TYPE_VOID, vec![5]),
+            (OP_TYPE_FUNCTION, vec![6, 5]),
+            (OP_FUNCTION, vec![5, 1, 0, 6]),
+            (OP_LABEL, vec![7]),
+            (OP_RETURN, vec![]),
+            (OP_FUNCTION_END, vec![]),
+        ]);
+        let frozen = freeze_spec_constants(&words, &[(7, SpecValue::Int(-3))]).unwrap();
+        let body = body(&frozen);
+        assert!(!body.iter().any(|(opcode, operands)| {
+            matches!(*opcode, OP_SPEC_CONSTANT | OP_SPEC_CONSTANT_OP)
+                || (*opcode == OP_DECORATE && operands[1] == DECORATION_SPEC_ID)
+        }));
+        let constants: Vec<u32> = body
+            .iter()
+            .filter(|(opcode, _)| *opcode == OP_CONSTANT)
+            .map(|(_, operands)| operands[2])
+            .collect();
+        assert!(constants.contains(&(-3i32 as u32)));
+        assert!(constants.contains(&(-6i32 as u32)));
+    }
+
+    #[test]
+    fn renaming_leaves_entry_points_alone() {
+        let words = module(&[
+            (OP_ENTRY_POINT, named(&[4, 3], "main")),
+            (OP_NAME, named(&[3], "main")),
+            (OP_NAME, named(&[5], "")),
+            (OP_MEMBER_NAME, named(&[6, 0], "color")),
+        ]);
+        let renamed = rename_identifiers(&words, |name| name.to_uppercase()).unwrap();
+        assert_eq!(
+            body(&renamed),
+            [
+                (OP_ENTRY_POINT, named(&[4, 3], "main")),
+                (OP_NAME, named(&[3], "MAIN")),
+                (OP_NAME, named(&[5], "")),
+                (OP_MEMBER_NAME, named(&[6, 0], "COLOR")),
+            ]
+        );
+    }
+
+    #[test]
+    fn renaming_drops_debug_info() {
+        let words = module(&[
+            (OP_STRING, named(&[1], "secret/lighting.frag")),
+            (
+                OP_SOURCE,
+                [2, 450, 1]
+                    .iter()
+                    .copied()
+                    .chain(encode_string("void main"))
+                    .collect(),
+            ),
+            (OP_SOURCE_CONTINUED, encode_string("() {}")),
+            (OP_NAME, named(&[3], "main")),
+            (OP_LINE, vec![1, 12, 0]),
+            (OP_NO_LINE, vec![]),
+        ]);
+        let renamed = rename_identifiers(&words, str::to_owned).unwrap();
+        assert_eq!(body(&renamed), [(OP_NAME, named(&[3], "main"))]);
+    }
+
+    #[test]
+    fn renaming_refuses_extended_debug_info() {
+        let words = module(&[(
+            OP_EXT_INST_IMPORT,
+            named(&[1], "NonSemantic.Shader.DebugInfo.100"),
+        )]);
+        assert!(rename_identifiers(&words, str::to_owned).is_err());
+        let words = module(&[(OP_EXT_INST_IMPORT, named(&[1], "GLSL.std.450"))]);
+        assert!(rename_identifiers(&words, str::to_owned).is_ok());
+    }
+
+    #[test]
+    fn workgroup_size_prefers_the_built_in() {
+        let local_size = (
+            OP_EXECUTION_MODE,
+            vec![3, EXECUTION_MODE_LOCAL_SIZE, 8, 4, 1],
+        );
+        assert_eq!(
+            workgroup_size(&module(std::slice::from_ref(&local_size))),
+            Some([8, 4, 1])
+        );
+        let words = module(&[
+            local_size,
+            (
+                OP_DECORATE,
+                vec![9, DECORATION_BUILT_IN, BUILT_IN_WORKGROUP_SIZE],
+            ),
+            (OP_TYPE_INT, vec![1, 32, 0]),
+            (OP_CONSTANT, vec![1, 5, 64]),
+            (OP_SPEC_CONSTANT, vec![1, 6, 2]),
+            (OP_CONSTANT_COMPOSITE, vec![7, 9, 5, 6, 6]),
+        ]);
+        assert_eq!(workgroup_size(&words), Some([64, 2, 2]));
+        assert_eq!(workgroup_size(&module(&[])), None);
+    }
+}
@@ -0,0 +1,343 @@
+// What each `TargetEnv` can consume, for turning down modules that declare more before they
+// reach a driver that would reject them, or worse, accept them.  It's the SPIR-V version, the
+// capabilities and the extensions that are checked:
+//
+//   - A target takes SPIR-V up to some version: 1.0 for Vulkan 1.0 and OpenGL 4.5, 1.3 for
+//     Vulkan 1.1, 1.5 for Vulkan 1.2, and 1.6 for Vulkan 1.3.  A `spirv_version` asked for
+//     explicitly is taken to be supported, like 1.4 on Vulkan 1.1 with VK_KHR_spirv_1_4.
+//   - Some capabilities are only for OpenCL kernels, and others, like ray tracing, only for
+//     Vulkan.  Others became core in some version of SPIR-V, and before that need the extension
+//     that brought them in to be declared.  OpenGL 4.5 has none of the 8, 16, and 64-bit types.
+//   - Ray tracing, ray query, mesh shading and shading rate extensions are only for Vulkan, and
+//     the first three need SPIR-V 1.4.
+//
+// Whether a device has a feature, like `shaderInt64`, isn't something a target says, so it isn't
+// checked here; `allowed_capabilities` can narrow things down that far.
+
+use crate::spirv;
+use crate::{Capability, SpirvVersion, TargetEnv};
+
+/// SPIR-V versions as a module's version word has them.
+const V1_0: u32 = 0x0001_0000;
+const V1_3: u32 = 0x0001_0300;
+const V1_4: u32 = 0x0001_0400;
+const V1_5: u32 = 0x0001_0500;
+const V1_6: u32 = 0x0001_0600;
+
+fn version_name(version: u32) -> String {
+    format!("{}.{}", (version >> 16) & 0xff, (version >> 8) & 0xff)
+}
+
+/// The newest SPIR-V `target` consumes, or `spirv_version` if that's set.
+fn max_version(target: TargetEnv, spirv_version: Option<SpirvVersion>) -> u32 {
+    if let Some(version) = spirv_version {
+        let minor = match version {
+            SpirvVersion::V1_0 => 0,
+            SpirvVersion::V1_1 => 1,
+            SpirvVersion::V1_2 => 2,
+            SpirvVersion::V1_3 => 3,
+            SpirvVersion::V1_4 => 4,
+            SpirvVersion::V1_5 => 5,
+            SpirvVersion::V1_6 => 6,
+        };
+        return V1_0 | minor << 8;
+    }
+    match target {
+        TargetEnv::Vulkan1_0 | TargetEnv::OpenGl4_5 => V1_0,
+        TargetEnv::Vulkan1_1 => V1_3,
+        TargetEnv::Vulkan1_2 => V1_5,
+        TargetEnv::Vulkan1_3 => V1_6,
+    }
+}
+
+fn is_vulkan(target: TargetEnv) -> bool {
+    target != TargetEnv::OpenGl4_5
+}
+
+/// Where a capability can be used.
+enum Needs {
+    /// Only in OpenCL kernels, never in graphics.
+    Kernel,
+    /// Only in Vulkan, and only with this extension.
+    Vulkan(&'static str),
+    /// From this version of SPIR-V on, or before it with one of these extensions.
+    Core(u32, &'static [&'static str]),
+    /// Anywhere but OpenGL 4.5, which only has it through an OpenGL extension.
+    NotOpenGl,
+}
+
+fn needs(capability: Capability) -> Option<Needs> {
+    use Capability::*;
+    Some(match capability {
+        Addresses | Linkage | Kernel | Vector16 | Float16Buffer | ImageBasic | ImageReadWrite
+        | ImageMipmap | Pipes | DeviceEnqueue | LiteralSampler | GenericPointer
+        | SubgroupDispatch | NamedBarrier | PipeStorage => Needs::Kernel,
+        RayQueryKHR => Needs::Vulkan("SPV_KHR_ray_query"),
+        RayTracingKHR => Needs::Vulkan("SPV_KHR_ray_tracing"),
+        RayTraversalPrimitiveCullingKHR => Needs::Vulkan("SPV_KHR_ray_tracing"),
+        MeshShadingEXT => Needs::Vulkan("SPV_EXT_mesh_shader"),
+        FragmentShadingRateKHR => Needs::Vulkan("SPV_KHR_fragment_shading_rate"),
+        GroupNonUniform
+        | GroupNonUniformVote
+        | GroupNonUniformArithmetic
+        | GroupNonUniformBallot
+        | GroupNonUniformShuffle
+        | GroupNonUniformShuffleRelative
+        | GroupNonUniformClustered
+        | GroupNonUniformQuad => Needs::Core(V1_3, &[]),
+        DeviceGroup => Needs::Core(V1_3, &["SPV_KHR_device_group"]),
+        MultiView => Needs::Core(V1_3, &["SPV_KHR_multiview"]),
+        StorageBuffer16BitAccess
+        | UniformAndStorageBuffer16BitAccess
+        | StoragePushConstant16
+        | StorageInputOutput16 => Needs::Core(V1_3, &["SPV_KHR_16bit_storage"]),
+        VariablePointers | VariablePointersStorageBuffer => {
+            Needs::Core(V1_3, &["SPV_KHR_variable_pointers"])
+        }
+        DrawParameters => Needs::Core(V1_3, &["SPV_KHR_shader_draw_parameters"]),
+        DenormPreserve
+        | DenormFlushToZero
+        | SignedZeroInfNanPreserve
+        | RoundingModeRTE
+        | RoundingModeRTZ => Needs::Core(V1_4, &["SPV_KHR_float_controls"]),
+        StorageBuffer8BitAccess | UniformAndStorageBuffer8BitAccess | StoragePushConstant8 => {
+            Needs::Core(V1_5, &["SPV_KHR_8bit_storage"])
+        }
+        ShaderNonUniform
+        | RuntimeDescriptorArray
+        | InputAttachmentArrayDynamicIndexing
+        | UniformTexelBufferArrayDynamicIndexing
+        | StorageTexelBufferArrayDynamicIndexing
+        | UniformBufferArrayNonUniformIndexing
+        | SampledImageArrayNonUniformIndexing
+        | StorageBufferArrayNonUniformIndexing
+        | StorageImageArrayNonUniformIndexing
+        | InputAttachmentArrayNonUniformIndexing
+        | UniformTexelBufferArrayNonUniformIndexing
+        | StorageTexelBufferArrayNonUniformIndexing => {
+            Needs::Core(V1_5, &["SPV_EXT_descriptor_indexing"])
+        }
+        PhysicalStorageBufferAddresses => Needs::Core(
+            V1_5,
+            &[
+                "SPV_KHR_physical_storage_buffer",
+                "SPV_EXT_physical_storage_buffer",
+            ],
+        ),
+        VulkanMemoryModel | VulkanMemoryModelDeviceScope => {
+            Needs::Core(V1_5, &["SPV_KHR_vulkan_memory_model"])
+        }
+        ShaderViewportIndex | ShaderLayer => {
+            Needs::Core(V1_5, &["SPV_EXT_shader_viewport_index_layer"])
+        }
+        DemoteToHelperInvocation => Needs::Core(V1_6, &["SPV_EXT_demote_to_helper_invocation"]),
+        DotProduct | DotProductInputAll | DotProductInput4x8Bit | DotProductInput4x8BitPacked => {
+            Needs::Core(V1_6, &["SPV_KHR_integer_dot_product"])
+        }
+        Int64 | Int64Atomics | Int16 | Int8 | Float16 => Needs::NotOpenGl,
+        _ => return None,
+    })
+}
+
+/// Extensions only Vulkan has, with the SPIR-V version Vulkan needs them in.
+const VULKAN_EXTENSIONS: &[(&str, u32)] = &[
+    ("SPV_KHR_ray_query", V1_4),
+    ("SPV_KHR_ray_tracing", V1_4),
+    ("SPV_EXT_mesh_shader", V1_4),
+    ("SPV_KHR_fragment_shading_rate", V1_0),
+];
+
+/// Lists what `words` declares that `target` can't consume, skipping the capabilities in
+/// `allowed_capabilities` and extensions in `allowed_extensions`, which are taken to be
+/// supported.
+pub(crate) fn unsupported(
+    target: TargetEnv,
+    spirv_version: Option<SpirvVersion>,
+    words: &[u32],
+    allowed_capabilities: &[Capability],
+    allowed_extensions: &[String],
+) -> Vec<String> {
+    let mut refused = Vec::new();
+    let version = words.get(1).copied().unwrap_or(V1_0);
+    let max = max_version(target, spirv_version);
+    if version > max {
+        refused.push(format!(
+            "SPIR-V {}, newer than its {}",
+            version_name(version),
+            version_name(max)
+        ));
+    }
+    let extensions = spirv::extensions(words);
+    let declared = |extension: &str| extensions.iter().any(|e| e == extension);
+    for number in spirv::capabilities(words) {
+        let capability = match Capability::from_u32(number) {
+            Some(capability) if !allowed_capabilities.contains(&capability) => capability,
+            // Unknown ones are for `allowed_capabilities` to turn down.
+            _ => continue,
+        };
+        let reason = match needs(capability) {
+            Some(Needs::Kernel) => "which is only for OpenCL kernels".to_owned(),
+            Some(Needs::Vulkan(_)) if !is_vulkan(target) => "which is only for Vulkan".to_owned(),
+            Some(Needs::Vulkan(extension)) if !declared(extension) => {
+                format!("without {}", extension)
+            }
+            Some(Needs::Core(core, extensions))
+                if version < core && !extensions.iter().any(|e| declared(e)) =>
+            {
+                match extensions.first() {
+                    Some(extension) => {
+                        format!("which needs SPIR-V {} or {}", version_name(core), extension)
+                    }
+                    None => format!("which needs SPIR-V {}", version_name(core)),
+                }
+            }
+            Some(Needs::NotOpenGl) if !is_vulkan(target) => {
+                "which OpenGL 4.5 only has through an extension".to_owned()
+            }
+            _ => continue,
+        };
+        refused.push(format!("capability {:?}, {}", capability, reason));
+    }
+    for extension in extensions.iter() {
+        if allowed_extensions.contains(extension) {
+            continue;
+        }
+        let needed = VULKAN_EXTENSIONS.iter().find(|(e, _)| e == extension);
+        match needed {
+            Some(_) if !is_vulkan(target) => {
+                refused.push(format!("extension {}, which is only for Vulkan", extension))
+            }
+            Some((_, needed)) if version < *needed => refused.push(format!(
+                "extension {}, which needs SPIR-V {}",
+                extension,
+                version_name(*needed)
+            )),
+            _ => {}
+        }
+    }
+    refused
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spirv::{encode_string, MAGIC, OP_CAPABILITY, OP_EXTENSION};
+
+    /// A module of SPIR-V `version` declaring `capabilities` and `extensions`, and nothing else.
+    fn module(version: u32, capabilities: &[Capability], extensions: &[&str]) -> Vec<u32> {
+        let mut words = vec![MAGIC, version, 0, 1, 0];
+        for capability in capabilities.iter() {
+            words.extend([2 << 16 | OP_CAPABILITY as u32, *capability as u32]);
+        }
+        for extension in extensions.iter() {
+            let name = encode_string(extension);
+            words.push((name.len() as u32 + 1) << 16 | OP_EXTENSION as u32);
+            words.extend(name);
+        }
+        words
+    }
+
+    fn check(target: TargetEnv, words: &[u32]) -> Vec<String> {
+        unsupported(target, None, words, &[], &[])
+    }
+
+    #[test]
+    fn versions_past_the_targets_are_refused_unless_asked_for() {
+        let words = module(V1_3, &[], &[]);
+        assert_eq!(
+            check(TargetEnv::Vulkan1_0, &words),
+            ["SPIR-V 1.3, newer than its 1.0"]
+        );
+        assert!(check(TargetEnv::Vulkan1_1, &words).is_empty());
+        let asked = Some(SpirvVersion::V1_3);
+        assert!(unsupported(TargetEnv::Vulkan1_0, asked, &words, &[], &[]).is_empty());
+    }
+
+    #[test]
+    fn capabilities_are_checked_against_the_target() {
+        use Capability::*;
+        let refused = |target, version, capabilities: &[Capability], extensions: &[&str]| {
+            check(target, &module(version, capabilities, extensions))
+        };
+        assert_eq!(
+            refused(TargetEnv::Vulkan1_3, V1_6, &[Addresses], &[]),
+            ["capability Addresses, which is only for OpenCL kernels"]
+        );
+        assert_eq!(
+            refused(TargetEnv::OpenGl4_5, V1_0, &[RayQueryKHR, Int64], &[]),
+            [
+                "capability RayQueryKHR, which is only for Vulkan",
+                "capability Int64, which OpenGL 4.5 only has through an extension",
+            ]
+        );
+        assert_eq!(
+            refused(TargetEnv::Vulkan1_2, V1_5, &[RayQueryKHR], &[]),
+            ["capability RayQueryKHR, without SPV_KHR_ray_query"]
+        );
+        let declared = ["SPV_KHR_ray_query"];
+        assert!(refused(TargetEnv::Vulkan1_2, V1_5, &[RayQueryKHR, Int64], &declared).is_empty());
+        assert_eq!(
+            refused(
+                TargetEnv::Vulkan1_0,
+                V1_0,
+                &[GroupNonUniform, DrawParameters],
+                &[]
+            ),
+            [
+                "capability GroupNonUniform, which needs SPIR-V 1.3",
+                "capability DrawParameters, which needs SPIR-V 1.3 or \
+                 SPV_KHR_shader_draw_parameters",
+            ]
+        );
+        // Declaring the extension that brought a capability in makes up for the version.
+        let declared = ["SPV_KHR_shader_draw_parameters"];
+        assert!(refused(TargetEnv::Vulkan1_0, V1_0, &[DrawParameters], &declared).is_empty());
+    }
+
+    #[test]
+    fn vulkan_extensions_need_vulkan_and_their_version() {
+        let words = module(V1_0, &[], &["SPV_KHR_ray_tracing"]);
+        assert_eq!(
+            check(TargetEnv::Vulkan1_0, &words),
+            ["extension SPV_KHR_ray_tracing, which needs SPIR-V 1.4"]
+        );
+        assert_eq!(
+            check(TargetEnv::OpenGl4_5, &words),
+            ["extension SPV_KHR_ray_tracing, which is only for Vulkan"]
+        );
+        let allowed = ["SPV_KHR_ray_tracing".to_owned()];
+        assert!(unsupported(TargetEnv::OpenGl4_5, None, &words, &[], &allowed).is_empty());
+    }
+
+    #[test]
+    fn allowed_capabilities_are_taken_to_be_supported() {
+        let words = module(V1_0, &[Capability::Int64], &[]);
+        assert_eq!(check(TargetEnv::OpenGl4_5, &words).len(), 1);
+        let allowed = [Capability::Int64];
+        assert!(unsupported(TargetEnv::OpenGl4_5, None, &words, &allowed, &[]).is_empty());
+    }
+
+    #[test]
+    fn stages_needing_spirv_1_4_get_vulkan_1_2() {
+        use crate::{target_env, Instructions, ShaderKind};
+        let mut instructions = Instructions::default();
+        assert_eq!(
+            target_env(&instructions, ShaderKind::RayGeneration),
+            TargetEnv::Vulkan1_2
+        );
+        assert_eq!(
+            target_env(&instructions, ShaderKind::Fragment),
+            TargetEnv::Vulkan1_0
+        );
+        instructions.target_env = TargetEnv::Vulkan1_3;
+        assert_eq!(
+            target_env(&instructions, ShaderKind::Mesh),
+            TargetEnv::Vulkan1_3
+        );
+        instructions.target_env = TargetEnv::OpenGl4_5;
+        assert_eq!(
+            target_env(&instructions, ShaderKind::Mesh),
+            TargetEnv::OpenGl4_5
+        );
+    }
+}
@@ -0,0 +1,22 @@
+// Hooks for feeding wrangler's numbers into whatever metrics system a studio already runs.
+
+use std::time::Duration;
+
+/// Receives metrics as a run progresses.  Every method does nothing by default, so implementors
+/// only override what their backend understands.  Methods take `&self`; implementations that
+/// need to mutate state should use interior mutability, as most metrics clients already do.
+///
+/// The metrics reported are:
+///
+/// * `wrangler.discovery` (timer): time spent searching `search_root`
+/// * `wrangler.discovered` (gauge): shaders found under `search_root`
+/// * `wrangler.pending` (gauge): shaders found to be out of date
+/// * `wrangler.compile` (timer): time spent compiling a single shader
+/// * `wrangler.compiled` (counter): shaders compiled and written successfully
+/// * `wrangler.failed` (counter): shaders that failed to compile or post-process
+/// * `wrangler.run` (timer): time spent on the whole run
+pub trait Telemetry {
+    fn counter(&self, _name: &str, _value: u64) {}
+    fn timer(&self, _name: &str, _duration: Duration) {}
+    fn gauge(&self, _name: &str, _value: f64) {}
+}
@@ -0,0 +1,90 @@
+// Instrumentation through whichever facade the `tracing` or `log` feature picks, or none.
+//
+// `span!` marks a stretch of work: with `tracing` it opens a span, entered until the returned
+// guard is dropped; with `log` the guard logs how long the work took when it is dropped.
+// `event!` is a single record at the level it names, `debug` or `warn`.  With neither feature
+// both expand to next to nothing.
+
+#[cfg(feature = "tracing")]
+macro_rules! span {
+    ($name:literal $(, $field:ident = $value:expr)*) => {
+        tracing::debug_span!($name $(, $field = ?$value)*).entered()
+    };
+}
+
+#[cfg(all(feature = "log", not(feature = "tracing")))]
+macro_rules! span {
+    ($name:literal $(, $field:ident = $value:expr)*) => {
+        $crate::trace::Timed::new(
+            $name,
+            format!(concat!($(" ", stringify!($field), "={:?}"),*) $(, $value)*),
+        )
+    };
+}
+
+#[cfg(not(any(feature = "log", feature = "tracing")))]
+macro_rules! span {
+    ($name:literal $(, $field:ident = $value:expr)*) => {{
+        $(let _ = &$value;)*
+        $crate::trace::Untraced
+    }};
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {
+        tracing::$level!($($arg)+)
+    };
+}
+
+#[cfg(all(feature = "log", not(feature = "tracing")))]
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {
+        log::$level!($($arg)+)
+    };
+}
+
+#[cfg(not(any(feature = "log", feature = "tracing")))]
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {{
+        let _ = format_args!($($arg)+);
+    }};
+}
+
+pub(crate) use {event, span};
+
+/// What `span!` hands back when nothing is listening.
+#[cfg(not(any(feature = "log", feature = "tracing")))]
+pub(crate) struct Untraced;
+
+/// Logs the name and fields of a `span!` with how long it lasted, once it is dropped.
+#[cfg(all(feature = "log", not(feature = "tracing")))]
+pub(crate) struct Timed {
+    name: &'static str,
+    fields: String,
+    started: std::time::Instant,
+}
+
+#[cfg(all(feature = "log", not(feature = "tracing")))]
+impl Timed {
+    pub fn new(name: &'static str, fields: String) -> Timed {
+        log::trace!("{}{} started", name, fields);
+        Timed {
+            name,
+            fields,
+            started: std::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(all(feature = "log", not(feature = "tracing")))]
+impl Drop for Timed {
+    fn drop(&mut self) {
+        log::debug!(
+            "{}{} took {:?}",
+            self.name,
+            self.fields,
+            self.started.elapsed()
+        );
+    }
+}
@@ -0,0 +1,317 @@
+// Rebuilding as shaders are edited, for `Watcher` and `watch`.  Editors tend to save a file in
+// several steps, and saving one header can touch many shaders, so changes are gathered until
+// things have been quiet for `DEBOUNCE` and then handled in one run.  That run finds every shader
+// that included a changed header through the record, so they all land in the same report.  The
+// record also says which headers live outside the watched directories, and those get watched too.
+
+use crate::record::{self, Record};
+use crate::{platform, run_one, Backend, CompilationReport, Instructions, Result};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher as _};
+use std::env;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+const DEBOUNCE: Duration = Duration::from_millis(150);
+
+/// Everything a run writes that might be under a watched directory, as absolute paths.  Changes
+/// to these are the run's own doing, and would otherwise set off another run after every one.
+fn written_paths(instructions: &Instructions, cwd: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![
+        instructions.output_root.clone(),
+        instructions.record_path.with_extension("history"),
+    ];
+    let optional = [
+        &instructions.pack_output,
+        &instructions.rust_module,
+        &instructions.obfuscation_map,
+        &instructions.docs_output,
+        &instructions.resource_report,
+        &instructions.sarif_output,
+        &instructions.junit_output,
+        &instructions.json_report,
+    ];
+    paths.extend(optional.iter().filter_map(|&path| path.clone()));
+    #[cfg(any(feature = "cross", feature = "wgsl-out"))]
+    paths.extend(
+        instructions
+            .cross_compile
+            .iter()
+            .map(|(_, dir)| dir.clone()),
+    );
+    paths.into_iter().map(|path| cwd.join(path)).collect()
+}
+
+/// Whether a change to `path` could call for compiling something.
+fn matters(path: &Path, written: &[PathBuf], records: &[PathBuf]) -> bool {
+    // The record, its lock, and its temporary files all start with its name.
+    let beside_record = records.iter().any(|record| {
+        let record_name = record.file_name().unwrap_or_default().to_string_lossy();
+        path.parent() == record.parent()
+            && path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with(&*record_name))
+    });
+    !beside_record && !written.iter().any(|written| path.starts_with(written))
+}
+
+/// Builds `instructions` again whenever something under `search_root`, `include_dirs`, or
+/// `include_packages` changes, for embedding the watch loop in an engine or editor.  Each run's
+/// report comes out of `recv`, which waits for it, or `try_recv`, which doesn't and so suits a
+/// frame loop; the watcher is also an iterator over them.  The first report is for a run of
+/// everything pending when the watcher was made.
+///
+/// As with `run_report`, failing to compile a shader only shows up in the report, but other
+/// errors, like one reading the record, come out on their own.  The compiler is loaded once and
+/// kept between runs, which only compile what changed, so a save usually costs a single shader.
+pub struct Watcher {
+    instructions: Instructions,
+    backend: Option<Backend>,
+    written: Vec<PathBuf>,
+    /// The record of every platform, or just the one if there are none.
+    records: Vec<PathBuf>,
+    changes: Receiver<notify::Result<notify::Event>>,
+    // Dropping this stops `changes`.
+    watcher: RecommendedWatcher,
+    /// The directories being watched, the recursively watched ones first.
+    watched: Vec<PathBuf>,
+    recursive: usize,
+    started: bool,
+    /// When the latest change came in, if one that matters hasn't been built yet.
+    pending: Option<Instant>,
+}
+
+impl Watcher {
+    /// Starts watching, without building anything yet.
+    pub fn new(mut instructions: Instructions) -> Result<Watcher> {
+        let cwd = env::current_dir().unwrap_or_default();
+        let (sender, changes) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        let watched: Vec<_> = std::iter::once(&instructions.search_root)
+            .chain(instructions.include_dirs.iter())
+            .chain(instructions.include_packages.values())
+            .map(|dir| cwd.join(dir))
+            .collect();
+        for dir in watched.iter() {
+            watcher.watch(dir, RecursiveMode::Recursive)?;
+        }
+        let mut written = written_paths(&instructions, &cwd);
+        let mut records = Vec::new();
+        platform::each(&mut instructions, |instructions| {
+            written.extend(written_paths(instructions, &cwd));
+            records.push(cwd.join(&instructions.record_path));
+            Ok(())
+        })?;
+        Ok(Watcher {
+            written,
+            records,
+            instructions,
+            backend: None,
+            changes,
+            watcher,
+            recursive: watched.len(),
+            watched,
+            started: false,
+            pending: None,
+        })
+    }
+
+    /// Waits for the next run and returns its report, or `None` once nothing can change any more.
+    pub fn recv(&mut self) -> Option<Result<CompilationReport>> {
+        self.poll(true)
+    }
+
+    /// Returns the report of a run if there is something to build and the burst of changes it
+    /// came in has passed, and otherwise `None` straight away.
+    pub fn try_recv(&mut self) -> Option<Result<CompilationReport>> {
+        self.poll(false)
+    }
+
+    fn poll(&mut self, wait: bool) -> Option<Result<CompilationReport>> {
+        if !self.started {
+            self.started = true;
+            return Some(self.run());
+        }
+        loop {
+            // Editors tend to save in several steps, so a run waits until things are quiet.
+            if let Some(since) = self.pending {
+                if since.elapsed() >= DEBOUNCE {
+                    self.pending = None;
+                    return Some(self.run());
+                }
+            }
+            let event = match (wait, self.pending) {
+                (false, _) => match self.changes.try_recv() {
+                    Ok(event) => event,
+                    Err(_) => return None,
+                },
+                (true, Some(since)) => {
+                    match self
+                        .changes
+                        .recv_timeout(DEBOUNCE.saturating_sub(since.elapsed()))
+                    {
+                        Ok(event) => event,
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        // The watcher is gone, so nothing more will happen.
+                        Err(RecvTimeoutError::Disconnected) => return None,
+                    }
+                }
+                (true, None) => match self.changes.recv() {
+                    Ok(event) => event,
+                    Err(_) => return None,
+                },
+            };
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => return Some(Err(e.into())),
+            };
+            // Anything at all keeps a burst going, but only a change that matters starts one.
+            if self.pending.is_some()
+                || event
+                    .paths
+                    .iter()
+                    .any(|p| matters(p, &self.written, &self.records))
+            {
+                self.pending = Some(Instant::now());
+            }
+        }
+    }
+
+    /// Builds straight away, for the daemon, taking care of any changes waiting to be built.
+    #[cfg(all(feature = "daemon", unix))]
+    pub(crate) fn build(&mut self) -> Result<CompilationReport> {
+        while let Ok(event) = self.changes.try_recv() {
+            drop(event);
+        }
+        self.started = true;
+        self.pending = None;
+        self.run()
+    }
+
+    #[cfg(all(feature = "daemon", unix))]
+    pub(crate) fn instructions(&self) -> &Instructions {
+        &self.instructions
+    }
+
+    fn run(&mut self) -> Result<CompilationReport> {
+        let mut report = CompilationReport::default();
+        let mut backend = self.backend.take();
+        let result = platform::build(&mut self.instructions, |instructions| {
+            backend = run_one(instructions, backend.take(), &mut report)?;
+            Ok(())
+        });
+        self.backend = backend;
+        result?;
+        self.watch_includes()?;
+        Ok(report)
+    }
+
+    /// Watches the directory of every header a shader included, as of the record, that isn't
+    /// already under a watched directory, like one reached through `../`.
+    fn watch_includes(&mut self) -> Result<()> {
+        let cwd = env::current_dir().unwrap_or_default();
+        let mut included = Vec::new();
+        platform::each(&mut self.instructions, |instructions| {
+            let _lock = record::lock(instructions)?;
+            included.extend(Record::try_load(instructions)?.included());
+            Ok(())
+        })?;
+        for header in included {
+            let dir = match cwd.join(header).parent() {
+                Some(dir) => dir.to_owned(),
+                None => continue,
+            };
+            let (recursive, single) = self.watched.split_at(self.recursive);
+            if recursive.iter().any(|root| dir.starts_with(root)) || single.contains(&dir) {
+                continue;
+            }
+            self.watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+            self.watched.push(dir);
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Watcher {
+    type Item = Result<CompilationReport>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.recv()
+    }
+}
+
+/// Runs `instructions`, then again whenever something under `search_root`, `include_dirs`, or
+/// `include_packages` changes, until `callback` breaks off.  `callback` gets the report of each
+/// run; this is a `Watcher` driven to completion, and errors that aren't about a single shader
+/// end the watch.
+pub fn watch(
+    instructions: Instructions,
+    mut callback: impl FnMut(&CompilationReport) -> ControlFlow<()>,
+) -> Result<()> {
+    for report in Watcher::new(instructions)? {
+        if callback(&report?).is_break() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn only_changes_the_run_didnt_make_matter() {
+        let instructions = Instructions {
+            output_root: "out".into(),
+            record_path: "shaders.record".into(),
+            pack_output: Some("assets/shaders.spvpak".into()),
+            ..Default::default()
+        };
+        let cwd = Path::new("/game");
+        let written = written_paths(&instructions, cwd);
+        let records = [cwd.join(&instructions.record_path)];
+        let matters = |path: &str| matters(Path::new(path), &written, &records);
+        assert!(matters("/game/shaders/sky.frag"));
+        assert!(matters("/game/out.glsl"));
+        assert!(!matters("/game/out/sky.spv_frag"));
+        assert!(!matters("/game/assets/shaders.spvpak"));
+        assert!(!matters("/game/shaders.history"));
+        assert!(!matters("/game/shaders.record"));
+        assert!(!matters("/game/shaders.record.lock"));
+        assert!(!matters("/game/shaders.record.tmp"));
+    }
+
+    #[test]
+    fn headers_outside_the_roots_get_watched() {
+        let dir = std::env::temp_dir().join(format!("wrangler-watch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src/fx")).unwrap();
+        fs::create_dir_all(dir.join("shared")).unwrap();
+        let instructions = Instructions {
+            search_root: dir.join("src"),
+            output_root: dir.join("out"),
+            record_path: dir.join("record"),
+            ..Default::default()
+        };
+        let sky = dir.join("src/sky.frag");
+        let (inside, outside) = (dir.join("src/fx/fog.glsl"), dir.join("shared/common.glsl"));
+        for path in [&sky, &inside, &outside] {
+            fs::write(path, "").unwrap();
+        }
+        let mut record = Record::try_load(&instructions).unwrap();
+        record.log(&sky, &[inside, outside], &[]).unwrap();
+        record.write().unwrap();
+
+        let mut watcher = Watcher::new(instructions).unwrap();
+        watcher.watch_includes().unwrap();
+        assert_eq!(watcher.watched, [dir.join("src"), dir.join("shared")]);
+        // Watching twice adds nothing.
+        watcher.watch_includes().unwrap();
+        assert_eq!(watcher.watched.len(), 2);
+        drop(watcher);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
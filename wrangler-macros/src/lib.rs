@@ -0,0 +1,99 @@
+//! `include_shaders!`, which runs wrangler over a directory as the crate using it is compiled
+//! and embeds every module, so a binary can carry its shaders without a build script.
+//!
+//! This is a crate of its own because proc-macros live in crates of their own.  It runs
+//! `wrangler-core`, so wrangler can re-export it as `wrangler::include_shaders!`.
+
+use proc_macro::TokenStream;
+use proc_macro2::Literal;
+use quote::quote;
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use syn::{parse_macro_input, LitStr};
+use wrangler_core::{Instructions, ShaderKind, ShaderPack};
+
+const KINDS: &[ShaderKind] = &[
+    ShaderKind::Vertex,
+    ShaderKind::Fragment,
+    ShaderKind::Compute,
+    ShaderKind::Geometry,
+    ShaderKind::TessControl,
+    ShaderKind::TessEvaluation,
+    ShaderKind::RayGeneration,
+    ShaderKind::Miss,
+    ShaderKind::ClosestHit,
+    ShaderKind::AnyHit,
+    ShaderKind::Intersection,
+    ShaderKind::Callable,
+    ShaderKind::Task,
+    ShaderKind::Mesh,
+];
+
+/// Where artifacts and the record for `search_root` are kept between expansions: `OUT_DIR` if
+/// the crate has a build script, and otherwise a directory of the system's for temporary files.
+fn work_dir(search_root: &Path) -> PathBuf {
+    let base = match env::var_os("OUT_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => env::temp_dir(),
+    };
+    let mut hasher = DefaultHasher::new();
+    search_root.hash(&mut hasher);
+    base.join("wrangler-macros")
+        .join(format!("{:016x}", hasher.finish()))
+}
+
+/// Compiles the shaders in `dir` with wrangler and reads them back as they would be packed.
+fn compile(search_root: &Path) -> wrangler_core::Result<ShaderPack> {
+    let work = work_dir(search_root);
+    let pack = work.join("shaders.spvpak");
+    wrangler_core::run(Instructions {
+        to_compile: KINDS.to_vec(),
+        search_root: search_root.to_owned(),
+        output_root: work.join("compiled"),
+        record_path: work.join("record.dat"),
+        pack_output: Some(pack.clone()),
+        compilation_error_terminates: true,
+        ..Default::default()
+    })?;
+    ShaderPack::open(pack)
+}
+
+/// Compiles every shader under a directory, relative to the crate root, and expands to a
+/// `&'static [(&'static str, &'static [u32])]` of each one's path relative to that directory,
+/// with forward slashes, and its module.  The pairs are sorted by path, so they can be searched
+/// with `binary_search_by_key`.
+///
+/// ```ignore
+/// static SHADERS: &[(&str, &[u32])] = wrangler::include_shaders!("assets/shaders");
+/// ```
+///
+/// A shader that fails to compile fails the build with its errors.  Editing a shader rebuilds
+/// the crate, though editing only a file it `#include`s doesn't.
+#[proc_macro]
+pub fn include_shaders(input: TokenStream) -> TokenStream {
+    let dir = parse_macro_input!(input as LitStr);
+    let crate_root = env::var_os("CARGO_MANIFEST_DIR").map_or_else(PathBuf::new, PathBuf::from);
+    let search_root = crate_root.join(dir.value());
+    let pack = match compile(&search_root) {
+        Ok(pack) => pack,
+        Err(e) => return syn::Error::new(dir.span(), e).to_compile_error().into(),
+    };
+    let mut sources = Vec::new();
+    let mut shaders = Vec::new();
+    for entry in pack.entries() {
+        // Including each source makes cargo expand the macro again when one changes.
+        let source = search_root.join(entry.name);
+        sources.push(source.to_string_lossy().into_owned());
+        let name = entry.name;
+        let words = entry.spirv.iter().map(|&w| Literal::u32_suffixed(w));
+        shaders.push(quote! { (#name, &[#(#words),*]) });
+    }
+    quote! {{
+        #(const _: &[u8] = include_bytes!(#sources);)*
+        const SHADERS: &[(&str, &[u32])] = &[#(#shaders),*];
+        SHADERS
+    }}
+    .into()
+}